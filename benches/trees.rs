@@ -0,0 +1,124 @@
+//! Benchmarks of the hot paths for large contracts: hashing templates, hashing deep trees, and
+//! building the spend package of wide trees. Run with `cargo bench --features bench`.
+
+use bitcoin::{
+    absolute::LockTime, transaction::Version, Address, Amount, Network, ScriptBuf, Sequence,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ctvlib::{Context, Fields, Output, TxType};
+
+#[allow(dead_code)]
+#[path = "../src/error.rs"]
+mod error;
+
+#[allow(dead_code)]
+#[path = "../src/util.rs"]
+mod util;
+
+const FEE: Amount = Amount::from_sat(600);
+const PAYOUT: Amount = Amount::from_sat(10_000);
+
+/// A distinct address for every leaf.
+fn address(idx: usize) -> Output {
+    let script = ScriptBuf::from_bytes(idx.to_le_bytes().to_vec());
+    Output::Address {
+        address: Address::p2wsh(&script, Network::Regtest)
+            .as_unchecked()
+            .clone(),
+        amount: PAYOUT,
+    }
+}
+
+fn template(outputs: Vec<Output>) -> Context {
+    Context {
+        network: Network::Regtest,
+        tx_type: TxType::Segwit,
+        fields: Fields {
+            version: Version::ONE,
+            locktime: LockTime::ZERO,
+            sequences: vec![Sequence::ZERO],
+            outputs,
+            input_idx: 0,
+        },
+    }
+}
+
+/// A binary tree with a leaf for each of `leaves`, like a mining payout tree.
+fn binary_tree(leaves: std::ops::Range<usize>) -> (Context, Amount) {
+    if leaves.len() <= 2 {
+        let outputs = leaves.clone().map(address).collect::<Vec<_>>();
+        return (template(outputs), PAYOUT * leaves.len() as u64 + FEE);
+    }
+    let mid = leaves.start + leaves.len() / 2;
+    let (left, left_amount) = binary_tree(leaves.start..mid);
+    let (right, right_amount) = binary_tree(mid..leaves.end);
+    let outputs = vec![
+        Output::Tree {
+            tree: Box::new(left),
+            amount: left_amount,
+        },
+        Output::Tree {
+            tree: Box::new(right),
+            amount: right_amount,
+        },
+    ];
+    (template(outputs), left_amount + right_amount + FEE)
+}
+
+/// A chain which pays one leaf per transaction, like a congestion control tree.
+fn deep_tree(depth: usize) -> Context {
+    let mut ctx = template(vec![address(0)]);
+    let mut amount = PAYOUT + FEE;
+    for idx in 1..depth {
+        ctx = template(vec![
+            Output::Tree {
+                tree: Box::new(ctx),
+                amount,
+            },
+            address(idx),
+        ]);
+        amount += PAYOUT + FEE;
+    }
+    ctx
+}
+
+fn hashing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("template hash");
+    for outputs in [1, 10, 100] {
+        let ctx = template((0..outputs).map(address).collect());
+        group.bench_with_input(BenchmarkId::from_parameter(outputs), &ctx, |b, ctx| {
+            b.iter(|| black_box(ctx).ctv().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn deep_hashing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deep tree address");
+    for depth in [10, 100, 500] {
+        let ctx = deep_tree(depth);
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &ctx, |b, ctx| {
+            b.iter(|| black_box(ctx).address().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn spend_package(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spend package");
+    group.sample_size(10);
+    for leaves in [16, 256, 1024] {
+        let (ctx, _) = binary_tree(0..leaves);
+        let txid = bitcoin::hashes::Hash::all_zeros();
+        group.bench_with_input(BenchmarkId::new("serial", leaves), &ctx, |b, ctx| {
+            b.iter(|| black_box(ctx).spending_tx(txid, 0).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", leaves), &ctx, |b, ctx| {
+            b.iter(|| util::spending_txs(black_box(ctx), txid, 0).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hashing, deep_hashing, spend_package);
+criterion_main!(benches);
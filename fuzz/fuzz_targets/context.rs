@@ -0,0 +1,29 @@
+//! Arbitrary JSON, deserialized as a CTV template the way the spending forms do, and then
+//! validated, hashed and spent.
+
+#![no_main]
+
+use ctvlib::Context;
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/error.rs"]
+mod error;
+#[allow(dead_code)]
+#[path = "../../src/util.rs"]
+mod util;
+#[allow(dead_code)]
+#[path = "../../src/validate.rs"]
+mod validate;
+
+fuzz_target!(|data: &str| {
+    let Ok(ctx) = serde_json::from_str::<Context>(data) else {
+        return;
+    };
+    if validate::check_sequences(&ctx).is_err() {
+        return;
+    }
+    let _ = ctx.ctv();
+    let _ = ctx.address();
+    let _ = ctx.spending_tx(validate::placeholder_txid(), 0);
+});
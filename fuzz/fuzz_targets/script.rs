@@ -0,0 +1,23 @@
+//! Arbitrary scripts, tokenized and then colorized for display.
+
+#![no_main]
+
+use bitcoin::Script;
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/error.rs"]
+mod error;
+#[allow(dead_code)]
+#[path = "../../src/util.rs"]
+mod util;
+
+fuzz_target!(|data: &[u8]| {
+    let script = Script::from_bytes(data);
+    for instruction in script.instructions() {
+        if instruction.is_err() {
+            break;
+        }
+    }
+    let _ = util::colorize(&script.to_string());
+});
@@ -0,0 +1,27 @@
+//! Arbitrary transactions, run through every check which doesn't need a CTV template.
+
+#![no_main]
+
+use bitcoin::{Amount, Network, Transaction};
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/error.rs"]
+mod error;
+#[allow(dead_code)]
+#[path = "../../src/util.rs"]
+mod util;
+#[allow(dead_code)]
+#[path = "../../src/validate.rs"]
+mod validate;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(txs) = bitcoin::consensus::deserialize::<Vec<Transaction>>(data) else {
+        return;
+    };
+    let fees = vec![Amount::from_sat(600); txs.len()];
+    let _ = validate::check_conservation(Amount::MAX_MONEY, &txs, &fees);
+    let _ = validate::check_timelocks(&txs);
+    let _ = validate::check_scripts(&txs);
+    let _ = validate::check_standard(Network::Regtest, &txs);
+});
@@ -0,0 +1,57 @@
+//! Paper backups of contracts, so that recovering covenant funds doesn't depend on anything but
+//! the printout.
+
+use anyhow::anyhow;
+use bitcoin::Transaction;
+use qrcode::{render::svg, EcLevel, QrCode};
+
+/// The most contract JSON in one QR code, which keeps each code small enough to scan reliably
+/// off paper.
+const QR_PART_SIZE: usize = 800;
+
+/// Everything needed to recover a funded contract.
+pub(crate) struct Backup {
+    pub(crate) contract: String,
+    pub(crate) json: String,
+    /// The contract JSON split across QR codes, as SVG. Each part starts with `cdv:<part>/<parts>:`
+    /// so that they can be put back together in order.
+    pub(crate) qr_parts: Vec<String>,
+    /// Every pre-committed transaction, with a label, in the order they're broadcast.
+    pub(crate) transactions: Vec<(String, String)>,
+    pub(crate) instructions: Vec<String>,
+}
+
+impl Backup {
+    pub(crate) fn new(
+        contract: &str,
+        json: String,
+        transactions: Vec<(String, Transaction)>,
+        instructions: Vec<String>,
+    ) -> anyhow::Result<Backup> {
+        Ok(Backup {
+            contract: contract.to_string(),
+            qr_parts: qr_parts(&json)?,
+            json,
+            transactions: transactions
+                .into_iter()
+                .map(|(label, tx)| (label, hex::encode(bitcoin::consensus::serialize(&tx))))
+                .collect(),
+            instructions,
+        })
+    }
+}
+
+fn qr_parts(json: &str) -> anyhow::Result<Vec<String>> {
+    let chunks: Vec<&[u8]> = json.as_bytes().chunks(QR_PART_SIZE).collect();
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(idx, chunk)| {
+            let mut data = format!("cdv:{}/{}:", idx + 1, chunks.len()).into_bytes();
+            data.extend_from_slice(chunk);
+            let code = QrCode::with_error_correction_level(data, EcLevel::M)
+                .map_err(|e| anyhow!("Contract doesn't fit in a QR code: {e}"))?;
+            Ok(code.render::<svg::Color>().min_dimensions(240, 240).build())
+        })
+        .collect()
+}
@@ -0,0 +1,382 @@
+//! Command line access to the contracts, for scripting them without running the web server.
+//! Contracts are read as JSON, in the same form the web pages pass between steps, from a file or
+//! stdin, and results are printed as JSON. Trees are built from their payouts instead, as CSV.
+
+use std::{
+    fs,
+    io::{self, IsTerminal, Read},
+    path::PathBuf,
+};
+
+use anyhow::Context as _;
+use bitcoin::{Amount, Network, OutPoint, Transaction, TxOut, Txid};
+use clap::{Args, Parser, Subcommand};
+use ctvlib::Context;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    psbt, server,
+    tree::{self, TreeBuilder},
+    util::{self, Highlight},
+    validate,
+    vault::Vault,
+};
+
+#[derive(Parser)]
+#[command(about = "Build and spend CTV contracts")]
+pub(crate) struct Cli {
+    /// Runs the web server if no command is given.
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Run the web server.
+    Serve,
+    /// CTV templates, as ctvlib's JSON.
+    #[command(subcommand)]
+    Ctv(CtvCommand),
+    /// Vaults, as the JSON of the vault pages.
+    #[command(subcommand)]
+    Vault(VaultCommand),
+    /// Trees of CTV templates.
+    #[command(subcommand)]
+    Tree(TreeCommand),
+}
+
+#[derive(Subcommand)]
+pub(crate) enum CtvCommand {
+    /// Print the template hash, locking script and address.
+    Hash(Input),
+    /// Print the locking script, highlighted on a terminal.
+    Decode(Input),
+    /// Check every transaction of the template against consensus and relay policy.
+    Verify {
+        #[command(flatten)]
+        input: Input,
+        /// What the contract is funded with.
+        #[arg(long, value_parser = util::parse_amount)]
+        amount: Amount,
+        /// The fee each transaction pays.
+        #[arg(long, value_parser = util::parse_amount)]
+        fee: Amount,
+    },
+    /// Print the transaction which spends the template.
+    Spend {
+        #[command(flatten)]
+        input: Input,
+        #[command(flatten)]
+        funding: Funding,
+    },
+}
+
+#[derive(Subcommand)]
+pub(crate) enum VaultCommand {
+    /// Check the vault, and print its address and unvaulting script.
+    Create(Input),
+    /// Print the unvaulting transaction.
+    Unvault {
+        #[command(flatten)]
+        input: Input,
+        /// The transaction which funded the vault.
+        #[arg(long)]
+        txid: Txid,
+        #[arg(long, default_value_t = 0)]
+        vout: u32,
+    },
+    /// Print the hot spend and the cold sweep.
+    Spend {
+        #[command(flatten)]
+        input: Input,
+        /// The unvaulting transaction.
+        #[arg(long)]
+        txid: Txid,
+    },
+}
+
+#[derive(Subcommand)]
+pub(crate) enum TreeCommand {
+    /// Build a tree from its payouts, one `address,amount` per line, and print its address and
+    /// amount, and once it's funded, every transaction needed to unroll it, in broadcast order.
+    Build {
+        #[command(flatten)]
+        input: Input,
+        #[arg(long)]
+        network: Network,
+        /// How many outputs each transaction has.
+        #[arg(long, default_value_t = 4)]
+        radix: usize,
+        /// The fees of the whole tree together.
+        #[arg(long, value_parser = util::parse_amount, conflicts_with = "fee_rate")]
+        fee_budget: Option<Amount>,
+        /// The fee rate of every transaction, in sat/vB.
+        #[arg(long, required_unless_present = "fee_budget")]
+        fee_rate: Option<u64>,
+        #[arg(long)]
+        taproot: bool,
+        /// The transaction which funded the tree.
+        #[arg(long)]
+        txid: Option<Txid>,
+        #[arg(long, default_value_t = 0)]
+        vout: u32,
+    },
+}
+
+#[derive(Args)]
+pub(crate) struct Input {
+    /// File to read the contract from, or - for stdin.
+    #[arg(default_value = "-")]
+    file: PathBuf,
+}
+
+/// Where a contract was funded.
+#[derive(Args)]
+pub(crate) struct Funding {
+    #[arg(long)]
+    txid: Txid,
+    #[arg(long, default_value_t = 0)]
+    vout: u32,
+    /// What the funding output holds, like "100000 sat" or "0.001 btc".
+    #[arg(long, value_parser = util::parse_amount)]
+    amount: Amount,
+}
+
+#[derive(Serialize)]
+struct HashOutput {
+    ctv_hash: String,
+    locking_script: String,
+    locking_hex: String,
+    address: String,
+}
+
+#[derive(Serialize)]
+struct VerifyOutput {
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct VaultOutput {
+    address: String,
+    unvault_script: String,
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct VaultSpendOutput {
+    hot: TxOutput,
+    cold: TxOutput,
+}
+
+#[derive(Serialize)]
+struct TreeOutput {
+    address: String,
+    amount: Amount,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    transactions: Vec<TxOutput>,
+}
+
+#[derive(Serialize)]
+struct TxOutput {
+    txid: Txid,
+    hex: String,
+    psbt: String,
+}
+
+pub(crate) async fn run(command: Option<Command>) -> anyhow::Result<()> {
+    match command {
+        None | Some(Command::Serve) => server::server().await,
+        Some(Command::Ctv(command)) => ctv(command),
+        Some(Command::Vault(command)) => vault(command),
+        Some(Command::Tree(command)) => tree(command),
+    }
+}
+
+fn ctv(command: CtvCommand) -> anyhow::Result<()> {
+    match command {
+        CtvCommand::Hash(input) => {
+            let ctv: Context = input.read()?;
+            let locking_script = ctv.locking_script()?;
+            print(&HashOutput {
+                ctv_hash: hex::encode(ctv.ctv()?),
+                locking_script: locking_script.to_string(),
+                locking_hex: hex::encode(locking_script.as_bytes()),
+                address: ctv.address()?.to_string(),
+            })
+        }
+        CtvCommand::Decode(input) => {
+            let ctv: Context = input.read()?;
+            let script = ctv.locking_script()?.to_string();
+            if io::stdout().is_terminal() {
+                println!("{}", util::highlight(&script, Highlight::Ansi));
+            } else {
+                println!("{script}");
+            }
+            Ok(())
+        }
+        CtvCommand::Verify { input, amount, fee } => {
+            let ctv: Context = input.read()?;
+            validate::check_data_outputs(&ctv, true)?;
+            print(&VerifyOutput {
+                warnings: validate::check_tree(&ctv, amount, fee)?,
+            })
+        }
+        CtvCommand::Spend { input, funding } => {
+            let ctv: Context = input.read()?;
+            validate::check_sequences(&ctv)?;
+            let tx = util::spending_tx(&ctv, funding.txid, funding.vout)?;
+            let funding = funding.output(&ctv)?;
+            print(&tx_outputs(&[tx], funding)?.remove(0))
+        }
+    }
+}
+
+/// Print warnings to stderr, so they don't end up in the JSON on stdout.
+fn warn(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!("warning: {warning}");
+    }
+}
+
+fn vault(command: VaultCommand) -> anyhow::Result<()> {
+    match command {
+        VaultCommand::Create(input) => {
+            let vault: Vault = input.read()?;
+            let warnings = vault.validate()?;
+            print(&VaultOutput {
+                address: vault.vault_address()?.assume_checked().to_string(),
+                unvault_script: vault.unvault_redeem_script()?.to_string(),
+                warnings,
+            })
+        }
+        VaultCommand::Unvault { input, txid, vout } => {
+            let vault: Vault = input.read()?;
+            warn(&vault.validate()?);
+            let vault_ctv = vault.vault_ctv()?;
+            let tx = vault_ctv.spending_tx(txid, vout)?[0].clone();
+            let funding = TxOut {
+                value: vault.amount,
+                script_pubkey: vault_ctv.address()?.script_pubkey(),
+            };
+            print(&tx_outputs(&[tx], (OutPoint { txid, vout }, funding))?.remove(0))
+        }
+        VaultCommand::Spend { input, txid } => {
+            let vault: Vault = input.read()?;
+            warn(&vault.validate()?);
+            // The unvaulting output doesn't depend on where the vault was funded from.
+            let unvault = vault
+                .vault_ctv()?
+                .spending_tx(validate::placeholder_txid(), 0)?[0]
+                .output[0]
+                .clone();
+            let outpoint = OutPoint { txid, vout: 0 };
+            let hot = vault.hot_spend(txid, 0)?;
+            let cold = vault.cold_spend(txid, 0)?;
+            print(&VaultSpendOutput {
+                hot: tx_outputs(&[hot], (outpoint, unvault.clone()))?.remove(0),
+                cold: tx_outputs(&[cold], (outpoint, unvault))?.remove(0),
+            })
+        }
+    }
+}
+
+fn tree(command: TreeCommand) -> anyhow::Result<()> {
+    match command {
+        TreeCommand::Build {
+            input,
+            network,
+            radix,
+            fee_budget,
+            fee_rate,
+            taproot,
+            txid,
+            vout,
+        } => {
+            let mut builder = TreeBuilder::new(network, tree::parse_payouts(&input.text()?.1)?)
+                .radix(radix)
+                .taproot(taproot);
+            if let Some(fee_budget) = fee_budget {
+                builder = builder.fee_budget(fee_budget);
+            }
+            if let Some(fee_rate) = fee_rate {
+                builder = builder.fee_rate(fee_rate);
+            }
+            let tree = builder.build()?;
+            let (warnings, _) = tree.validate()?;
+            warn(&warnings);
+            let address = tree.ctv.address()?;
+            let transactions = match txid {
+                Some(txid) => {
+                    let funding = TxOut {
+                        value: tree.amount,
+                        script_pubkey: address.script_pubkey(),
+                    };
+                    tx_outputs(
+                        &tree.transactions(txid, vout)?,
+                        (OutPoint { txid, vout }, funding),
+                    )?
+                }
+                None => Vec::new(),
+            };
+            print(&TreeOutput {
+                address: address.to_string(),
+                amount: tree.amount,
+                transactions,
+            })
+        }
+    }
+}
+
+impl Input {
+    /// Read and parse the contract, naming the file if it isn't valid.
+    fn read<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        let (name, json) = self.text()?;
+        serde_json::from_str(&json).with_context(|| format!("{name} isn't a valid contract"))
+    }
+
+    /// The file's name and what it holds.
+    fn text(&self) -> anyhow::Result<(String, String)> {
+        if self.file.as_os_str() == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            Ok(("stdin".to_string(), text))
+        } else {
+            let name = self.file.display().to_string();
+            let text =
+                fs::read_to_string(&self.file).with_context(|| format!("Couldn't read {name}"))?;
+            Ok((name, text))
+        }
+    }
+}
+
+impl Funding {
+    fn output(&self, ctv: &Context) -> anyhow::Result<(OutPoint, TxOut)> {
+        let outpoint = OutPoint {
+            txid: self.txid,
+            vout: self.vout,
+        };
+        let output = TxOut {
+            value: self.amount,
+            script_pubkey: ctv.address()?.script_pubkey(),
+        };
+        Ok((outpoint, output))
+    }
+}
+
+fn tx_outputs(txs: &[Transaction], funding: (OutPoint, TxOut)) -> anyhow::Result<Vec<TxOutput>> {
+    let psbts = psbt::from_txs(txs, funding)?;
+    Ok(txs
+        .iter()
+        .zip(psbts)
+        .map(|(tx, psbt)| TxOutput {
+            txid: tx.txid(),
+            hex: hex::encode(bitcoin::consensus::serialize(tx)),
+            psbt: psbt.to_string(),
+        })
+        .collect())
+}
+
+fn print<T: Serialize>(output: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(output)?);
+    Ok(())
+}
@@ -0,0 +1,296 @@
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime,
+    address::NetworkUnchecked,
+    opcodes::all::{
+        OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF, OP_IF, OP_NOP4,
+    },
+    script::PushBytesBuf,
+    secp256k1::SECP256K1,
+    taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo},
+    transaction::Version,
+    Address, Amount, Network, OutPoint, PublicKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, Witness, XOnlyPublicKey,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+
+use crate::{util::nums_points, validate};
+
+/// Fee paid by the timeout spend.
+const TIMEOUT_FEE: Amount = Amount::from_sat(600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Party {
+    pub(crate) key: PublicKey,
+    pub(crate) address: Address<NetworkUnchecked>,
+    /// What this party recovers if the other party stops cooperating.
+    pub(crate) share: Amount,
+}
+
+/// Funds held jointly by two parties.
+///
+/// While both parties cooperate, they can spend the funds however they like with a 2-of-2
+/// signature. If either party disappears, the other can wait out the timeout and then use a
+/// pre-committed CTV template which pays both parties their agreed share.
+///
+/// The 2-of-2 is a script, not a key path: one branch of the witness script for segwit custody,
+/// or a script leaf beside the timeout leaf for taproot custody, whose internal key is the
+/// unspendable NUMS point. A MuSig2 key path would be cheaper, but needs an interactive signing
+/// round between the parties.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Custody {
+    pub(crate) network: Network,
+    pub(crate) first: Party,
+    pub(crate) second: Party,
+    pub(crate) timeout: u16,
+    pub(crate) taproot: bool,
+}
+
+impl Custody {
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        validate::check_network("First party's address", &self.first.address, self.network)?;
+        validate::check_network("Second party's address", &self.second.address, self.network)?;
+        if self.timeout == 0 {
+            bail!("Timeout must be at least one block");
+        }
+        if self.first.key == self.second.key {
+            bail!("Both parties must use different keys");
+        }
+        Ok(validate::check_tree(
+            &self.timeout_ctv()?,
+            self.amount()?,
+            TIMEOUT_FEE,
+        )?)
+    }
+
+    /// The amount to lock into the contract: both shares plus the fee of the timeout spend.
+    pub(crate) fn amount(&self) -> anyhow::Result<Amount> {
+        [self.first.share, self.second.share]
+            .into_iter()
+            .try_fold(TIMEOUT_FEE, |total, share| total.checked_add(share))
+            .ok_or_else(|| anyhow!("Shares add up to more than 21 million BTC"))
+    }
+
+    pub(crate) fn address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+        let address = if self.taproot {
+            let tsi = self.taproot_spend_info()?;
+            Address::p2tr(SECP256K1, nums_points(), tsi.merkle_root(), self.network)
+        } else {
+            Address::p2wsh(&self.redeem_script()?, self.network)
+        };
+        Ok(address.as_unchecked().clone())
+    }
+
+    /// The script both parties sign to spend cooperatively: the whole witness script for segwit
+    /// custody, or just the 2-of-2 leaf for taproot.
+    pub(crate) fn cooperative_script(&self) -> anyhow::Result<ScriptBuf> {
+        if self.taproot {
+            return Ok(bitcoin::script::Builder::new()
+                .push_x_only_key(&XOnlyPublicKey::from(self.first.key.inner))
+                .push_opcode(OP_CHECKSIGVERIFY)
+                .push_x_only_key(&XOnlyPublicKey::from(self.second.key.inner))
+                .push_opcode(OP_CHECKSIG)
+                .into_script());
+        }
+        self.redeem_script()
+    }
+
+    fn redeem_script(&self) -> anyhow::Result<ScriptBuf> {
+        let timeout_hash = PushBytesBuf::try_from(self.timeout_ctv()?.ctv()?)?;
+        Ok(bitcoin::script::Builder::new()
+            .push_opcode(OP_IF)
+            .push_key(&self.first.key)
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .push_key(&self.second.key)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ELSE)
+            .push_sequence(Sequence::from_height(self.timeout))
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_slice(timeout_hash)
+            .push_opcode(OP_NOP4)
+            .push_opcode(OP_ENDIF)
+            .into_script())
+    }
+
+    /// Control block for the cooperative leaf, when using taproot.
+    pub(crate) fn cooperative_control_block(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        if !self.taproot {
+            return Ok(None);
+        }
+        let cb = self
+            .taproot_spend_info()?
+            .control_block(&(self.cooperative_script()?, LeafVersion::TapScript))
+            .ok_or_else(|| anyhow!("Invalid tapscript formation"))?;
+        Ok(Some(cb.serialize()))
+    }
+
+    /// The unilateral recovery transaction, valid once the timeout has passed.
+    pub(crate) fn timeout_spend(&self, txid: Txid, vout: u32) -> anyhow::Result<Transaction> {
+        let mut witness = Witness::new();
+        if self.taproot {
+            let script = self.timeout_script()?;
+            let cb = self
+                .taproot_spend_info()?
+                .control_block(&(script.clone(), LeafVersion::TapScript))
+                .ok_or_else(|| anyhow!("Invalid tapscript formation"))?;
+            witness.push(script);
+            witness.push(cb.serialize());
+        } else {
+            witness.push([]);
+            witness.push(self.redeem_script()?);
+        }
+
+        Ok(Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint { txid, vout },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_height(self.timeout),
+                witness,
+            }],
+            output: vec![
+                TxOut {
+                    value: self.first.share,
+                    script_pubkey: self
+                        .first
+                        .address
+                        .clone()
+                        .require_network(self.network)?
+                        .script_pubkey(),
+                },
+                TxOut {
+                    value: self.second.share,
+                    script_pubkey: self
+                        .second
+                        .address
+                        .clone()
+                        .require_network(self.network)?
+                        .script_pubkey(),
+                },
+            ],
+        })
+    }
+
+    fn timeout_script(&self) -> anyhow::Result<ScriptBuf> {
+        let timeout_hash = PushBytesBuf::try_from(self.timeout_ctv()?.ctv()?)?;
+        Ok(bitcoin::script::Builder::new()
+            .push_sequence(Sequence::from_height(self.timeout))
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_slice(timeout_hash)
+            .push_opcode(OP_NOP4)
+            .into_script())
+    }
+
+    fn timeout_ctv(&self) -> anyhow::Result<Context> {
+        Ok(Context {
+            network: self.network,
+            tx_type: self.tx_type(),
+            fields: Fields {
+                version: Version::TWO,
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::from_height(self.timeout)],
+                outputs: vec![
+                    Output::Address {
+                        address: self.first.address.clone(),
+                        amount: self.first.share,
+                    },
+                    Output::Address {
+                        address: self.second.address.clone(),
+                        amount: self.second.share,
+                    },
+                ],
+                input_idx: 0,
+            },
+        })
+    }
+
+    fn tx_type(&self) -> TxType {
+        if self.taproot {
+            return TxType::Taproot {
+                internal_key: nums_points(),
+            };
+        }
+        TxType::Segwit
+    }
+
+    fn taproot_spend_info(&self) -> anyhow::Result<TaprootSpendInfo> {
+        TaprootBuilder::new()
+            .add_leaf(1, self.cooperative_script()?)?
+            .add_leaf(1, self.timeout_script()?)?
+            .finalize(SECP256K1, nums_points())
+            .map_err(|_| anyhow!("Taproot not finalizable"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, secp256k1::SecretKey};
+
+    use super::*;
+
+    fn party(idx: u8, share: u64) -> Party {
+        let secret = SecretKey::from_slice(&[idx; 32]).unwrap();
+        let script = ScriptBuf::from_bytes(vec![idx; 8]);
+        Party {
+            key: PublicKey::new(secret.public_key(SECP256K1)),
+            address: Address::p2wsh(&script, Network::Regtest)
+                .as_unchecked()
+                .clone(),
+            share: Amount::from_sat(share),
+        }
+    }
+
+    fn custody(taproot: bool) -> Custody {
+        Custody {
+            network: Network::Regtest,
+            first: party(1, 60_000),
+            second: party(2, 40_000),
+            timeout: 144,
+            taproot,
+        }
+    }
+
+    #[test]
+    fn timeout_spend() {
+        for taproot in [false, true] {
+            let custody = custody(taproot);
+            custody.validate().unwrap();
+            assert_eq!(custody.amount().unwrap(), Amount::from_sat(100_600));
+
+            // The recovery has to be exactly the transaction the timeout template commits to.
+            let spend = custody.timeout_spend(Txid::all_zeros(), 0).unwrap();
+            let template = &custody
+                .timeout_ctv()
+                .unwrap()
+                .spending_tx(Txid::all_zeros(), 0)
+                .unwrap()[0];
+            assert_eq!(spend.version, template.version);
+            assert_eq!(spend.lock_time, template.lock_time);
+            assert_eq!(spend.input[0].sequence, template.input[0].sequence);
+            assert_eq!(spend.output, template.output);
+            assert_eq!(
+                custody.cooperative_control_block().unwrap().is_some(),
+                taproot
+            );
+        }
+    }
+
+    #[test]
+    fn rejects() {
+        let mut same_key = custody(false);
+        same_key.second.key = same_key.first.key;
+        assert!(same_key.validate().is_err());
+
+        let mut no_timeout = custody(false);
+        no_timeout.timeout = 0;
+        assert!(no_timeout.validate().is_err());
+
+        let mut overflow = custody(false);
+        overflow.first.share = Amount::MAX;
+        assert!(overflow.amount().is_err());
+    }
+}
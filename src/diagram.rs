@@ -0,0 +1,391 @@
+//! Diagrams of the transactions in a contract, for design docs and reviews.
+//!
+//! Transactions are boxes, and the addresses and data they pay out to are ellipses. An edge is
+//! an output, which is labelled with its amount and with any timelock on spending it.
+
+use std::fmt::Write;
+
+use bitcoin::{relative, Sequence};
+use ctvlib::{Context, Output};
+use serde::Deserialize;
+
+use crate::{validate, vault::Vault};
+
+/// The formats a diagram can be exported in.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Format {
+    /// Graphviz DOT source.
+    #[default]
+    Dot,
+    /// A Mermaid flowchart, which GitHub and most wikis render inline.
+    Mermaid,
+    /// An SVG picture, with the full details of each node in its tooltip.
+    Svg,
+}
+
+impl Format {
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Format::Dot => "text/vnd.graphviz",
+            Format::Mermaid => "text/plain",
+            Format::Svg => "image/svg+xml",
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Format::Dot => "dot",
+            Format::Mermaid => "mmd",
+            Format::Svg => "svg",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Shape {
+    Transaction,
+    Destination,
+}
+
+#[derive(Debug)]
+pub(crate) struct Node {
+    pub(crate) shape: Shape,
+    pub(crate) label: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct Edge {
+    pub(crate) from: usize,
+    pub(crate) to: usize,
+    pub(crate) label: String,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Diagram {
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) edges: Vec<Edge>,
+}
+
+impl Diagram {
+    /// The tree of transactions which unrolls a CTV template, starting from its address.
+    pub(crate) fn from_ctv(ctx: &Context) -> anyhow::Result<Diagram> {
+        let mut diagram = Diagram::default();
+        let funding = diagram.node(
+            Shape::Destination,
+            format!("CTV address\n{}", ctx.address()?),
+        );
+        diagram.add_template(funding, String::new(), ctx)?;
+        Ok(diagram)
+    }
+
+    /// A vault's two ways out: straight to the cold address, or to the hot address after the
+    /// delay.
+    pub(crate) fn from_vault(vault: &Vault) -> anyhow::Result<Diagram> {
+        let mut diagram = Diagram::default();
+        let unvault_tx = &vault
+            .vault_ctv()?
+            .spending_tx(validate::placeholder_txid(), 0)?[0];
+        let unvault_amount = unvault_tx.output[0].value;
+        let cold_tx = vault.cold_spend(unvault_tx.txid(), 0)?;
+        let hot_tx = vault.hot_spend(unvault_tx.txid(), 0)?;
+
+        let funding = diagram.node(
+            Shape::Destination,
+            format!("Vault address\n{}", vault.vault_address()?.assume_checked()),
+        );
+        let unvault = diagram.node(Shape::Transaction, "Unvault".to_string());
+        diagram.edge(funding, unvault, vault.amount.to_string());
+
+        let cold = diagram.node(Shape::Transaction, "Cold sweep".to_string());
+        diagram.edge(unvault, cold, format!("{unvault_amount}\nany time"));
+        let cold_address = diagram.node(
+            Shape::Destination,
+            format!("Cold address\n{}", vault.cold.clone().assume_checked()),
+        );
+        diagram.edge(cold, cold_address, cold_tx.output[0].value.to_string());
+
+        let hot = diagram.node(Shape::Transaction, "Hot spend".to_string());
+        let delay = timelock(hot_tx.input[0].sequence).unwrap_or_default();
+        diagram.edge(unvault, hot, format!("{unvault_amount}\n{delay}"));
+        let hot_address = diagram.node(
+            Shape::Destination,
+            format!("Hot address\n{}", vault.hot.clone().assume_checked()),
+        );
+        diagram.edge(hot, hot_address, hot_tx.output[0].value.to_string());
+        Ok(diagram)
+    }
+
+    pub(crate) fn render(&self, format: Format) -> String {
+        match format {
+            Format::Dot => self.to_dot(),
+            Format::Mermaid => self.to_mermaid(),
+            Format::Svg => self.to_svg(),
+        }
+    }
+
+    /// Graphviz DOT source for the diagram.
+    pub(crate) fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph contract {\n  rankdir=LR;\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let shape = match node.shape {
+                Shape::Transaction => "box",
+                Shape::Destination => "ellipse",
+            };
+            let _ = writeln!(
+                dot,
+                "  n{id} [shape={shape}, label=\"{}\"];",
+                dot_escape(&node.label)
+            );
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                dot,
+                "  n{} -> n{} [label=\"{}\"];",
+                edge.from,
+                edge.to,
+                dot_escape(&edge.label)
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// A Mermaid flowchart of the diagram.
+    pub(crate) fn to_mermaid(&self) -> String {
+        let mut mermaid = String::from("flowchart LR\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let label = mermaid_escape(&node.label);
+            let _ = match node.shape {
+                Shape::Transaction => writeln!(mermaid, "  n{id}[\"{label}\"]"),
+                Shape::Destination => writeln!(mermaid, "  n{id}([\"{label}\"])"),
+            };
+        }
+        for edge in &self.edges {
+            let _ = if edge.label.is_empty() {
+                writeln!(mermaid, "  n{} --> n{}", edge.from, edge.to)
+            } else {
+                writeln!(
+                    mermaid,
+                    "  n{} -->|\"{}\"| n{}",
+                    edge.from,
+                    mermaid_escape(&edge.label),
+                    edge.to
+                )
+            };
+        }
+        mermaid
+    }
+
+    /// An SVG picture of the diagram, laid out as a tree from left to right. Hovering over a node
+    /// shows its full label and the output it spends.
+    pub(crate) fn to_svg(&self) -> String {
+        const COLUMN: usize = 280;
+        const ROW: usize = 64;
+        const WIDTH: usize = 220;
+        const HEIGHT: usize = 44;
+        const MARGIN: usize = 20;
+
+        let mut children = vec![Vec::new(); self.nodes.len()];
+        let mut incoming = vec![None; self.nodes.len()];
+        for edge in &self.edges {
+            children[edge.from].push(edge.to);
+            incoming[edge.to] = Some(edge);
+        }
+        let mut layout = Layout {
+            children: &children,
+            positions: vec![(0, 0.0); self.nodes.len()],
+            rows: 0,
+        };
+        for root in (0..self.nodes.len()).filter(|&id| incoming[id].is_none()) {
+            layout.place(root, 0);
+        }
+        let columns = layout
+            .positions
+            .iter()
+            .map(|(c, _)| c + 1)
+            .max()
+            .unwrap_or(0);
+        let position = |id: usize| {
+            let (column, row) = layout.positions[id];
+            (
+                MARGIN + column * COLUMN,
+                MARGIN + (row * ROW as f64) as usize,
+            )
+        };
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="monospace" font-size="12">"#,
+            MARGIN * 2 + columns.saturating_sub(1) * COLUMN + WIDTH,
+            MARGIN * 2 + layout.rows.saturating_sub(1) * ROW + HEIGHT,
+        );
+        for edge in &self.edges {
+            let (x1, y1) = position(edge.from);
+            let (x2, y2) = position(edge.to);
+            let (x1, y1, y2) = (x1 + WIDTH, y1 + HEIGHT / 2, y2 + HEIGHT / 2);
+            let middle = (x1 + x2) / 2;
+            let _ = writeln!(
+                svg,
+                r#"  <path d="M{x1},{y1} C{middle},{y1} {middle},{y2} {x2},{y2}" fill="none" stroke="gray" />"#,
+            );
+            let label = edge.label.lines().next().unwrap_or_default();
+            let _ = writeln!(
+                svg,
+                r#"  <text x="{}" y="{}" text-anchor="middle" fill="gray">{}</text>"#,
+                middle,
+                (y1 + y2) / 2 - 4,
+                xml_escape(label),
+            );
+        }
+        for (id, node) in self.nodes.iter().enumerate() {
+            let (x, y) = position(id);
+            let (fill, radius) = match node.shape {
+                Shape::Transaction => ("#e8f0fe", 4),
+                Shape::Destination => ("#fef7e0", HEIGHT / 2),
+            };
+            let mut tooltip = node.label.clone();
+            if let Some(edge) = incoming[id].filter(|e| !e.label.is_empty()) {
+                let _ = write!(tooltip, "\n\nSpends: {}", edge.label.replace('\n', ", "));
+            }
+            let _ = writeln!(svg, "  <g>\n    <title>{}</title>", xml_escape(&tooltip));
+            let _ = writeln!(
+                svg,
+                r#"    <rect x="{x}" y="{y}" width="{WIDTH}" height="{HEIGHT}" rx="{radius}" fill="{fill}" stroke="black" />"#,
+            );
+            for (idx, line) in node.label.lines().take(2).enumerate() {
+                let _ = writeln!(
+                    svg,
+                    r#"    <text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+                    x + WIDTH / 2,
+                    y + 18 + idx * 16,
+                    xml_escape(&shorten(line, 28)),
+                );
+            }
+            svg.push_str("  </g>\n");
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    fn node(&mut self, shape: Shape, label: String) -> usize {
+        self.nodes.push(Node { shape, label });
+        self.nodes.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: String) {
+        self.edges.push(Edge { from, to, label });
+    }
+
+    /// Add the transaction which spends a template, and everything it pays out to.
+    fn add_template(&mut self, from: usize, amount: String, ctx: &Context) -> anyhow::Result<()> {
+        let mut label = format!("CTV {}", &hex::encode(ctx.ctv()?)[..16]);
+        if ctx.fields.locktime.to_consensus_u32() != 0 {
+            let _ = write!(label, "\nlocktime {}", ctx.fields.locktime);
+        }
+        let tx = self.node(Shape::Transaction, label);
+        let sequence = ctx.fields.sequences.get(ctx.fields.input_idx as usize);
+        let edge = match sequence.copied().and_then(timelock) {
+            Some(timelock) if !amount.is_empty() => format!("{amount}\n{timelock}"),
+            Some(timelock) => timelock,
+            None => amount,
+        };
+        self.edge(from, tx, edge);
+
+        for output in &ctx.fields.outputs {
+            match output {
+                Output::Address { address, amount } => {
+                    let to = self.node(
+                        Shape::Destination,
+                        address.clone().assume_checked().to_string(),
+                    );
+                    self.edge(tx, to, amount.to_string());
+                }
+                Output::Data { data } => {
+                    let to = self.node(Shape::Destination, format!("OP_RETURN\n{data}"));
+                    self.edge(tx, to, String::new());
+                }
+                Output::Tree { tree, amount } => {
+                    self.add_template(tx, amount.to_string(), tree)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Positions of the nodes of a tree, in columns by depth, with each parent centred on its
+/// children.
+struct Layout<'a> {
+    children: &'a [Vec<usize>],
+    positions: Vec<(usize, f64)>,
+    rows: usize,
+}
+
+impl Layout<'_> {
+    fn place(&mut self, node: usize, column: usize) -> f64 {
+        let row = match self.children[node].as_slice() {
+            [] => {
+                self.rows += 1;
+                (self.rows - 1) as f64
+            }
+            children => {
+                let rows: Vec<f64> = children
+                    .iter()
+                    .map(|&child| self.place(child, column + 1))
+                    .collect();
+                (rows[0] + rows[rows.len() - 1]) / 2.0
+            }
+        };
+        self.positions[node] = (column, row);
+        row
+    }
+}
+
+/// The relative timelock a sequence puts on spending, if any.
+fn timelock(sequence: Sequence) -> Option<String> {
+    match sequence.to_relative_lock_time()? {
+        relative::LockTime::Blocks(height) if height.value() > 0 => {
+            Some(format!("after {} blocks", height.value()))
+        }
+        relative::LockTime::Time(time) if time.value() > 0 => {
+            Some(format!("after {} seconds", u32::from(time.value()) * 512))
+        }
+        _ => None,
+    }
+}
+
+fn dot_escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn mermaid_escape(label: &str) -> String {
+    label
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+        .replace('\n', "<br>")
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Cut the middle out of long labels, such as addresses, so they fit in a node.
+fn shorten(text: &str, max: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= max {
+        return text.to_string();
+    }
+    let keep = (max - 1) / 2;
+    let start: String = chars[..keep].iter().collect();
+    let end: String = chars[chars.len() - keep..].iter().collect();
+    format!("{start}…{end}")
+}
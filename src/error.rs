@@ -1,41 +1,117 @@
+use bitcoin::{Amount, Network};
 
-
-use askama::Template;
-use askama_axum::IntoResponse;
-use axum::http::StatusCode;
-
-#[derive(Template)]
-#[template(path = "error.html.jinja")]
-pub struct ErrorTemplate {
-    message: String,
-}
-
-pub struct AppError(anyhow::Error);
-
-impl IntoResponse for AppError {
-    fn into_response(self) -> askama_axum::Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            ErrorTemplate {
-                message: self.0.to_string(),
-            }
-            .into_response(),
-        )
-            .into_response()
-    }
-}
-
-impl<E> From<E> for AppError
-where
-    E: Into<anyhow::Error>,
-{
-    fn from(err: E) -> Self {
-        Self(err.into())
-    }
+/// Errors from building, validating and spending contracts, for callers which need to tell them
+/// apart rather than just show the message.
+#[derive(Debug, thiserror::Error)]
+pub enum CdvError {
+    #[error("CTV hash is {0} bytes instead of 32")]
+    HashLength(usize),
+    #[error("{field} {address} is a {actual} address, but the contract is for {network}")]
+    NetworkMismatch {
+        field: String,
+        address: String,
+        actual: String,
+        network: Network,
+    },
+    #[error("Template doesn't commit to a sequence for its input")]
+    MissingSequence,
+    #[error("Template commits to {0} inputs, but the covenant is spent on its own")]
+    InputCount(usize),
+    #[error("Template has unexpected outputs")]
+    UnexpectedOutputs,
+    #[error("Template has no spending transaction")]
+    NoSpendingTx,
+    #[error("Building the spends of a subtree panicked")]
+    SubtreePanicked,
+    #[error("{amount} does not cover the fees of {fees}")]
+    AmountOverflow { amount: Amount, fees: Amount },
+    #[error("Fee rate {0} sat/vB is too high")]
+    FeeRate(u64),
+    #[error("A vault with an anchor pays no fees up front, so it can't have a fee rate")]
+    AnchorFeeRate,
+    #[error("Preimage does not match the covenant's hash")]
+    PreimageMismatch,
+    #[error("Taproot tree is not finalizable")]
+    TaprootTree,
+    #[error("Tapscript is not in the taproot tree")]
+    TapscriptMissing,
+    #[error("{var} must be {expected}, not {value}")]
+    Config {
+        var: &'static str,
+        expected: &'static str,
+        value: String,
+    },
+    #[error("{transactions} transactions were given {fees} fees, instead of one each")]
+    FeeCount { transactions: usize, fees: usize },
+    #[error("Transaction {number} pays {fee} for {vsize} vB, a fee rate of {fee_rate:.2} sat/vB, which is below the minimum of {limit} sat/vB")]
+    FeeRateTooLow {
+        number: usize,
+        fee: Amount,
+        vsize: usize,
+        fee_rate: f64,
+        limit: f64,
+    },
+    #[error("Transaction {number} pays {fee} for {vsize} vB, a fee rate of {fee_rate:.2} sat/vB, which is above the maximum of {limit} sat/vB")]
+    FeeRateTooHigh {
+        number: usize,
+        fee: Amount,
+        vsize: usize,
+        fee_rate: f64,
+        limit: f64,
+    },
+    #[error("Tree pays out to {leaves} outputs, but the limit is {limit}")]
+    TreeLeaves { leaves: usize, limit: usize },
+    #[error("Tree is {depth} transactions deep, but the limit is {limit}")]
+    TreeDepth { depth: usize, limit: usize },
+    #[error("Tree of {transactions} transactions would take up about {vsize} vB on chain, but the limit is {limit} vB")]
+    TreeEstimate {
+        transactions: usize,
+        vsize: u64,
+        limit: u64,
+    },
+    #[error("Tree takes up {vsize} vB on chain, but the limit is {limit} vB")]
+    TreeVsize { vsize: u64, limit: u64 },
+    #[error("Output {vout} of transaction {number} pays {value} to {recipient}, which is below the dust limit of {dust}")]
+    Dust {
+        number: usize,
+        vout: usize,
+        value: Amount,
+        recipient: String,
+        dust: Amount,
+    },
+    #[error("Transaction {number} spends an unknown output")]
+    UnknownOutput { number: usize },
+    #[error("Transaction {number} moves more than the supply of bitcoin")]
+    SupplyOverflow { number: usize },
+    #[error("Transaction {number} spends {spent} but pays out {paid} plus a fee of {fee}, which is {difference} {direction} than it spends")]
+    Conservation {
+        number: usize,
+        spent: Amount,
+        paid: Amount,
+        fee: Amount,
+        difference: Amount,
+        direction: &'static str,
+    },
+    /// The sequences a template commits to don't match the inputs of its spending transaction.
+    #[error("{0}")]
+    Sequences(String),
+    /// A timelock can never be satisfied.
+    #[error("{0}")]
+    Timelock(String),
+    /// A witness script, or the stack it starts with, is over a consensus or policy limit.
+    #[error("{0}")]
+    Script(String),
+    /// A transaction breaks the TRUC or ephemeral anchor rules, so it could never be relayed.
+    #[error("{0}")]
+    Truc(String),
+    /// A transaction breaks relay policy, so no node would relay it.
+    #[error("{0}")]
+    NonStandard(String),
+    /// The payload of a data output can't be pushed, or is too large to relay.
+    #[error("{0}")]
+    Data(String),
+    #[error(transparent)]
+    Taproot(#[from] bitcoin::taproot::TaprootBuilderError),
+    #[error(transparent)]
+    Template(#[from] ctvlib::Error),
 }
-
-// impl AppError {
-//     pub fn message(msg: &str) -> AppError {
-//         AppError(anyhow::Error::from(msg.to_string()))
-//     }
-// }
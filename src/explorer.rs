@@ -0,0 +1,69 @@
+//! Links to a block explorer for the addresses and transactions shown in the UI.
+//!
+//! Each network has a URL template for addresses and one for transactions, which can be
+//! overridden with `CDV_EXPLORER_ADDRESS_<NETWORK>` (containing `{address}`) and
+//! `CDV_EXPLORER_TX_<NETWORK>` (containing `{txid}`), where `<NETWORK>` is `BITCOIN`, `TESTNET`,
+//! `SIGNET` or `REGTEST`. Setting a variable to an empty string turns those links off.
+
+use bitcoin::{Address, Network, Txid};
+
+/// The explorer page of an address, if there's an explorer for its network.
+pub(crate) fn address_url(address: &Address) -> Option<String> {
+    let template = url_template("ADDRESS", *address.network())?;
+    Some(template.replace("{address}", &address.to_string()))
+}
+
+/// The explorer page of a transaction, if there's an explorer for the network.
+pub(crate) fn tx_url(network: Network, txid: &Txid) -> Option<String> {
+    let template = url_template("TX", network)?;
+    Some(template.replace("{txid}", &txid.to_string()))
+}
+
+fn url_template(kind: &str, network: Network) -> Option<String> {
+    let suffix = match network {
+        Network::Bitcoin => "BITCOIN",
+        Network::Testnet => "TESTNET",
+        Network::Signet => "SIGNET",
+        Network::Regtest => "REGTEST",
+        _ => return None,
+    };
+    let var = format!("CDV_EXPLORER_{kind}_{suffix}");
+    let template = match std::env::var(var) {
+        Ok(template) => template,
+        Err(_) => default_template(kind, network)?,
+    };
+    Some(template).filter(|t| !t.trim().is_empty())
+}
+
+fn default_template(kind: &str, network: Network) -> Option<String> {
+    let base = match network {
+        Network::Bitcoin => "https://mempool.space",
+        Network::Testnet => "https://mempool.space/testnet",
+        Network::Signet => "https://mempool.space/signet",
+        _ => return None,
+    };
+    Some(match kind {
+        "TX" => format!("{base}/tx/{{txid}}"),
+        _ => format!("{base}/address/{{address}}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    #[test]
+    fn documented_variables() {
+        let txid = Txid::all_zeros();
+        std::env::set_var("CDV_EXPLORER_TX_BITCOIN", "https://example.com/tx/{txid}");
+        assert_eq!(
+            tx_url(Network::Bitcoin, &txid),
+            Some(format!("https://example.com/tx/{txid}"))
+        );
+        std::env::set_var("CDV_EXPLORER_TX_TESTNET", "");
+        assert_eq!(tx_url(Network::Testnet, &txid), None);
+        assert_eq!(tx_url(Network::Regtest, &txid), None);
+    }
+}
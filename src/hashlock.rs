@@ -0,0 +1,319 @@
+use bitcoin::{
+    absolute::LockTime,
+    address::NetworkUnchecked,
+    hashes::{sha256, Hash},
+    opcodes::all::{OP_ELSE, OP_ENDIF, OP_EQUALVERIFY, OP_IF, OP_NOP4, OP_SHA256},
+    secp256k1::SECP256K1,
+    taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo},
+    transaction::Version,
+    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::CdvError,
+    util::{nums_points, template_hash},
+    validate,
+};
+
+/// Fee paid by either spend of the covenant.
+const SPEND_FEE: Amount = Amount::from_sat(600);
+
+/// Length of the preimage the hashlock spend is measured with when validating, since the real one
+/// isn't known yet. Secrets are usually 32 bytes, like the hash.
+const PREIMAGE_LEN: usize = 32;
+
+/// A covenant with two CTV branches: one which anybody can take by revealing the preimage of a
+/// hash, and one which can only be mined after a locktime.
+///
+/// This is the building block for contracts where one party gets the funds when a secret is
+/// revealed (an event is cancelled, a loan is repaid), and the other party gets them otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct HashlockCovenant {
+    pub(crate) network: Network,
+    pub(crate) amount: Amount,
+    pub(crate) hash: sha256::Hash,
+    /// Where the funds go when the preimage is revealed.
+    pub(crate) hashlock_address: Address<NetworkUnchecked>,
+    /// Where the funds go after the locktime.
+    pub(crate) timelock_address: Address<NetworkUnchecked>,
+    pub(crate) locktime: LockTime,
+    pub(crate) taproot: bool,
+}
+
+impl HashlockCovenant {
+    /// Fails if the contract can't be built or relayed, and returns warnings about anything
+    /// which only some nodes would relay.
+    pub(crate) fn validate(&self) -> Result<Vec<String>, CdvError> {
+        validate::check_sequences(&self.hashlock_ctv()?)?;
+        validate::check_sequences(&self.timelock_ctv()?)?;
+        // The spends carry the preimage and the redeem script in their witnesses, so they're
+        // checked rather than the bare templates.
+        let outpoint = OutPoint {
+            txid: validate::placeholder_txid(),
+            vout: 0,
+        };
+        let hashlock = self.spend_with_preimage(outpoint, &[0; PREIMAGE_LEN])?;
+        let mut warnings =
+            validate::check_txs(self.network, self.amount, &[hashlock], &[SPEND_FEE])?;
+        warnings.extend(validate::check_txs(
+            self.network,
+            self.amount,
+            &[self.timelock_spend(outpoint)?],
+            &[SPEND_FEE],
+        )?);
+        Ok(warnings)
+    }
+
+    pub(crate) fn address(&self) -> Result<Address<NetworkUnchecked>, CdvError> {
+        let address = if self.taproot {
+            let tsi = self.taproot_spend_info()?;
+            Address::p2tr(SECP256K1, nums_points(), tsi.merkle_root(), self.network)
+        } else {
+            Address::p2wsh(&self.redeem_script()?, self.network)
+        };
+        Ok(address.as_unchecked().clone())
+    }
+
+    pub(crate) fn hashlock_spend(
+        &self,
+        outpoint: OutPoint,
+        preimage: &[u8],
+    ) -> Result<Transaction, CdvError> {
+        if sha256::Hash::hash(preimage) != self.hash {
+            return Err(CdvError::PreimageMismatch);
+        }
+        self.spend_with_preimage(outpoint, preimage)
+    }
+
+    /// The hashlock spend, without checking the preimage against the hash.
+    fn spend_with_preimage(
+        &self,
+        outpoint: OutPoint,
+        preimage: &[u8],
+    ) -> Result<Transaction, CdvError> {
+        let mut witness = Witness::new();
+        witness.push(preimage);
+        if self.taproot {
+            self.push_leaf(&mut witness, self.hashlock_script()?)?;
+        } else {
+            witness.push([1]);
+            witness.push(self.redeem_script()?);
+        }
+        self.spend(&self.hashlock_ctv()?, outpoint, witness)
+    }
+
+    pub(crate) fn timelock_spend(&self, outpoint: OutPoint) -> Result<Transaction, CdvError> {
+        let mut witness = Witness::new();
+        if self.taproot {
+            self.push_leaf(&mut witness, self.timelock_script()?)?;
+        } else {
+            witness.push([]);
+            witness.push(self.redeem_script()?);
+        }
+        self.spend(&self.timelock_ctv()?, outpoint, witness)
+    }
+
+    fn spend(
+        &self,
+        ctv: &Context,
+        outpoint: OutPoint,
+        witness: Witness,
+    ) -> Result<Transaction, CdvError> {
+        let address = match ctv.fields.outputs.as_slice() {
+            [Output::Address { address, .. }] => {
+                validate::check_network("Payout address", address, self.network)?;
+                address.clone().assume_checked()
+            }
+            _ => return Err(CdvError::UnexpectedOutputs),
+        };
+        // The template commits to the number of inputs, so it has to be spent on its own.
+        let sequence = match ctv.fields.sequences.as_slice() {
+            [] => return Err(CdvError::MissingSequence),
+            [sequence] => *sequence,
+            sequences => return Err(CdvError::InputCount(sequences.len())),
+        };
+        Ok(Transaction {
+            version: ctv.fields.version,
+            lock_time: ctv.fields.locktime,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence,
+                witness,
+            }],
+            output: vec![TxOut {
+                value: self.payout()?,
+                script_pubkey: address.script_pubkey(),
+            }],
+        })
+    }
+
+    fn push_leaf(&self, witness: &mut Witness, script: ScriptBuf) -> Result<(), CdvError> {
+        let cb = self
+            .taproot_spend_info()?
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or(CdvError::TapscriptMissing)?;
+        witness.push(script);
+        witness.push(cb.serialize());
+        Ok(())
+    }
+
+    fn redeem_script(&self) -> Result<ScriptBuf, CdvError> {
+        let hashlock_hash = template_hash(&self.hashlock_ctv()?)?;
+        let timelock_hash = template_hash(&self.timelock_ctv()?)?;
+        Ok(bitcoin::script::Builder::new()
+            .push_opcode(OP_IF)
+            .push_opcode(OP_SHA256)
+            .push_slice(self.hash.to_byte_array())
+            .push_opcode(OP_EQUALVERIFY)
+            .push_slice(hashlock_hash)
+            .push_opcode(OP_NOP4)
+            .push_opcode(OP_ELSE)
+            .push_slice(timelock_hash)
+            .push_opcode(OP_NOP4)
+            .push_opcode(OP_ENDIF)
+            .into_script())
+    }
+
+    fn hashlock_script(&self) -> Result<ScriptBuf, CdvError> {
+        let hashlock_hash = template_hash(&self.hashlock_ctv()?)?;
+        Ok(bitcoin::script::Builder::new()
+            .push_opcode(OP_SHA256)
+            .push_slice(self.hash.to_byte_array())
+            .push_opcode(OP_EQUALVERIFY)
+            .push_slice(hashlock_hash)
+            .push_opcode(OP_NOP4)
+            .into_script())
+    }
+
+    fn timelock_script(&self) -> Result<ScriptBuf, CdvError> {
+        let timelock_hash = template_hash(&self.timelock_ctv()?)?;
+        Ok(bitcoin::script::Builder::new()
+            .push_slice(timelock_hash)
+            .push_opcode(OP_NOP4)
+            .into_script())
+    }
+
+    fn taproot_spend_info(&self) -> Result<TaprootSpendInfo, CdvError> {
+        TaprootBuilder::new()
+            .add_leaf(1, self.hashlock_script()?)?
+            .add_leaf(1, self.timelock_script()?)?
+            .finalize(SECP256K1, nums_points())
+            .map_err(|_| CdvError::TaprootTree)
+    }
+
+    fn hashlock_ctv(&self) -> Result<Context, CdvError> {
+        Ok(Context {
+            network: self.network,
+            tx_type: self.tx_type(),
+            fields: Fields {
+                version: Version::TWO,
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::ZERO],
+                outputs: vec![Output::Address {
+                    address: self.hashlock_address.clone(),
+                    amount: self.payout()?,
+                }],
+                input_idx: 0,
+            },
+        })
+    }
+
+    fn timelock_ctv(&self) -> Result<Context, CdvError> {
+        Ok(Context {
+            network: self.network,
+            tx_type: self.tx_type(),
+            fields: Fields {
+                version: Version::TWO,
+                locktime: self.locktime,
+                sequences: vec![Sequence::ENABLE_LOCKTIME_NO_RBF],
+                outputs: vec![Output::Address {
+                    address: self.timelock_address.clone(),
+                    amount: self.payout()?,
+                }],
+                input_idx: 0,
+            },
+        })
+    }
+
+    fn payout(&self) -> Result<Amount, CdvError> {
+        self.amount
+            .checked_sub(SPEND_FEE)
+            .ok_or(CdvError::AmountOverflow {
+                amount: self.amount,
+                fees: SPEND_FEE,
+            })
+    }
+
+    fn tx_type(&self) -> TxType {
+        if self.taproot {
+            return TxType::Taproot {
+                internal_key: nums_points(),
+            };
+        }
+        TxType::Segwit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::Txid;
+
+    use super::*;
+
+    const PREIMAGE: &[u8] = b"cancelled";
+
+    fn covenant(taproot: bool) -> HashlockCovenant {
+        let address = |byte: u8| {
+            let script = ScriptBuf::from_bytes(vec![byte; 8]);
+            Address::p2wsh(&script, Network::Regtest)
+                .as_unchecked()
+                .clone()
+        };
+        HashlockCovenant {
+            network: Network::Regtest,
+            amount: Amount::from_sat(100_000),
+            hash: sha256::Hash::hash(PREIMAGE),
+            hashlock_address: address(1),
+            timelock_address: address(2),
+            locktime: LockTime::from_height(800_000).unwrap(),
+            taproot,
+        }
+    }
+
+    fn assert_matches(tx: &Transaction, ctv: &Context) {
+        let template = &ctv.spending_tx(Txid::all_zeros(), 0).unwrap()[0];
+        assert_eq!(tx.version, template.version);
+        assert_eq!(tx.lock_time, template.lock_time);
+        assert_eq!(tx.input[0].sequence, template.input[0].sequence);
+        assert_eq!(tx.output, template.output);
+    }
+
+    #[test]
+    fn spends() {
+        for taproot in [false, true] {
+            let covenant = covenant(taproot);
+            covenant.validate().unwrap();
+            let outpoint = OutPoint::new(Txid::all_zeros(), 0);
+
+            let hashlock = covenant.hashlock_spend(outpoint, PREIMAGE).unwrap();
+            assert_eq!(hashlock.input[0].witness.nth(0), Some(PREIMAGE));
+            assert_matches(&hashlock, &covenant.hashlock_ctv().unwrap());
+
+            let timelock = covenant.timelock_spend(outpoint).unwrap();
+            assert_matches(&timelock, &covenant.timelock_ctv().unwrap());
+            assert_eq!(timelock.output[0].value, Amount::from_sat(99_400));
+        }
+    }
+
+    #[test]
+    fn wrong_preimage() {
+        let outpoint = OutPoint::new(Txid::all_zeros(), 0);
+        assert!(matches!(
+            covenant(false).hashlock_spend(outpoint, b"not cancelled"),
+            Err(CdvError::PreimageMismatch)
+        ));
+    }
+}
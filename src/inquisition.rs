@@ -0,0 +1,540 @@
+//! End-to-end tests against a bitcoin-inquisition regtest node, where OP_CTV is active.
+//!
+//! Each test starts its own node, funds covenant addresses generated by cdv, and broadcasts and
+//! mines the spends, so a test only passes if the scripts and witnesses are consensus-valid, and
+//! not just if the template hashes match. They only run with the `inquisition` feature:
+//!
+//! ```sh
+//! INQUISITION_BITCOIND=/path/to/bitcoind cargo test --features inquisition inquisition
+//! ```
+//!
+//! `bitcoin-cli` is expected to be next to `bitcoind`.
+
+use std::{
+    net::TcpListener,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+use bitcoin::{
+    absolute::LockTime,
+    address::NetworkUnchecked,
+    hashes::{sha256, Hash},
+    key::{Keypair, Secp256k1},
+    secp256k1::rand::thread_rng,
+    transaction::Version,
+    Address, Amount, Denomination, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Txid, Witness, XOnlyPublicKey,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+
+use crate::{hashlock::HashlockCovenant, util, vault::Vault};
+
+/// A regtest node in its own data directory, which is stopped and removed when dropped.
+struct Node {
+    bitcoind: PathBuf,
+    datadir: PathBuf,
+    rpcport: u16,
+    process: Child,
+}
+
+impl Node {
+    fn start() -> Node {
+        static STARTED: AtomicUsize = AtomicUsize::new(0);
+        let bitcoind = PathBuf::from(
+            std::env::var("INQUISITION_BITCOIND").unwrap_or_else(|_| "bitcoind".to_string()),
+        );
+        let datadir = std::env::temp_dir().join(format!(
+            "cdv-inquisition-{}-{}",
+            std::process::id(),
+            STARTED.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&datadir).expect("Failed to create the data directory");
+        let rpcport = free_port();
+        let process = Command::new(&bitcoind)
+            .arg("-regtest")
+            .arg(format!("-datadir={}", datadir.display()))
+            .arg(format!("-rpcport={rpcport}"))
+            .arg(format!("-port={}", free_port()))
+            .arg("-txindex")
+            .arg("-fallbackfee=0.0001")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|e| panic!("Failed to start {}: {e}", bitcoind.display()));
+        let node = Node {
+            bitcoind,
+            datadir,
+            rpcport,
+            process,
+        };
+
+        for _ in 0..100 {
+            if node.try_cli(&["getblockchaininfo"]).is_ok() {
+                node.cli(&["createwallet", "cdv"]);
+                node.mine(101);
+                return node;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        panic!("Node didn't start answering RPCs in time");
+    }
+
+    fn try_cli(&self, args: &[&str]) -> Result<String, String> {
+        let output = Command::new(self.bitcoind.with_file_name("bitcoin-cli"))
+            .arg("-regtest")
+            .arg(format!("-datadir={}", self.datadir.display()))
+            .arg(format!("-rpcport={}", self.rpcport))
+            .args(args)
+            .output()
+            .map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn cli(&self, args: &[&str]) -> String {
+        self.try_cli(args)
+            .unwrap_or_else(|e| panic!("bitcoin-cli {} failed: {e}", args.join(" ")))
+    }
+
+    fn mine(&self, blocks: u32) {
+        let address = self.cli(&["getnewaddress"]);
+        self.cli(&["generatetoaddress", &blocks.to_string(), &address]);
+    }
+
+    fn height(&self) -> u32 {
+        self.cli(&["getblockcount"]).parse().unwrap()
+    }
+
+    fn new_address(&self) -> Address<NetworkUnchecked> {
+        Address::from_str(&self.cli(&["getnewaddress"])).unwrap()
+    }
+
+    /// Send `amount` to the address from the node's wallet, mine it, and return the output.
+    fn fund(&self, address: &Address<NetworkUnchecked>, amount: Amount) -> OutPoint {
+        let address = address.assume_checked_ref().to_string();
+        let amount = amount.to_string_in(Denomination::Bitcoin);
+        let txid = Txid::from_str(&self.cli(&["sendtoaddress", &address, &amount])).unwrap();
+        self.mine(1);
+        let tx: serde_json::Value =
+            serde_json::from_str(&self.cli(&["getrawtransaction", &txid.to_string(), "true"]))
+                .unwrap();
+        let vout = tx["vout"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|o| o["scriptPubKey"]["address"] == address.as_str())
+            .and_then(|o| o["n"].as_u64())
+            .expect("Funding transaction doesn't pay the covenant address");
+        OutPoint {
+            txid,
+            vout: vout as u32,
+        }
+    }
+
+    /// Broadcast a transaction and mine it, failing if the node rejects it.
+    fn confirm(&self, tx: &Transaction) {
+        let hex = hex::encode(bitcoin::consensus::serialize(tx));
+        self.cli(&["sendrawtransaction", &hex]);
+        self.mine(1);
+        let confirmed: serde_json::Value =
+            serde_json::from_str(&self.cli(&["getrawtransaction", &tx.txid().to_string(), "true"]))
+                .unwrap();
+        assert!(confirmed["confirmations"].as_u64().unwrap_or_default() > 0);
+    }
+
+    /// Bump a TRUC transaction which pays no fee of its own with a child spending its anchor and
+    /// one of the wallet's outputs, relay them together as a package, and mine them.
+    fn confirm_package(&self, parent: &Transaction) {
+        let anchor = parent
+            .output
+            .iter()
+            .position(|o| o.script_pubkey == util::anchor_script())
+            .expect("Transaction has no anchor");
+        let unspent: serde_json::Value = serde_json::from_str(&self.cli(&["listunspent"])).unwrap();
+        let utxo = &unspent[0];
+        let value = Amount::from_btc(utxo["amount"].as_f64().unwrap()).unwrap();
+        let child = Transaction {
+            version: Version(3),
+            lock_time: LockTime::ZERO,
+            input: vec![
+                TxIn {
+                    previous_output: OutPoint {
+                        txid: parent.txid(),
+                        vout: anchor as u32,
+                    },
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ZERO,
+                    witness: Witness::new(),
+                },
+                TxIn {
+                    previous_output: OutPoint {
+                        txid: Txid::from_str(utxo["txid"].as_str().unwrap()).unwrap(),
+                        vout: utxo["vout"].as_u64().unwrap() as u32,
+                    },
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ZERO,
+                    witness: Witness::new(),
+                },
+            ],
+            output: vec![TxOut {
+                value: value - Amount::from_sat(5_000),
+                script_pubkey: self.new_address().assume_checked().script_pubkey(),
+            }],
+        };
+        // The wallet doesn't know the parent, so it's told about the anchor, which needs no
+        // signature.
+        let prevtxs = serde_json::json!([{
+            "txid": parent.txid().to_string(),
+            "vout": anchor,
+            "scriptPubKey": hex::encode(util::anchor_script().as_bytes()),
+            "amount": 0,
+        }]);
+        let signed: serde_json::Value = serde_json::from_str(&self.cli(&[
+            "signrawtransactionwithwallet",
+            &hex::encode(bitcoin::consensus::serialize(&child)),
+            &prevtxs.to_string(),
+        ]))
+        .unwrap();
+        assert_eq!(
+            signed["complete"], true,
+            "Failed to sign the child: {signed}"
+        );
+
+        let package = serde_json::json!([
+            hex::encode(bitcoin::consensus::serialize(parent)),
+            signed["hex"].as_str().unwrap(),
+        ]);
+        let result: serde_json::Value =
+            serde_json::from_str(&self.cli(&["submitpackage", &package.to_string()])).unwrap();
+        assert_eq!(
+            result["package_msg"], "success",
+            "Package rejected: {result}"
+        );
+        self.mine(1);
+        let confirmed: serde_json::Value = serde_json::from_str(&self.cli(&[
+            "getrawtransaction",
+            &parent.txid().to_string(),
+            "true",
+        ]))
+        .unwrap();
+        assert!(confirmed["confirmations"].as_u64().unwrap_or_default() > 0);
+    }
+
+    fn rejects(&self, tx: &Transaction) -> bool {
+        let hex = hex::encode(bitcoin::consensus::serialize(tx));
+        self.try_cli(&["sendrawtransaction", &hex]).is_err()
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let _ = self.try_cli(&["stop"]);
+        let _ = self.process.wait();
+        let _ = std::fs::remove_dir_all(&self.datadir);
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .map(|a| a.port())
+        .expect("No free port")
+}
+
+fn tx_type(taproot: bool) -> TxType {
+    if taproot {
+        return TxType::Taproot {
+            internal_key: util::nums_points(),
+        };
+    }
+    TxType::Segwit
+}
+
+fn context(outputs: Vec<Output>, taproot: bool) -> Context {
+    Context {
+        network: Network::Regtest,
+        tx_type: tx_type(taproot),
+        fields: Fields {
+            version: Version::ONE,
+            locktime: LockTime::ZERO,
+            sequences: vec![Sequence::ZERO],
+            outputs,
+            input_idx: 0,
+        },
+    }
+}
+
+#[test]
+fn simple_lock() {
+    let node = Node::start();
+    for taproot in [false, true] {
+        let ctx = context(
+            vec![
+                Output::Address {
+                    address: node.new_address(),
+                    amount: Amount::from_sat(40_000),
+                },
+                Output::Address {
+                    address: node.new_address(),
+                    amount: Amount::from_sat(59_400),
+                },
+                Output::Data {
+                    data: "cdv".to_string(),
+                },
+            ],
+            taproot,
+        );
+        let address = ctx.address().unwrap().as_unchecked().clone();
+        let funding = node.fund(&address, Amount::from_sat(100_000));
+        for tx in ctx.spending_tx(funding.txid, funding.vout).unwrap() {
+            node.confirm(&tx);
+        }
+    }
+}
+
+#[test]
+fn congestion_tree() {
+    let node = Node::start();
+    for taproot in [false, true] {
+        let leaf = context(
+            vec![Output::Address {
+                address: node.new_address(),
+                amount: Amount::from_sat(49_400),
+            }],
+            taproot,
+        );
+        let ctx = context(
+            vec![
+                Output::Tree {
+                    tree: Box::new(leaf),
+                    amount: Amount::from_sat(50_000),
+                },
+                Output::Address {
+                    address: node.new_address(),
+                    amount: Amount::from_sat(49_400),
+                },
+            ],
+            taproot,
+        );
+        let address = ctx.address().unwrap().as_unchecked().clone();
+        let funding = node.fund(&address, Amount::from_sat(100_000));
+        for tx in ctx.spending_tx(funding.txid, funding.vout).unwrap() {
+            node.confirm(&tx);
+        }
+    }
+}
+
+#[test]
+fn wrong_outputs_are_rejected() {
+    let node = Node::start();
+    for taproot in [false, true] {
+        let ctx = context(
+            vec![Output::Address {
+                address: node.new_address(),
+                amount: Amount::from_sat(99_400),
+            }],
+            taproot,
+        );
+        let address = ctx.address().unwrap().as_unchecked().clone();
+        let funding = node.fund(&address, Amount::from_sat(100_000));
+        let mut tx = ctx.spending_tx(funding.txid, funding.vout).unwrap()[0].clone();
+        tx.output[0].value = Amount::from_sat(99_000);
+        assert!(node.rejects(&tx));
+    }
+}
+
+#[test]
+fn vault() {
+    let node = Node::start();
+    for taproot in [false, true] {
+        let vault = Vault {
+            hot: node.new_address(),
+            cold: node.new_address(),
+            amount: Amount::from_sat(100_000),
+            network: Network::Regtest,
+            delay: 5,
+            taproot,
+            internal_key: None,
+            fee_rate: None,
+            anchor: false,
+        };
+        vault.validate().unwrap();
+
+        // Sweep to the cold address straight away.
+        let funding = node.fund(&vault.vault_address().unwrap(), vault.amount);
+        let unvault = &vault
+            .vault_ctv()
+            .unwrap()
+            .spending_tx(funding.txid, funding.vout)
+            .unwrap()[0];
+        node.confirm(unvault);
+        node.confirm(&vault.cold_spend(unvault.txid(), 0).unwrap());
+
+        // Spend to the hot address, which has to wait out the delay.
+        let funding = node.fund(&vault.vault_address().unwrap(), vault.amount);
+        let unvault = &vault
+            .vault_ctv()
+            .unwrap()
+            .spending_tx(funding.txid, funding.vout)
+            .unwrap()[0];
+        node.confirm(unvault);
+        let hot = vault.hot_spend(unvault.txid(), 0).unwrap();
+        assert!(node.rejects(&hot));
+        node.mine(vault.delay as u32);
+        node.confirm(&hot);
+    }
+}
+
+#[test]
+fn vault_with_internal_key() {
+    let node = Node::start();
+    let secp = Secp256k1::new();
+    let (key, _) = XOnlyPublicKey::from_keypair(&Keypair::new(&secp, &mut thread_rng()));
+    let vault = Vault {
+        hot: node.new_address(),
+        cold: node.new_address(),
+        amount: Amount::from_sat(100_000),
+        network: Network::Regtest,
+        delay: 5,
+        taproot: true,
+        internal_key: Some(key),
+        fee_rate: None,
+        anchor: false,
+    };
+    // The key could skip the covenant, which is worth a warning but still spends fine.
+    assert!(!vault.validate().unwrap().is_empty());
+
+    // The control blocks of both covenant spends have to commit to the caller's key.
+    let funding = node.fund(&vault.vault_address().unwrap(), vault.amount);
+    let unvault = &vault
+        .vault_ctv()
+        .unwrap()
+        .spending_tx(funding.txid, funding.vout)
+        .unwrap()[0];
+    node.confirm(unvault);
+    node.confirm(&vault.cold_spend(unvault.txid(), 0).unwrap());
+
+    let funding = node.fund(&vault.vault_address().unwrap(), vault.amount);
+    let unvault = &vault
+        .vault_ctv()
+        .unwrap()
+        .spending_tx(funding.txid, funding.vout)
+        .unwrap()[0];
+    node.confirm(unvault);
+    node.mine(vault.delay as u32);
+    node.confirm(&vault.hot_spend(unvault.txid(), 0).unwrap());
+}
+
+#[test]
+fn vault_with_fee_rate() {
+    let node = Node::start();
+    for taproot in [false, true] {
+        let vault = Vault {
+            hot: node.new_address(),
+            cold: node.new_address(),
+            amount: Amount::from_sat(100_000),
+            network: Network::Regtest,
+            delay: 5,
+            taproot,
+            internal_key: None,
+            fee_rate: Some(2),
+            anchor: false,
+        };
+        vault.validate().unwrap();
+
+        let funding = node.fund(&vault.vault_address().unwrap(), vault.amount);
+        let unvault = &vault
+            .vault_ctv()
+            .unwrap()
+            .spending_tx(funding.txid, funding.vout)
+            .unwrap()[0];
+        node.confirm(unvault);
+        node.confirm(&vault.cold_spend(unvault.txid(), 0).unwrap());
+
+        let funding = node.fund(&vault.vault_address().unwrap(), vault.amount);
+        let unvault = &vault
+            .vault_ctv()
+            .unwrap()
+            .spending_tx(funding.txid, funding.vout)
+            .unwrap()[0];
+        node.confirm(unvault);
+        node.mine(vault.delay as u32);
+        node.confirm(&vault.hot_spend(unvault.txid(), 0).unwrap());
+    }
+}
+
+/// Zero-value anchors are only relayed by nodes based on Bitcoin Core v29 and later.
+#[test]
+fn vault_with_anchor() {
+    let node = Node::start();
+    for taproot in [false, true] {
+        let vault = Vault {
+            hot: node.new_address(),
+            cold: node.new_address(),
+            amount: Amount::from_sat(100_000),
+            network: Network::Regtest,
+            delay: 5,
+            taproot,
+            internal_key: None,
+            fee_rate: None,
+            anchor: true,
+        };
+        vault.validate().unwrap();
+
+        // Each transaction pays no fee, so it's only relayed with a child which pays for both.
+        let funding = node.fund(&vault.vault_address().unwrap(), vault.amount);
+        let unvault = &vault
+            .vault_ctv()
+            .unwrap()
+            .spending_tx(funding.txid, funding.vout)
+            .unwrap()[0];
+        assert!(node.rejects(unvault));
+        node.confirm_package(unvault);
+        node.confirm_package(&vault.cold_spend(unvault.txid(), 0).unwrap());
+
+        let funding = node.fund(&vault.vault_address().unwrap(), vault.amount);
+        let unvault = &vault
+            .vault_ctv()
+            .unwrap()
+            .spending_tx(funding.txid, funding.vout)
+            .unwrap()[0];
+        node.confirm_package(unvault);
+        node.mine(vault.delay as u32);
+        node.confirm_package(&vault.hot_spend(unvault.txid(), 0).unwrap());
+    }
+}
+
+#[test]
+fn hashlock() {
+    let node = Node::start();
+    for taproot in [false, true] {
+        let preimage = b"cdv inquisition";
+        let covenant = HashlockCovenant {
+            network: Network::Regtest,
+            amount: Amount::from_sat(100_000),
+            hash: sha256::Hash::hash(preimage),
+            hashlock_address: node.new_address(),
+            timelock_address: node.new_address(),
+            locktime: LockTime::from_height(node.height() + 10).unwrap(),
+            taproot,
+        };
+        covenant.validate().unwrap();
+
+        // Reveal the preimage.
+        let funding = node.fund(&covenant.address().unwrap(), covenant.amount);
+        node.confirm(&covenant.hashlock_spend(funding, preimage).unwrap());
+
+        // Wait out the locktime.
+        let funding = node.fund(&covenant.address().unwrap(), covenant.amount);
+        let timelock = covenant.timelock_spend(funding).unwrap();
+        assert!(node.rejects(&timelock));
+        node.mine(10);
+        node.confirm(&timelock);
+    }
+}
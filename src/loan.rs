@@ -0,0 +1,156 @@
+use bitcoin::{
+    absolute::LockTime, address::NetworkUnchecked, hashes::sha256, Address, Amount, Network,
+    OutPoint, Transaction,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{hashlock::HashlockCovenant, util, validate};
+
+/// A peer-to-peer loan, secured by collateral which is locked in a covenant.
+///
+/// The lender picks a repayment secret and only shares its hash. Once the loan is repaid, for
+/// example with a payment which reveals the secret, the borrower can use the secret to return the
+/// collateral to themselves. If the loan isn't repaid by the due date, the lender can take the
+/// collateral instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Loan {
+    pub(crate) network: Network,
+    pub(crate) borrower: Address<NetworkUnchecked>,
+    pub(crate) lender: Address<NetworkUnchecked>,
+    pub(crate) principal: Amount,
+    pub(crate) collateral: Amount,
+    /// The due date, as a unix timestamp.
+    pub(crate) due_date: u32,
+    pub(crate) repayment_hash: sha256::Hash,
+    pub(crate) taproot: bool,
+}
+
+/// Everything the borrower needs to get their collateral back after repaying the loan.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct BorrowerKit {
+    pub(crate) loan: Loan,
+    pub(crate) outpoint: OutPoint,
+}
+
+/// Everything the lender needs to take the collateral after the due date.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct LenderKit {
+    pub(crate) loan: Loan,
+    pub(crate) outpoint: OutPoint,
+    pub(crate) forfeit_tx: String,
+}
+
+impl Loan {
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        validate::check_network("Borrower address", &self.borrower, self.network)?;
+        validate::check_network("Lender address", &self.lender, self.network)?;
+        Ok(self.covenant()?.validate()?)
+    }
+
+    pub(crate) fn address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+        Ok(self.covenant()?.address()?)
+    }
+
+    pub(crate) fn due_date(&self) -> String {
+        util::format_date(self.due_date)
+    }
+
+    /// The transaction kits for both parties, once the collateral has been locked.
+    pub(crate) fn kits(&self, outpoint: OutPoint) -> anyhow::Result<(BorrowerKit, LenderKit)> {
+        let forfeit_tx = self.forfeit_tx(outpoint)?;
+        Ok((
+            BorrowerKit {
+                loan: self.clone(),
+                outpoint,
+            },
+            LenderKit {
+                loan: self.clone(),
+                outpoint,
+                forfeit_tx: hex::encode(bitcoin::consensus::serialize(&forfeit_tx)),
+            },
+        ))
+    }
+
+    /// Return the collateral to the borrower, which needs the repayment secret.
+    pub(crate) fn return_tx(
+        &self,
+        outpoint: OutPoint,
+        secret: &[u8],
+    ) -> anyhow::Result<Transaction> {
+        Ok(self.covenant()?.hashlock_spend(outpoint, secret)?)
+    }
+
+    /// Forfeit the collateral to the lender, which can be mined after the due date.
+    pub(crate) fn forfeit_tx(&self, outpoint: OutPoint) -> anyhow::Result<Transaction> {
+        Ok(self.covenant()?.timelock_spend(outpoint)?)
+    }
+
+    fn covenant(&self) -> anyhow::Result<HashlockCovenant> {
+        Ok(HashlockCovenant {
+            network: self.network,
+            amount: self.collateral,
+            hash: self.repayment_hash,
+            hashlock_address: self.borrower.clone(),
+            timelock_address: self.lender.clone(),
+            locktime: LockTime::from_time(self.due_date)?,
+            taproot: self.taproot,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        consensus,
+        hashes::{sha256, Hash},
+        ScriptBuf, Txid,
+    };
+
+    use super::*;
+
+    fn loan(taproot: bool) -> Loan {
+        let address = |byte: u8| {
+            let script = ScriptBuf::from_bytes(vec![byte; 8]);
+            Address::p2wsh(&script, Network::Regtest)
+                .as_unchecked()
+                .clone()
+        };
+        Loan {
+            network: Network::Regtest,
+            borrower: address(1),
+            lender: address(2),
+            principal: Amount::from_sat(500_000),
+            collateral: Amount::from_sat(1_000_000),
+            due_date: 1_767_225_600,
+            repayment_hash: sha256::Hash::hash(b"repaid"),
+            taproot,
+        }
+    }
+
+    #[test]
+    fn kits() {
+        for taproot in [false, true] {
+            let loan = loan(taproot);
+            loan.validate().unwrap();
+            let outpoint = OutPoint::new(Txid::all_zeros(), 0);
+            let (borrower, lender) = loan.kits(outpoint).unwrap();
+            assert_eq!(borrower.outpoint, outpoint);
+
+            // The lender's kit carries the forfeit, which pays them after the due date.
+            let forfeit: Transaction =
+                consensus::deserialize(&hex::decode(&lender.forfeit_tx).unwrap()).unwrap();
+            assert_eq!(forfeit, loan.forfeit_tx(outpoint).unwrap());
+            let lender = loan.lender.assume_checked_ref().script_pubkey();
+            assert_eq!(forfeit.output[0].script_pubkey, lender);
+            assert_eq!(
+                forfeit.lock_time,
+                LockTime::from_time(loan.due_date).unwrap()
+            );
+
+            let returned = borrower.loan.return_tx(outpoint, b"repaid").unwrap();
+            let borrower = loan.borrower.assume_checked_ref().script_pubkey();
+            assert_eq!(returned.output[0].script_pubkey, borrower);
+            assert!(loan.return_tx(outpoint, b"unpaid").is_err());
+        }
+    }
+}
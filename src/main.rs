@@ -1,10 +1,34 @@
+mod backup;
+mod cli;
+mod custody;
+mod diagram;
 mod error;
+mod explorer;
+mod hashlock;
+#[cfg(all(test, feature = "inquisition"))]
+mod inquisition;
+mod loan;
+mod mining;
+mod package;
+mod payroll;
+mod pegout;
+mod pool;
+mod psbt;
+mod savings;
 mod server;
+mod spacechain;
+mod splitter;
+mod summary;
+mod tickets;
+mod treasury;
+mod tree;
 mod util;
+mod validate;
 mod vault;
 
+use clap::Parser;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    server::server().await?;
-    Ok(())
+    cli::run(cli::Cli::parse().command).await
 }
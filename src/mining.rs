@@ -0,0 +1,357 @@
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime, address::NetworkUnchecked, transaction::Version, Address, Amount, FeeRate,
+    Network, Sequence, Transaction, Txid,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    util::{self, nums_points},
+    validate::{self, Footprint, TxCost},
+};
+
+/// Fee paid by every transaction in the payout tree.
+const NODE_FEE: Amount = Amount::from_sat(600);
+
+/// A payout is flagged once unrolling its branch costs this percentage of it.
+const EXPENSIVE_UNROLL_PERCENT: u64 = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Share {
+    pub(crate) address: Address<NetworkUnchecked>,
+    pub(crate) shares: u64,
+}
+
+pub(crate) struct Payout {
+    pub(crate) address: Address<NetworkUnchecked>,
+    pub(crate) amount: Amount,
+}
+
+/// What it costs a miner to unroll their own branch of the tree, if nobody else has unrolled any
+/// of it yet.
+pub(crate) struct UnrollCost {
+    pub(crate) miner: usize,
+    pub(crate) payout: Amount,
+    pub(crate) vsize: u64,
+    pub(crate) cost: Amount,
+}
+
+/// One round of mining pool payouts.
+///
+/// Instead of paying every miner in the coinbase, the pool pays the round's reward into a
+/// single output which commits to a binary CTV tree. Each leaf of the tree pays one miner their
+/// share of the reward, and each miner can unroll just their own branch of the tree.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct PayoutRound {
+    pub(crate) network: Network,
+    pub(crate) round: u32,
+    pub(crate) reward: Amount,
+    pub(crate) shares: Vec<Share>,
+    pub(crate) taproot: bool,
+}
+
+impl PayoutRound {
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        for (idx, share) in self.shares.iter().enumerate() {
+            let field = format!("Miner {} address", idx + 1);
+            validate::check_network(&field, &share.address, self.network)?;
+        }
+        if self.shares.is_empty() {
+            bail!("Round {} has no shares", self.round);
+        }
+        if self.total_shares()? == 0 {
+            bail!("Round {} has no shares", self.round);
+        }
+        validate::TreeLimits::from_env()?.check_shape(self.shares.len(), self.depth())?;
+        if self.reward <= self.fees() {
+            bail!("Reward does not cover the fees of the payout tree");
+        }
+        Ok(validate::check_tree(
+            &self.root_ctv()?,
+            self.reward,
+            NODE_FEE,
+        )?)
+    }
+
+    /// The size of the payout tree, and what it costs if every miner unrolls their branch.
+    pub(crate) fn footprint(&self) -> anyhow::Result<Footprint> {
+        Ok(validate::measure_tree(&self.root_ctv()?, NODE_FEE)?)
+    }
+
+    /// The size and fee of every transaction in the payout tree.
+    pub(crate) fn fee_report(&self) -> anyhow::Result<Vec<TxCost>> {
+        validate::fee_report(&self.root_ctv()?, self.reward)
+    }
+
+    /// How much each miner is paid, in the order of the share list.
+    pub(crate) fn payouts(&self) -> anyhow::Result<Vec<Payout>> {
+        let available = self
+            .reward
+            .checked_sub(self.fees())
+            .ok_or_else(|| anyhow!("Reward does not cover the fees of the payout tree"))?;
+        let total = self.total_shares()? as u128;
+        let mut payouts = self
+            .shares
+            .iter()
+            .map(|share| Payout {
+                address: share.address.clone(),
+                amount: Amount::from_sat(
+                    (available.to_sat() as u128 * share.shares as u128 / total) as u64,
+                ),
+            })
+            .collect::<Vec<_>>();
+        // Whatever is left over from rounding goes to the last miner, rather than to the fee.
+        let paid = payouts
+            .iter()
+            .try_fold(Amount::ZERO, |total, p| total.checked_add(p.amount))
+            .ok_or_else(|| anyhow!("Payouts add up to more than 21 million BTC"))?;
+        if let Some(last) = payouts.last_mut() {
+            last.amount += available - paid;
+        }
+        Ok(payouts)
+    }
+
+    pub(crate) fn address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+        Ok(self.root_ctv()?.address()?.as_unchecked().clone())
+    }
+
+    /// Every transaction in the payout tree.
+    pub(crate) fn spending_txs(&self, txid: Txid, vout: u32) -> anyhow::Result<Vec<Transaction>> {
+        Ok(util::spending_txs(&self.root_ctv()?, txid, vout)?)
+    }
+
+    /// The transactions a single miner needs to broadcast, in order, to unroll their branch of
+    /// the tree.
+    pub(crate) fn redemption_path(
+        &self,
+        miner: usize,
+        mut txid: Txid,
+        mut vout: u32,
+    ) -> anyhow::Result<Vec<Transaction>> {
+        let payouts = self.payouts()?;
+        if miner >= payouts.len() {
+            bail!("Unknown miner #{miner}");
+        }
+
+        let mut path = Vec::new();
+        let mut start = 0;
+        let mut node = payouts.as_slice();
+        loop {
+            let tx = util::spending_tx(&self.node_ctv(node)?, txid, vout)?;
+            txid = tx.txid();
+            path.push(tx);
+            if node.len() == 1 {
+                return Ok(path);
+            }
+
+            let mid = node.len() / 2;
+            if miner - start < mid {
+                node = &node[..mid];
+                vout = 0;
+            } else {
+                node = &node[mid..];
+                start += mid;
+                vout = 1;
+            }
+            if node.len() == 1 {
+                return Ok(path);
+            }
+        }
+    }
+
+    /// What unrolling each miner's branch costs at the given fee rate, in the order of the share
+    /// list.
+    pub(crate) fn unroll_costs(&self, fee_rate: FeeRate) -> anyhow::Result<Vec<UnrollCost>> {
+        self.payouts()?
+            .into_iter()
+            .enumerate()
+            .map(|(miner, payout)| {
+                let vsize = self
+                    .redemption_path(miner, validate::placeholder_txid(), 0)?
+                    .iter()
+                    .try_fold(0u64, |total, tx| total.checked_add(tx.vsize() as u64))
+                    .ok_or_else(|| anyhow!("Branch of miner {} is too large", miner + 1))?;
+                let cost = fee_rate
+                    .fee_vb(vsize)
+                    .ok_or_else(|| anyhow!("Fee rate {fee_rate:#} is too high"))?;
+                Ok(UnrollCost {
+                    miner,
+                    payout: payout.amount,
+                    vsize,
+                    cost,
+                })
+            })
+            .collect()
+    }
+
+    /// Flag the payouts which cost more than they're worth to unroll at the given fee rate, or
+    /// close to it. Fails on the former if `reject` is set, and returns warnings otherwise.
+    pub(crate) fn check_unroll_costs(
+        &self,
+        fee_rate: FeeRate,
+        reject: bool,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut warnings = Vec::new();
+        for cost in self.unroll_costs(fee_rate)? {
+            let miner = cost.miner + 1;
+            if cost.cost >= cost.payout {
+                let message = format!(
+                    "Miner {miner} is paid {}, but unrolling their {} vB branch costs {} at {fee_rate:#}",
+                    cost.payout, cost.vsize, cost.cost
+                );
+                if reject {
+                    bail!(message);
+                }
+                warnings.push(message);
+            } else if cost.cost * 100 >= cost.payout * EXPENSIVE_UNROLL_PERCENT {
+                warnings.push(format!(
+                    "Miner {miner} is paid {}, and unrolling their {} vB branch costs {} of it at {fee_rate:#}",
+                    cost.payout, cost.vsize, cost.cost
+                ));
+            }
+        }
+        Ok(warnings)
+    }
+
+    fn total_shares(&self) -> anyhow::Result<u64> {
+        self.shares
+            .iter()
+            .try_fold(0u64, |total, s| total.checked_add(s.shares))
+            .ok_or_else(|| anyhow!("Shares add up to more than {}", u64::MAX))
+    }
+
+    /// How many transactions deep the tree is, since every node splits its payouts in half.
+    fn depth(&self) -> usize {
+        let leaves = self.shares.len().max(2);
+        (usize::BITS - (leaves - 1).leading_zeros()) as usize
+    }
+
+    /// Every internal node of the tree is a transaction which pays a fee.
+    fn fees(&self) -> Amount {
+        NODE_FEE * self.shares.len().saturating_sub(1).max(1) as u64
+    }
+
+    fn root_ctv(&self) -> anyhow::Result<Context> {
+        self.node_ctv(&self.payouts()?)
+    }
+
+    fn node_ctv(&self, payouts: &[Payout]) -> anyhow::Result<Context> {
+        let outputs = if payouts.len() == 1 {
+            vec![self.output(payouts)?]
+        } else {
+            let (left, right) = payouts.split_at(payouts.len() / 2);
+            vec![self.output(left)?, self.output(right)?]
+        };
+        Ok(Context {
+            network: self.network,
+            tx_type: self.tx_type(),
+            fields: Fields {
+                version: Version::ONE,
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::ZERO],
+                outputs,
+                input_idx: 0,
+            },
+        })
+    }
+
+    fn output(&self, payouts: &[Payout]) -> anyhow::Result<Output> {
+        if let [payout] = payouts {
+            return Ok(Output::Address {
+                address: payout.address.clone(),
+                amount: payout.amount,
+            });
+        }
+        let amount = payouts
+            .iter()
+            .try_fold(NODE_FEE * (payouts.len() - 1) as u64, |total, p| {
+                total.checked_add(p.amount)
+            })
+            .ok_or_else(|| anyhow!("Payouts add up to more than 21 million BTC"))?;
+        Ok(Output::Tree {
+            tree: Box::new(self.node_ctv(payouts)?),
+            amount,
+        })
+    }
+
+    fn tx_type(&self) -> TxType {
+        if self.taproot {
+            return TxType::Taproot {
+                internal_key: nums_points(),
+            };
+        }
+        TxType::Segwit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, ScriptBuf};
+
+    use super::*;
+
+    fn round(shares: &[u64]) -> PayoutRound {
+        PayoutRound {
+            network: Network::Regtest,
+            round: 1,
+            reward: Amount::from_sat(1_000_000),
+            shares: shares
+                .iter()
+                .enumerate()
+                .map(|(idx, shares)| {
+                    let script = ScriptBuf::from_bytes(vec![idx as u8; 8]);
+                    Share {
+                        address: Address::p2wsh(&script, Network::Regtest)
+                            .as_unchecked()
+                            .clone(),
+                        shares: *shares,
+                    }
+                })
+                .collect(),
+            taproot: false,
+        }
+    }
+
+    #[test]
+    fn payouts() {
+        let round = round(&[1, 2, 3, 4, 5]);
+        round.validate().unwrap();
+        let payouts = round.payouts().unwrap();
+        let paid: Amount = payouts.iter().map(|p| p.amount).sum();
+        assert_eq!(paid + NODE_FEE * 4, round.reward);
+        assert!(payouts[0].amount < payouts[4].amount);
+
+        // One transaction per internal node of the tree.
+        let txs = round.spending_txs(Txid::all_zeros(), 0).unwrap();
+        assert_eq!(txs.len(), 4);
+    }
+
+    #[test]
+    fn redemption_path() {
+        let round = round(&[1, 2, 3, 4, 5]);
+        let payouts = round.payouts().unwrap();
+        let all = round.spending_txs(Txid::all_zeros(), 0).unwrap();
+        for (miner, payout) in payouts.iter().enumerate() {
+            let path = round.redemption_path(miner, Txid::all_zeros(), 0).unwrap();
+            assert!(path.len() <= round.depth());
+            // The branch is made of the same transactions as the whole tree, and ends by paying
+            // the miner.
+            assert!(path.iter().all(|tx| all.contains(tx)));
+            let script = payout.address.assume_checked_ref().script_pubkey();
+            let last = path.last().unwrap();
+            assert!(last
+                .output
+                .iter()
+                .any(|o| o.script_pubkey == script && o.value == payout.amount));
+        }
+        assert!(round.redemption_path(5, Txid::all_zeros(), 0).is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        let round = round(&[u64::MAX, 1]);
+        assert!(round.validate().is_err());
+        assert!(round.payouts().is_err());
+    }
+}
@@ -0,0 +1,35 @@
+//! Spend packages: every transaction needed to unroll a contract, written as one hex-encoded
+//! transaction per line, in broadcast order.
+
+use std::io::{self, Write};
+
+use bitcoin::{consensus::Encodable, Transaction};
+
+/// Write a single line of a spend package, encoding the transaction straight into the writer
+/// instead of building its hex string first.
+pub(crate) fn write_tx<W: Write>(tx: &Transaction, out: &mut W) -> io::Result<()> {
+    tx.consensus_encode(&mut HexWriter(&mut *out))?;
+    out.write_all(b"\n")
+}
+
+/// Hex-encodes everything written to it into the inner writer.
+struct HexWriter<W>(W);
+
+impl<W: Write> Write for HexWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let mut buf = [0; 128];
+        for chunk in bytes.chunks(buf.len() / 2) {
+            for (idx, byte) in chunk.iter().enumerate() {
+                buf[idx * 2] = DIGITS[(byte >> 4) as usize];
+                buf[idx * 2 + 1] = DIGITS[(byte & 0xf) as usize];
+            }
+            self.0.write_all(&buf[..chunk.len() * 2])?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime, address::NetworkUnchecked, transaction::Version, Address, Amount, Network,
+    Sequence, Transaction, Txid,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+
+use crate::{util::nums_points, validate};
+
+/// Fee added on top of the salaries for every pay period.
+const PERIOD_FEE: Amount = Amount::from_sat(600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Employee {
+    pub(crate) address: Address<NetworkUnchecked>,
+    pub(crate) amount: Amount,
+}
+
+/// A payroll which pre-commits salary payments for a number of pay periods.
+///
+/// Each pay period gets its own funding output, locked to a CTV template which pays every
+/// employee their salary, and which can't be mined before the period's block height.
+///
+/// The salaries are paid straight from that one transaction rather than through a tree of
+/// templates, so a period costs a single fee however many employees there are. A payroll too
+/// large for one standard transaction fails validation.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Payroll {
+    pub(crate) network: Network,
+    pub(crate) employees: Vec<Employee>,
+    pub(crate) periods: u32,
+    pub(crate) start_height: u32,
+    pub(crate) interval: u32,
+    pub(crate) taproot: bool,
+}
+
+pub(crate) struct Period {
+    pub(crate) index: u32,
+    pub(crate) height: u32,
+    pub(crate) address: Address<NetworkUnchecked>,
+    pub(crate) amount: Amount,
+}
+
+impl Payroll {
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        for (idx, employee) in self.employees.iter().enumerate() {
+            let field = format!("Employee {} address", idx + 1);
+            validate::check_network(&field, &employee.address, self.network)?;
+        }
+        if self.employees.is_empty() {
+            bail!("Payroll has no employees");
+        }
+        if self.periods == 0 {
+            bail!("Payroll needs at least one pay period");
+        }
+        self.height(self.periods - 1)?;
+        Ok(validate::check_tree(
+            &self.period_ctv(0)?,
+            self.period_amount()?,
+            PERIOD_FEE,
+        )?)
+    }
+
+    /// The amount which has to be sent to each pay period's address.
+    pub(crate) fn period_amount(&self) -> anyhow::Result<Amount> {
+        self.employees
+            .iter()
+            .try_fold(PERIOD_FEE, |total, e| total.checked_add(e.amount))
+            .ok_or_else(|| anyhow!("Salaries add up to more than 21 million BTC"))
+    }
+
+    pub(crate) fn periods(&self) -> anyhow::Result<Vec<Period>> {
+        let amount = self.period_amount()?;
+        (0..self.periods)
+            .map(|index| {
+                Ok(Period {
+                    index,
+                    height: self.height(index)?,
+                    address: self.period_ctv(index)?.address()?.as_unchecked().clone(),
+                    amount,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) fn spending_tx(
+        &self,
+        period: u32,
+        txid: Txid,
+        vout: u32,
+    ) -> anyhow::Result<Transaction> {
+        if period >= self.periods {
+            bail!("Payroll has no pay period {period}");
+        }
+        Ok(self.period_ctv(period)?.spending_tx(txid, vout)?[0].clone())
+    }
+
+    pub(crate) fn height(&self, period: u32) -> anyhow::Result<u32> {
+        period
+            .checked_mul(self.interval)
+            .and_then(|offset| offset.checked_add(self.start_height))
+            .filter(|height| *height < 500_000_000)
+            .ok_or_else(|| anyhow!("Pay period {period} is too far in the future"))
+    }
+
+    fn period_ctv(&self, period: u32) -> anyhow::Result<Context> {
+        Ok(Context {
+            network: self.network,
+            tx_type: self.tx_type(),
+            fields: Fields {
+                version: Version::TWO,
+                locktime: LockTime::from_height(self.height(period)?)?,
+                sequences: vec![Sequence::ENABLE_LOCKTIME_NO_RBF],
+                outputs: self
+                    .employees
+                    .iter()
+                    .map(|e| Output::Address {
+                        address: e.address.clone(),
+                        amount: e.amount,
+                    })
+                    .collect(),
+                input_idx: 0,
+            },
+        })
+    }
+
+    fn tx_type(&self) -> TxType {
+        if self.taproot {
+            return TxType::Taproot {
+                internal_key: nums_points(),
+            };
+        }
+        TxType::Segwit
+    }
+}
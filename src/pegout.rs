@@ -0,0 +1,186 @@
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime, address::NetworkUnchecked, transaction::Version, Address, Amount, Network,
+    Sequence, Transaction, Txid,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+
+use crate::{util::nums_points, validate};
+
+/// Fee paid by the batch transaction.
+const BATCH_FEE: Amount = Amount::from_sat(600);
+
+/// A batch can't have more withdrawals than this.
+const MAX_WITHDRAWALS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Withdrawal {
+    pub(crate) address: Address<NetworkUnchecked>,
+    pub(crate) amount: Amount,
+}
+
+/// A batch of peg-outs from a federated sidechain.
+///
+/// The federation moves the funds for the batch into a CTV address, which can only be spent by
+/// the transaction paying every committed withdrawal. From then on, the federation can't pay
+/// anything else with those funds, and any watchtower can broadcast the batch.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct PegoutBatch {
+    pub(crate) network: Network,
+    pub(crate) withdrawals: Vec<Withdrawal>,
+    pub(crate) taproot: bool,
+}
+
+/// What a user needs to check that their withdrawal is committed to by the batch: the batch's
+/// address and template hash, and every output of the template, so the hash can be recomputed.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Inclusion {
+    pub(crate) address: Address<NetworkUnchecked>,
+    pub(crate) template_hash: String,
+    pub(crate) vout: u32,
+    pub(crate) withdrawal: Withdrawal,
+    pub(crate) fields: Fields,
+}
+
+impl PegoutBatch {
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        for (idx, withdrawal) in self.withdrawals.iter().enumerate() {
+            let field = format!("Withdrawal {} address", idx + 1);
+            validate::check_network(&field, &withdrawal.address, self.network)?;
+        }
+        if self.withdrawals.is_empty() {
+            bail!("Batch has no withdrawals");
+        }
+        if self.withdrawals.len() > MAX_WITHDRAWALS {
+            bail!("Batch can't have more than {MAX_WITHDRAWALS} withdrawals");
+        }
+        Ok(validate::check_tree(
+            &self.batch_ctv(),
+            self.amount()?,
+            BATCH_FEE,
+        )?)
+    }
+
+    /// The amount the federation has to commit: every withdrawal plus the fee of the batch.
+    pub(crate) fn amount(&self) -> anyhow::Result<Amount> {
+        self.withdrawals
+            .iter()
+            .try_fold(BATCH_FEE, |total, w| total.checked_add(w.amount))
+            .ok_or_else(|| anyhow!("Withdrawals add up to more than 21 million BTC"))
+    }
+
+    pub(crate) fn address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+        Ok(self.batch_ctv().address()?.as_unchecked().clone())
+    }
+
+    pub(crate) fn template_hash(&self) -> anyhow::Result<String> {
+        Ok(hex::encode(self.batch_ctv().ctv()?))
+    }
+
+    /// The inclusion data of every withdrawal, in batch order.
+    pub(crate) fn inclusions(&self) -> anyhow::Result<Vec<Inclusion>> {
+        let ctv = self.batch_ctv();
+        let address = self.address()?;
+        let template_hash = self.template_hash()?;
+        Ok(self
+            .withdrawals
+            .iter()
+            .enumerate()
+            .map(|(vout, withdrawal)| Inclusion {
+                address: address.clone(),
+                template_hash: template_hash.clone(),
+                vout: vout as u32,
+                withdrawal: withdrawal.clone(),
+                fields: ctv.fields.clone(),
+            })
+            .collect())
+    }
+
+    pub(crate) fn batch_tx(&self, txid: Txid, vout: u32) -> anyhow::Result<Transaction> {
+        Ok(self.batch_ctv().spending_tx(txid, vout)?[0].clone())
+    }
+
+    fn batch_ctv(&self) -> Context {
+        Context {
+            network: self.network,
+            tx_type: self.tx_type(),
+            fields: Fields {
+                version: Version::ONE,
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::ZERO],
+                outputs: self
+                    .withdrawals
+                    .iter()
+                    .map(|w| Output::Address {
+                        address: w.address.clone(),
+                        amount: w.amount,
+                    })
+                    .collect(),
+                input_idx: 0,
+            },
+        }
+    }
+
+    fn tx_type(&self) -> TxType {
+        if self.taproot {
+            return TxType::Taproot {
+                internal_key: nums_points(),
+            };
+        }
+        TxType::Segwit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, ScriptBuf};
+
+    use super::*;
+
+    fn batch(amounts: &[u64]) -> PegoutBatch {
+        PegoutBatch {
+            network: Network::Regtest,
+            withdrawals: amounts
+                .iter()
+                .enumerate()
+                .map(|(idx, amount)| {
+                    let script = ScriptBuf::from_bytes(vec![idx as u8; 8]);
+                    Withdrawal {
+                        address: Address::p2wsh(&script, Network::Regtest)
+                            .as_unchecked()
+                            .clone(),
+                        amount: Amount::from_sat(*amount),
+                    }
+                })
+                .collect(),
+            taproot: false,
+        }
+    }
+
+    #[test]
+    fn inclusions() {
+        let batch = batch(&[10_000, 20_000, 30_000]);
+        batch.validate().unwrap();
+        assert_eq!(batch.amount().unwrap(), Amount::from_sat(60_600));
+
+        // Every user can find their own withdrawal in the batch transaction.
+        let tx = batch.batch_tx(Txid::all_zeros(), 0).unwrap();
+        for inclusion in batch.inclusions().unwrap() {
+            let output = &tx.output[inclusion.vout as usize];
+            let address = inclusion.withdrawal.address.assume_checked_ref();
+            assert_eq!(output.script_pubkey, address.script_pubkey());
+            assert_eq!(output.value, inclusion.withdrawal.amount);
+            assert_eq!(inclusion.template_hash, batch.template_hash().unwrap());
+            assert_eq!(inclusion.address, batch.address().unwrap());
+        }
+    }
+
+    #[test]
+    fn rejects() {
+        assert!(batch(&[]).validate().is_err());
+        let overflow = batch(&[u64::MAX, 1]);
+        assert!(overflow.amount().is_err());
+        assert!(overflow.validate().is_err());
+    }
+}
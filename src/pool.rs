@@ -0,0 +1,345 @@
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime,
+    address::NetworkUnchecked,
+    opcodes::all::{OP_CHECKSIG, OP_CHECKSIGADD, OP_NOP4, OP_NUMEQUAL},
+    script::PushBytesBuf,
+    secp256k1::SECP256K1,
+    taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo},
+    transaction::Version,
+    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+    XOnlyPublicKey,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+
+use crate::{util::nums_points, validate};
+
+/// Fee paid by each member when the pool is exited.
+const EXIT_FEE: Amount = Amount::from_sat(600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Member {
+    pub(crate) key: XOnlyPublicKey,
+    pub(crate) address: Address<NetworkUnchecked>,
+    pub(crate) amount: Amount,
+}
+
+/// A payment pool shared by a set of members.
+///
+/// The pool output is a taproot output with two leaves: an n-of-n cooperative leaf, used to
+/// splice members out of the pool, and a CTV leaf which pays every member out at once, which
+/// any member can use if cooperation breaks down.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Pool {
+    pub(crate) network: Network,
+    pub(crate) members: Vec<Member>,
+    /// Every cooperative exit re-pools the remaining members into the next version of the pool.
+    pub(crate) version: u32,
+    /// Where this version of the pool lives on-chain, once it has been funded.
+    pub(crate) outpoint: Option<OutPoint>,
+}
+
+impl Pool {
+    pub(crate) fn new(network: Network, members: Vec<Member>) -> anyhow::Result<Pool> {
+        if members.len() < 2 {
+            bail!("A pool needs at least two members");
+        }
+        Ok(Pool {
+            network,
+            members,
+            version: 0,
+            outpoint: None,
+        })
+    }
+
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        for (idx, member) in self.members.iter().enumerate() {
+            let field = format!("Member {} address", idx + 1);
+            validate::check_network(&field, &member.address, self.network)?;
+        }
+        Ok(validate::check_tree(
+            &self.exit_ctv()?,
+            self.amount()?,
+            EXIT_FEE * self.members.len() as u64,
+        )?)
+    }
+
+    pub(crate) fn amount(&self) -> anyhow::Result<Amount> {
+        self.members
+            .iter()
+            .try_fold(Amount::ZERO, |total, m| total.checked_add(m.amount))
+            .ok_or_else(|| anyhow!("Members add up to more than 21 million BTC"))
+    }
+
+    pub(crate) fn address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+        let tsi = self.taproot_spend_info()?;
+        Ok(
+            Address::p2tr(SECP256K1, nums_points(), tsi.merkle_root(), self.network)
+                .as_unchecked()
+                .clone(),
+        )
+    }
+
+    pub(crate) fn cooperative_script(&self) -> ScriptBuf {
+        let mut builder = bitcoin::script::Builder::new();
+        for (idx, member) in self.members.iter().enumerate() {
+            builder = builder.push_x_only_key(&member.key);
+            builder = if idx == 0 {
+                builder.push_opcode(OP_CHECKSIG)
+            } else {
+                builder.push_opcode(OP_CHECKSIGADD)
+            };
+        }
+        builder
+            .push_int(self.members.len() as i64)
+            .push_opcode(OP_NUMEQUAL)
+            .into_script()
+    }
+
+    pub(crate) fn exit_script(&self) -> anyhow::Result<ScriptBuf> {
+        let exit_hash = PushBytesBuf::try_from(self.exit_ctv()?.ctv()?)?;
+        Ok(bitcoin::script::Builder::new()
+            .push_slice(exit_hash)
+            .push_opcode(OP_NOP4)
+            .into_script())
+    }
+
+    /// Control block for the cooperative leaf, which the members need in order to sign a
+    /// splice-out.
+    pub(crate) fn cooperative_control_block(&self) -> anyhow::Result<Vec<u8>> {
+        let cb = self
+            .taproot_spend_info()?
+            .control_block(&(self.cooperative_script(), LeafVersion::TapScript))
+            .ok_or_else(|| anyhow!("Invalid tapscript formation"))?;
+        Ok(cb.serialize())
+    }
+
+    /// Pays every member their share, without needing anyone's signature.
+    pub(crate) fn unilateral_exit(&self) -> anyhow::Result<Transaction> {
+        let outpoint = self.outpoint()?;
+        let exit_ctv = self.exit_ctv()?;
+        let script = self.exit_script()?;
+        let cb = self
+            .taproot_spend_info()?
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or_else(|| anyhow!("Invalid tapscript formation"))?;
+        let mut witness = Witness::new();
+        witness.push(script);
+        witness.push(cb.serialize());
+
+        let output = self
+            .members
+            .iter()
+            .map(|m| payout(m, self.network))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Transaction {
+            version: exit_ctv.fields.version,
+            lock_time: exit_ctv.fields.locktime,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ZERO,
+                witness,
+            }],
+            output,
+        })
+    }
+
+    /// Build the cooperative splice-out transaction which pays the leaving members and re-pools
+    /// everyone else. The transaction is unsigned: every current member has to sign it with the
+    /// cooperative leaf. Returns the next version of the pool, if at least two members are left
+    /// in it.
+    pub(crate) fn splice_out(
+        &self,
+        leavers: &[usize],
+    ) -> anyhow::Result<(Transaction, Option<Pool>)> {
+        let outpoint = self.outpoint()?;
+        if leavers.is_empty() {
+            bail!("No members have requested to exit");
+        }
+        if let Some(idx) = leavers.iter().find(|idx| **idx >= self.members.len()) {
+            bail!("Unknown pool member #{idx}");
+        }
+
+        let mut output = Vec::new();
+        let mut remaining = Vec::new();
+        for (idx, member) in self.members.iter().enumerate() {
+            if leavers.contains(&idx) {
+                output.push(payout(member, self.network)?);
+            } else {
+                remaining.push(member.clone());
+            }
+        }
+
+        // A pool of one is just that member's output, so they're paid out along with the leavers.
+        if let [last] = remaining.as_slice() {
+            output.push(payout(last, self.network)?);
+            remaining.clear();
+        }
+
+        let next = if remaining.is_empty() {
+            None
+        } else {
+            let mut next = Pool::new(self.network, remaining)?;
+            next.version = self.version + 1;
+            output.push(TxOut {
+                value: next.amount()?,
+                script_pubkey: next
+                    .address()?
+                    .require_network(self.network)?
+                    .script_pubkey(),
+            });
+            let tx = splice_tx(outpoint, output.clone());
+            next.outpoint = Some(OutPoint {
+                txid: tx.txid(),
+                vout: (output.len() - 1) as u32,
+            });
+            Some(next)
+        };
+
+        Ok((splice_tx(outpoint, output), next))
+    }
+
+    fn exit_ctv(&self) -> anyhow::Result<Context> {
+        Ok(Context {
+            network: self.network,
+            tx_type: TxType::Taproot {
+                internal_key: nums_points(),
+            },
+            fields: Fields {
+                version: Version::ONE,
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::ZERO],
+                outputs: self
+                    .members
+                    .iter()
+                    .map(|m| {
+                        Ok(Output::Address {
+                            address: m.address.clone(),
+                            amount: payout_amount(m)?,
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+                input_idx: 0,
+            },
+        })
+    }
+
+    fn taproot_spend_info(&self) -> anyhow::Result<TaprootSpendInfo> {
+        TaprootBuilder::new()
+            .add_leaf(1, self.cooperative_script())?
+            .add_leaf(1, self.exit_script()?)?
+            .finalize(SECP256K1, nums_points())
+            .map_err(|_| anyhow!("Taproot not finalizable"))
+    }
+
+    fn outpoint(&self) -> anyhow::Result<OutPoint> {
+        self.outpoint
+            .ok_or_else(|| anyhow!("Pool version {} has not been funded", self.version))
+    }
+}
+
+fn splice_tx(outpoint: OutPoint, output: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output,
+    }
+}
+
+fn payout(member: &Member, network: Network) -> anyhow::Result<TxOut> {
+    Ok(TxOut {
+        value: payout_amount(member)?,
+        script_pubkey: member
+            .address
+            .clone()
+            .require_network(network)?
+            .script_pubkey(),
+    })
+}
+
+fn payout_amount(member: &Member) -> anyhow::Result<Amount> {
+    member
+        .amount
+        .checked_sub(EXIT_FEE)
+        .ok_or_else(|| anyhow!("Member amount {} does not cover the fee", member.amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, secp256k1::SecretKey, Txid};
+
+    use super::*;
+
+    fn members(amounts: &[u64]) -> Vec<Member> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(idx, amount)| {
+                let secret = SecretKey::from_slice(&[idx as u8 + 1; 32]).unwrap();
+                let script = ScriptBuf::from_bytes(vec![idx as u8; 8]);
+                Member {
+                    key: secret.x_only_public_key(SECP256K1).0,
+                    address: Address::p2wsh(&script, Network::Regtest)
+                        .as_unchecked()
+                        .clone(),
+                    amount: Amount::from_sat(*amount),
+                }
+            })
+            .collect()
+    }
+
+    fn funded(amounts: &[u64]) -> Pool {
+        let mut pool = Pool::new(Network::Regtest, members(amounts)).unwrap();
+        pool.outpoint = Some(OutPoint::new(Txid::all_zeros(), 0));
+        pool
+    }
+
+    #[test]
+    fn unilateral_exit() {
+        let pool = funded(&[50_000, 30_000, 20_000]);
+        pool.validate().unwrap();
+        assert_eq!(pool.amount().unwrap(), Amount::from_sat(100_000));
+
+        // The exit has to pay exactly what the CTV leaf commits to.
+        let exit = pool.unilateral_exit().unwrap();
+        let template = &pool
+            .exit_ctv()
+            .unwrap()
+            .spending_tx(Txid::all_zeros(), 0)
+            .unwrap()[0];
+        assert_eq!(exit.output, template.output);
+        assert_eq!(exit.version, template.version);
+        assert_eq!(exit.input[0].sequence, template.input[0].sequence);
+    }
+
+    #[test]
+    fn splice_out() {
+        let pool = funded(&[50_000, 30_000, 20_000]);
+        let (tx, next) = pool.splice_out(&[0]).unwrap();
+        let next = next.unwrap();
+        assert_eq!(next.version, 1);
+        assert_eq!(next.members.len(), 2);
+        assert_eq!(tx.output[1].value, Amount::from_sat(50_000));
+        assert_eq!(next.outpoint, Some(OutPoint::new(tx.txid(), 1)));
+
+        // Leaving one member behind pays them out too.
+        let (tx, next) = pool.splice_out(&[0, 1]).unwrap();
+        assert!(next.is_none());
+        assert_eq!(tx.output.len(), 3);
+    }
+
+    #[test]
+    fn overflow() {
+        let pool = funded(&[u64::MAX, 10_000]);
+        assert!(pool.amount().is_err());
+        assert!(pool.validate().is_err());
+    }
+}
@@ -0,0 +1,70 @@
+//! PSBTs of contract spends, for wallets which want to check them, or add their own inputs,
+//! before they're broadcast.
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use bitcoin::{taproot::ControlBlock, OutPoint, Psbt, ScriptBuf, Transaction, TxOut, Witness};
+
+/// Wrap transactions in PSBTs, in the same order. `funding` is the output the first one spends;
+/// the outputs of every transaction in the list can be spent by the ones after it.
+///
+/// Covenant spends need no signatures, so each input is already finalized with its witness, and
+/// also carries the script the witness executes so wallets can see what they're spending.
+pub(crate) fn from_txs(
+    txs: &[Transaction],
+    funding: (OutPoint, TxOut),
+) -> anyhow::Result<Vec<Psbt>> {
+    let mut utxos = HashMap::from([funding]);
+    let mut psbts = Vec::new();
+    for (idx, tx) in txs.iter().enumerate() {
+        let mut unsigned = tx.clone();
+        for input in &mut unsigned.input {
+            input.script_sig = ScriptBuf::new();
+            input.witness = Witness::new();
+        }
+        let mut psbt = Psbt::from_unsigned_tx(unsigned)?;
+        for (input, psbt_input) in tx.input.iter().zip(&mut psbt.inputs) {
+            let utxo = utxos.get(&input.previous_output).ok_or_else(|| {
+                anyhow!(
+                    "Transaction {} spends {}, which isn't the funding output or an output of an earlier transaction",
+                    idx + 1,
+                    input.previous_output
+                )
+            })?;
+            if utxo.script_pubkey.is_p2wsh() {
+                psbt_input.witness_script = input
+                    .witness
+                    .last()
+                    .map(|script| ScriptBuf::from_bytes(script.to_vec()));
+            } else if utxo.script_pubkey.is_p2tr() {
+                if let (Some(script), Some(control_block)) =
+                    (input.witness.tapscript(), input.witness.last())
+                {
+                    let control_block = ControlBlock::decode(control_block)?;
+                    psbt_input.tap_internal_key = Some(control_block.internal_key);
+                    let leaf_version = control_block.leaf_version;
+                    psbt_input
+                        .tap_scripts
+                        .insert(control_block, (script.into(), leaf_version));
+                }
+            }
+            psbt_input.witness_utxo = Some(utxo.clone());
+            if !input.witness.is_empty() {
+                psbt_input.final_script_witness = Some(input.witness.clone());
+            }
+        }
+        let txid = tx.txid();
+        for (vout, output) in tx.output.iter().enumerate() {
+            utxos.insert(
+                OutPoint {
+                    txid,
+                    vout: vout as u32,
+                },
+                output.clone(),
+            );
+        }
+        psbts.push(psbt);
+    }
+    Ok(psbts)
+}
@@ -0,0 +1,314 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime,
+    address::NetworkUnchecked,
+    opcodes::all::{OP_CHECKSIG, OP_ELSE, OP_ENDIF, OP_IF, OP_NOP4},
+    script::PushBytesBuf,
+    secp256k1::SECP256K1,
+    taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo},
+    transaction::Version,
+    Address, Amount, Network, OutPoint, PublicKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, Witness, XOnlyPublicKey,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    util::{self, nums_points},
+    validate,
+};
+
+/// Fee paid by every release transaction.
+const RELEASE_FEE: Amount = Amount::from_sat(600);
+
+/// Savings plans can't have more maturity dates than this.
+const MAX_MATURITIES: usize = 100;
+
+/// A self-custodial savings plan which releases an installment on each maturity date.
+///
+/// Each stage of the plan is locked to a CTV template, which can't be mined before the stage's
+/// maturity date, and which pays out one installment and locks the rest of the savings into the
+/// next stage. Every stage can also be spent at any time with the emergency key, which should be
+/// stored separately from everyday keys.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SavingsPlan {
+    pub(crate) network: Network,
+    pub(crate) owner: Address<NetworkUnchecked>,
+    pub(crate) emergency_key: PublicKey,
+    pub(crate) installment: Amount,
+    /// Maturity dates, as unix timestamps.
+    pub(crate) maturities: Vec<u32>,
+    pub(crate) taproot: bool,
+}
+
+pub(crate) struct Maturity {
+    pub(crate) stage: usize,
+    pub(crate) date: String,
+    pub(crate) amount: Amount,
+    pub(crate) matured: bool,
+}
+
+impl SavingsPlan {
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        validate::check_network("Owner address", &self.owner, self.network)?;
+        if self.maturities.is_empty() {
+            bail!("Savings plan needs at least one maturity date");
+        }
+        if self.maturities.len() > MAX_MATURITIES {
+            bail!("Savings plan can't have more than {MAX_MATURITIES} maturity dates");
+        }
+        if self.maturities.windows(2).any(|w| w[0] >= w[1]) {
+            bail!("Maturity dates must be in order, without duplicates");
+        }
+        let releases = self.releases(validate::placeholder_txid(), 0)?;
+        Ok(validate::check_txs(
+            self.network,
+            self.amount()?,
+            &releases,
+            &vec![RELEASE_FEE; releases.len()],
+        )?)
+    }
+
+    /// The amount to deposit: every installment plus the fee of every release.
+    pub(crate) fn amount(&self) -> anyhow::Result<Amount> {
+        self.stage_amount(0)
+    }
+
+    pub(crate) fn address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+        self.stage_address(0)
+    }
+
+    /// When each installment matures, and whether it has already matured.
+    pub(crate) fn calendar(&self) -> anyhow::Result<Vec<Maturity>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        Ok(self
+            .maturities
+            .iter()
+            .enumerate()
+            .map(|(stage, timestamp)| Maturity {
+                stage,
+                date: util::format_date(*timestamp),
+                amount: self.installment,
+                matured: now >= *timestamp as u64,
+            })
+            .collect())
+    }
+
+    /// The script which spends any stage with the emergency key.
+    pub(crate) fn emergency_script(&self, stage: usize) -> anyhow::Result<ScriptBuf> {
+        if self.taproot {
+            return Ok(bitcoin::script::Builder::new()
+                .push_x_only_key(&XOnlyPublicKey::from(self.emergency_key.inner))
+                .push_opcode(OP_CHECKSIG)
+                .into_script());
+        }
+        self.redeem_script(stage)
+    }
+
+    /// Every release transaction of the plan, in maturity order.
+    pub(crate) fn releases(&self, txid: Txid, vout: u32) -> anyhow::Result<Vec<Transaction>> {
+        let mut outpoint = OutPoint { txid, vout };
+        let mut releases = Vec::new();
+        for stage in 0..self.maturities.len() {
+            let tx = self.release(stage, outpoint)?;
+            outpoint = OutPoint {
+                txid: tx.txid(),
+                vout: 1,
+            };
+            releases.push(tx);
+        }
+        Ok(releases)
+    }
+
+    fn release(&self, stage: usize, outpoint: OutPoint) -> anyhow::Result<Transaction> {
+        let mut witness = Witness::new();
+        if self.taproot {
+            let script = self.release_script(stage)?;
+            let cb = self
+                .stage_spend_info(stage)?
+                .control_block(&(script.clone(), LeafVersion::TapScript))
+                .ok_or_else(|| anyhow!("Invalid tapscript formation"))?;
+            witness.push(script);
+            witness.push(cb.serialize());
+        } else {
+            witness.push([]);
+            witness.push(self.redeem_script(stage)?);
+        }
+
+        let release_ctv = self.release_ctv(stage)?;
+        let output = release_ctv
+            .fields
+            .outputs
+            .iter()
+            .map(|output| match output {
+                Output::Address { address, amount } => Ok(TxOut {
+                    value: *amount,
+                    script_pubkey: address
+                        .clone()
+                        .require_network(self.network)?
+                        .script_pubkey(),
+                }),
+                _ => Err(anyhow!("Unexpected output in savings plan")),
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Transaction {
+            version: release_ctv.fields.version,
+            lock_time: release_ctv.fields.locktime,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+                witness,
+            }],
+            output,
+        })
+    }
+
+    fn stage_amount(&self, stage: usize) -> anyhow::Result<Amount> {
+        self.installment
+            .checked_add(RELEASE_FEE)
+            .and_then(|release| release.checked_mul((self.maturities.len() - stage) as u64))
+            .ok_or_else(|| anyhow!("Installments add up to more than 21 million BTC"))
+    }
+
+    fn stage_address(&self, stage: usize) -> anyhow::Result<Address<NetworkUnchecked>> {
+        let address = if self.taproot {
+            let tsi = self.stage_spend_info(stage)?;
+            Address::p2tr(SECP256K1, nums_points(), tsi.merkle_root(), self.network)
+        } else {
+            Address::p2wsh(&self.redeem_script(stage)?, self.network)
+        };
+        Ok(address.as_unchecked().clone())
+    }
+
+    fn redeem_script(&self, stage: usize) -> anyhow::Result<ScriptBuf> {
+        let release_hash = PushBytesBuf::try_from(self.release_ctv(stage)?.ctv()?)?;
+        Ok(bitcoin::script::Builder::new()
+            .push_opcode(OP_IF)
+            .push_key(&self.emergency_key)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ELSE)
+            .push_slice(release_hash)
+            .push_opcode(OP_NOP4)
+            .push_opcode(OP_ENDIF)
+            .into_script())
+    }
+
+    fn release_script(&self, stage: usize) -> anyhow::Result<ScriptBuf> {
+        let release_hash = PushBytesBuf::try_from(self.release_ctv(stage)?.ctv()?)?;
+        Ok(bitcoin::script::Builder::new()
+            .push_slice(release_hash)
+            .push_opcode(OP_NOP4)
+            .into_script())
+    }
+
+    fn stage_spend_info(&self, stage: usize) -> anyhow::Result<TaprootSpendInfo> {
+        TaprootBuilder::new()
+            .add_leaf(1, self.emergency_script(stage)?)?
+            .add_leaf(1, self.release_script(stage)?)?
+            .finalize(SECP256K1, nums_points())
+            .map_err(|_| anyhow!("Taproot not finalizable"))
+    }
+
+    fn release_ctv(&self, stage: usize) -> anyhow::Result<Context> {
+        let maturity = *self
+            .maturities
+            .get(stage)
+            .ok_or_else(|| anyhow!("Savings plan has no stage {stage}"))?;
+        let mut outputs = vec![Output::Address {
+            address: self.owner.clone(),
+            amount: self.installment,
+        }];
+        if stage + 1 < self.maturities.len() {
+            outputs.push(Output::Address {
+                address: self.stage_address(stage + 1)?,
+                amount: self.stage_amount(stage + 1)?,
+            });
+        }
+        Ok(Context {
+            network: self.network,
+            tx_type: self.tx_type(),
+            fields: Fields {
+                version: Version::TWO,
+                locktime: LockTime::from_time(maturity)?,
+                sequences: vec![Sequence::ENABLE_LOCKTIME_NO_RBF],
+                outputs,
+                input_idx: 0,
+            },
+        })
+    }
+
+    fn tx_type(&self) -> TxType {
+        if self.taproot {
+            return TxType::Taproot {
+                internal_key: nums_points(),
+            };
+        }
+        TxType::Segwit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, secp256k1::SecretKey};
+
+    use super::*;
+
+    fn plan(taproot: bool) -> SavingsPlan {
+        let secret = SecretKey::from_slice(&[1; 32]).unwrap();
+        let script = ScriptBuf::from_bytes(vec![2; 8]);
+        SavingsPlan {
+            network: Network::Regtest,
+            owner: Address::p2wsh(&script, Network::Regtest)
+                .as_unchecked()
+                .clone(),
+            emergency_key: PublicKey::new(secret.public_key(SECP256K1)),
+            installment: Amount::from_sat(50_000),
+            maturities: vec![1_767_225_600, 1_769_904_000, 1_772_323_200],
+            taproot,
+        }
+    }
+
+    #[test]
+    fn releases() {
+        for taproot in [false, true] {
+            let plan = plan(taproot);
+            plan.validate().unwrap();
+            assert_eq!(plan.amount().unwrap(), Amount::from_sat(3 * 50_600));
+
+            // Each release matches its template, and locks the rest into the next stage.
+            let releases = plan.releases(Txid::all_zeros(), 0).unwrap();
+            assert_eq!(releases.len(), 3);
+            for (stage, release) in releases.iter().enumerate() {
+                let previous = release.input[0].previous_output;
+                let template = &plan
+                    .release_ctv(stage)
+                    .unwrap()
+                    .spending_tx(previous.txid, previous.vout)
+                    .unwrap()[0];
+                assert_eq!(release.version, template.version);
+                assert_eq!(release.lock_time, template.lock_time);
+                assert_eq!(release.input[0].sequence, template.input[0].sequence);
+                assert_eq!(release.output, template.output);
+            }
+            assert_eq!(releases[2].output.len(), 1);
+        }
+    }
+
+    #[test]
+    fn rejects() {
+        let mut unordered = plan(false);
+        unordered.maturities.reverse();
+        assert!(unordered.validate().is_err());
+
+        let mut none = plan(false);
+        none.maturities.clear();
+        assert!(none.validate().is_err());
+
+        let mut overflow = plan(false);
+        overflow.installment = Amount::MAX;
+        assert!(overflow.amount().is_err());
+    }
+}
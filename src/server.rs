@@ -1,10 +1,33 @@
 use askama::Template;
-use axum::Router;
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Router,
+};
+use bitcoin::Transaction;
 
 use tokio::net::TcpListener;
 use tracing_subscriber::EnvFilter;
 
+use crate::{
+    backup::Backup,
+    diagram::{Diagram, Format},
+};
+
+mod custody;
+mod loan;
+mod mining;
+mod payroll;
+mod pegout;
+mod pool;
+mod savings;
 mod simple;
+mod spacechain;
+mod splitter;
+mod tickets;
+mod treasury;
+mod tree;
 mod vaults;
 
 pub async fn server() -> anyhow::Result<()> {
@@ -17,13 +40,89 @@ pub async fn server() -> anyhow::Result<()> {
         .route("/simple", axum::routing::get(simple::index))
         .route("/simple/locking", axum::routing::post(simple::locking))
         .route("/simple/spending", axum::routing::post(simple::spending))
+        .route("/simple/package", axum::routing::post(simple::package))
+        .route("/simple/backup", axum::routing::post(simple::backup))
+        .route("/simple/diagram", axum::routing::post(simple::diagram))
+        .route("/simple/summary", axum::routing::post(simple::summary))
+        .route("/simple/fees", axum::routing::post(simple::fees))
+        .route("/ctv/tree", axum::routing::get(tree::index))
+        .route("/ctv/tree/building", axum::routing::post(tree::building))
+        .route("/ctv/tree/spending", axum::routing::post(tree::spending))
+        .route("/ctv/tree/package", axum::routing::post(tree::package))
         .route("/vaults", axum::routing::get(vaults::index))
         .route("/vaults/vaulting", axum::routing::post(vaults::vaulting))
+        .route("/vaults/diagram", axum::routing::post(vaults::diagram))
+        .route("/vaults/backup", axum::routing::post(vaults::backup))
+        .route("/vaults/summary", axum::routing::post(vaults::summary))
         .route(
             "/vaults/unvaulting",
             axum::routing::post(vaults::unvaulting),
         )
-        .route("/vaults/spending", axum::routing::post(vaults::spending));
+        .route("/vaults/spending", axum::routing::post(vaults::spending))
+        .route("/vaults/state", axum::routing::post(vaults::state))
+        .route("/pool", axum::routing::get(pool::index))
+        .route("/pool/creating", axum::routing::post(pool::creating))
+        .route("/pool/funded", axum::routing::post(pool::funded))
+        .route("/pool/splicing", axum::routing::post(pool::splicing))
+        .route("/spacechain", axum::routing::get(spacechain::index))
+        .route(
+            "/spacechain/creating",
+            axum::routing::post(spacechain::creating),
+        )
+        .route(
+            "/spacechain/funded",
+            axum::routing::post(spacechain::funded),
+        )
+        .route(
+            "/spacechain/status",
+            axum::routing::post(spacechain::status),
+        )
+        .route(
+            "/spacechain/committing",
+            axum::routing::post(spacechain::committing),
+        )
+        .route("/payroll", axum::routing::get(payroll::index))
+        .route("/payroll/creating", axum::routing::post(payroll::creating))
+        .route("/payroll/spending", axum::routing::post(payroll::spending))
+        .route("/mining", axum::routing::get(mining::index))
+        .route("/mining/creating", axum::routing::post(mining::creating))
+        .route("/mining/spending", axum::routing::post(mining::spending))
+        .route("/mining/package", axum::routing::post(mining::package))
+        .route("/mining/fees", axum::routing::post(mining::fees))
+        .route("/custody", axum::routing::get(custody::index))
+        .route("/custody/creating", axum::routing::post(custody::creating))
+        .route("/custody/spending", axum::routing::post(custody::spending))
+        .route("/treasury", axum::routing::get(treasury::index))
+        .route(
+            "/treasury/creating",
+            axum::routing::post(treasury::creating),
+        )
+        .route(
+            "/treasury/spending",
+            axum::routing::post(treasury::spending),
+        )
+        .route("/savings", axum::routing::get(savings::index))
+        .route("/savings/creating", axum::routing::post(savings::creating))
+        .route("/savings/spending", axum::routing::post(savings::spending))
+        .route("/splitter", axum::routing::get(splitter::index))
+        .route(
+            "/splitter/creating",
+            axum::routing::post(splitter::creating),
+        )
+        .route(
+            "/splitter/spending",
+            axum::routing::post(splitter::spending),
+        )
+        .route("/tickets", axum::routing::get(tickets::index))
+        .route("/tickets/creating", axum::routing::post(tickets::creating))
+        .route("/tickets/spending", axum::routing::post(tickets::spending))
+        .route("/loan", axum::routing::get(loan::index))
+        .route("/loan/creating", axum::routing::post(loan::creating))
+        .route("/loan/funded", axum::routing::post(loan::funded))
+        .route("/loan/repaying", axum::routing::post(loan::repaying))
+        .route("/pegout", axum::routing::get(pegout::index))
+        .route("/pegout/creating", axum::routing::post(pegout::creating))
+        .route("/pegout/spending", axum::routing::post(pegout::spending));
     let listener = TcpListener::bind("localhost:5555").await?;
 
     tracing::info!("Starting server on localhost:5555");
@@ -38,3 +137,88 @@ struct IndexTemplate;
 async fn index() -> IndexTemplate {
     IndexTemplate
 }
+
+#[derive(Template)]
+#[template(path = "backup.html.jinja")]
+pub(crate) struct BackupTemplate {
+    pub(crate) backup: Backup,
+}
+
+#[derive(Template)]
+#[template(path = "error.html.jinja")]
+pub struct ErrorTemplate {
+    message: String,
+}
+
+pub struct AppError(anyhow::Error);
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> askama_axum::Response {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorTemplate {
+                message: self.0.to_string(),
+            }
+            .into_response(),
+        )
+            .into_response()
+    }
+}
+
+impl<E> From<E> for AppError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        Self(err.into())
+    }
+}
+
+/// Download a spend package as a text file, streaming it one transaction at a time, so that the
+/// hex of a large tree is never held in memory all at once.
+pub(crate) fn package(txs: Vec<Transaction>, filename: &str) -> Response {
+    let lines = futures_util::stream::iter(txs.into_iter().map(|tx| {
+        let mut line = Vec::with_capacity(tx.total_size() * 2 + 1);
+        crate::package::write_tx(&tx, &mut line)?;
+        Ok::<_, std::io::Error>(line)
+    }));
+    download(Body::from_stream(lines), "text/plain", filename)
+}
+
+/// Send a file for the browser to save rather than display.
+pub(crate) fn download(body: impl Into<Body>, content_type: &str, filename: &str) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body.into(),
+    )
+        .into_response()
+}
+
+/// Send a contract diagram. SVG is shown in the browser, and the text formats are downloaded.
+pub(crate) fn diagram(diagram: &Diagram, format: Format, name: &str) -> Response {
+    let body = diagram.render(format);
+    match format {
+        Format::Svg => ([(header::CONTENT_TYPE, format.content_type())], body).into_response(),
+        _ => download(
+            body,
+            format.content_type(),
+            &format!("{name}.{}", format.extension()),
+        ),
+    }
+}
+
+/// Run a CPU-heavy contract build on the blocking thread pool, so that hashing or unrolling a large
+/// tree doesn't stall every other request on the runtime.
+pub(crate) async fn blocking<T, F>(build: F) -> Result<T, AppError>
+where
+    F: FnOnce() -> Result<T, AppError> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(build).await?
+}
@@ -0,0 +1,127 @@
+use askama::Template;
+use axum::Form;
+use bitcoin::{
+    address::{NetworkChecked, NetworkUnchecked},
+    Address, Amount, Network, PublicKey, Txid,
+};
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::{
+    custody::{Custody, Party},
+    explorer,
+    server::AppError,
+    util, validate,
+};
+
+// SETTING UP CUSTODY
+// -------------------
+
+#[derive(Template)]
+#[template(path = "custody/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+pub(crate) struct CreatingRequest {
+    first_key: PublicKey,
+    first_address: Address<NetworkUnchecked>,
+    #[serde_as(as = "util::AmountWithUnits")]
+    first_share: Amount,
+    second_key: PublicKey,
+    second_address: Address<NetworkUnchecked>,
+    #[serde_as(as = "util::AmountWithUnits")]
+    second_share: Amount,
+    timeout: u16,
+    network: Network,
+    taproot: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+impl From<CreatingRequest> for Custody {
+    fn from(value: CreatingRequest) -> Self {
+        Custody {
+            network: value.network,
+            first: Party {
+                key: value.first_key,
+                address: value.first_address,
+                share: value.first_share,
+            },
+            second: Party {
+                key: value.second_key,
+                address: value.second_address,
+                share: value.second_share,
+            },
+            timeout: value.timeout,
+            taproot: value.taproot.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "custody/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    custody: String,
+    address: Address<NetworkChecked>,
+    address_url: Option<String>,
+    amount: Amount,
+    script: String,
+    control_block: Option<String>,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    let used = validate::parse_used(&request.used_addresses)?;
+    let custody: Custody = request.into();
+    let mut warnings = custody.validate()?;
+    let address = custody.address()?.require_network(custody.network)?;
+    warnings.extend(validate::check_reuse(
+        "custody",
+        std::slice::from_ref(&address),
+        &used,
+    ));
+    Ok(CreatingTemplate {
+        custody: serde_json::to_string(&custody)?,
+        address_url: explorer::address_url(&address),
+        address,
+        amount: custody.amount()?,
+        script: util::colorize(&custody.cooperative_script()?.to_string()),
+        control_block: custody.cooperative_control_block()?.map(hex::encode),
+        warnings,
+    })
+}
+
+// UNILATERAL RECOVERY
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct SpendingRequest {
+    custody: String,
+    txid: Txid,
+    vout: u32,
+}
+
+#[derive(Template)]
+#[template(path = "custody/spending.html.jinja")]
+pub(crate) struct SpendingTemplate {
+    timeout: u16,
+    tx: String,
+}
+
+pub(crate) async fn spending(
+    Form(request): Form<SpendingRequest>,
+) -> anyhow::Result<SpendingTemplate, AppError> {
+    let custody: Custody = serde_json::from_str(&request.custody)?;
+    let tx = custody.timeout_spend(request.txid, request.vout)?;
+    Ok(SpendingTemplate {
+        timeout: custody.timeout,
+        tx: hex::encode(bitcoin::consensus::serialize(&tx)),
+    })
+}
@@ -0,0 +1,147 @@
+use askama::Template;
+use axum::Form;
+use bitcoin::{
+    address::{NetworkChecked, NetworkUnchecked},
+    hashes::sha256,
+    Address, Amount, Network, OutPoint, Txid,
+};
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::{
+    explorer,
+    loan::{BorrowerKit, Loan},
+    server::AppError,
+    util, validate,
+};
+
+// SETTING UP THE LOAN
+// -------------------
+
+#[derive(Template)]
+#[template(path = "loan/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreatingRequest {
+    borrower: Address<NetworkUnchecked>,
+    lender: Address<NetworkUnchecked>,
+    #[serde_as(as = "util::AmountWithUnits")]
+    principal: Amount,
+    #[serde_as(as = "util::AmountWithUnits")]
+    collateral: Amount,
+    due_date: String,
+    repayment_hash: sha256::Hash,
+    network: Network,
+    taproot: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+#[derive(Template)]
+#[template(path = "loan/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    loan: String,
+    address: Address<NetworkChecked>,
+    address_url: Option<String>,
+    collateral: Amount,
+    due_date: String,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    tracing::debug!("{request:?}");
+    let used = validate::parse_used(&request.used_addresses)?;
+    let loan = Loan {
+        network: request.network,
+        borrower: request.borrower,
+        lender: request.lender,
+        principal: request.principal,
+        collateral: request.collateral,
+        due_date: util::parse_date(&request.due_date)?,
+        repayment_hash: request.repayment_hash,
+        taproot: request.taproot.unwrap_or_default(),
+    };
+    let mut warnings = loan.validate()?;
+    let address = loan.address()?.require_network(loan.network)?;
+    warnings.extend(validate::check_reuse(
+        "loan",
+        std::slice::from_ref(&address),
+        &used,
+    ));
+    Ok(CreatingTemplate {
+        loan: serde_json::to_string(&loan)?,
+        address_url: explorer::address_url(&address),
+        address,
+        collateral: loan.collateral,
+        due_date: loan.due_date(),
+        warnings,
+    })
+}
+
+// EXPORTING THE KITS
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct FundedRequest {
+    loan: String,
+    txid: Txid,
+    vout: u32,
+}
+
+#[derive(Template)]
+#[template(path = "loan/funded.html.jinja")]
+pub(crate) struct FundedTemplate {
+    due_date: String,
+    borrower_kit: String,
+    lender_kit: String,
+}
+
+pub(crate) async fn funded(
+    Form(request): Form<FundedRequest>,
+) -> anyhow::Result<FundedTemplate, AppError> {
+    let loan: Loan = serde_json::from_str(&request.loan)?;
+    let (borrower_kit, lender_kit) = loan.kits(OutPoint {
+        txid: request.txid,
+        vout: request.vout,
+    })?;
+    Ok(FundedTemplate {
+        due_date: loan.due_date(),
+        borrower_kit: serde_json::to_string_pretty(&borrower_kit)?,
+        lender_kit: serde_json::to_string_pretty(&lender_kit)?,
+    })
+}
+
+// RETURNING THE COLLATERAL
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct RepayingRequest {
+    kit: String,
+    secret: String,
+}
+
+#[derive(Template)]
+#[template(path = "loan/repaying.html.jinja")]
+pub(crate) struct RepayingTemplate {
+    tx: String,
+}
+
+pub(crate) async fn repaying(
+    Form(request): Form<RepayingRequest>,
+) -> anyhow::Result<RepayingTemplate, AppError> {
+    let kit: BorrowerKit = serde_json::from_str(&request.kit)?;
+    let tx = kit
+        .loan
+        .return_tx(kit.outpoint, &hex::decode(request.secret.trim())?)?;
+    Ok(RepayingTemplate {
+        tx: hex::encode(bitcoin::consensus::serialize(&tx)),
+    })
+}
@@ -0,0 +1,196 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use askama::Template;
+use axum::{response::Response, Form, Json};
+use bitcoin::{Address, Amount, FeeRate, Network, Txid};
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::{
+    explorer,
+    mining::{Payout, PayoutRound, Share},
+    server::{self, AppError},
+    util,
+    validate::{self, Footprint, TxCost},
+};
+
+// IMPORT A ROUND
+// -------------------
+
+#[derive(Template)]
+#[template(path = "mining/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreatingRequest {
+    round: u32,
+    #[serde_as(as = "util::AmountWithUnits")]
+    reward: Amount,
+    shares: String,
+    fee_rate: u64,
+    reject_uneconomical: Option<bool>,
+    network: Network,
+    taproot: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+#[derive(Template)]
+#[template(path = "mining/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    payout_round: String,
+    round: u32,
+    address: Address,
+    address_url: Option<String>,
+    reward: Amount,
+    payouts: Vec<Payout>,
+    footprint: Footprint,
+    fees: Vec<TxCost>,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    server::blocking(move || {
+        tracing::debug!("{request:?}");
+        let used = validate::parse_used(&request.used_addresses)?;
+        let payout_round = PayoutRound {
+            network: request.network,
+            round: request.round,
+            reward: request.reward,
+            shares: parse_shares(&request.shares)?,
+            taproot: request.taproot.unwrap_or_default(),
+        };
+        let mut warnings = payout_round.validate()?;
+        let fee_rate = FeeRate::from_sat_per_vb(request.fee_rate)
+            .ok_or_else(|| anyhow!("Fee rate {} sat/vB is too high", request.fee_rate))?;
+        warnings.extend(
+            payout_round
+                .check_unroll_costs(fee_rate, request.reject_uneconomical.unwrap_or_default())?,
+        );
+        let address = payout_round
+            .address()?
+            .require_network(payout_round.network)?;
+        warnings.extend(validate::check_reuse(
+            "mining payout",
+            std::slice::from_ref(&address),
+            &used,
+        ));
+        Ok(CreatingTemplate {
+            payout_round: serde_json::to_string(&payout_round)?,
+            round: payout_round.round,
+            address_url: explorer::address_url(&address),
+            address,
+            reward: payout_round.reward,
+            payouts: payout_round.payouts()?,
+            footprint: payout_round.footprint()?,
+            fees: payout_round.fee_report()?,
+            warnings,
+        })
+    })
+    .await
+}
+
+fn parse_shares(shares: &str) -> anyhow::Result<Vec<Share>> {
+    let mut parsed = Vec::new();
+    for line in shares.lines().filter(|l| !l.trim().is_empty()) {
+        let mut splitter = line.trim().split(':');
+        let address =
+            Address::from_str(splitter.next().ok_or_else(|| anyhow!("Missing address"))?)?;
+        let shares = splitter
+            .next()
+            .ok_or_else(|| anyhow!("Missing shares"))?
+            .trim()
+            .parse()?;
+        parsed.push(Share { address, shares });
+    }
+    Ok(parsed)
+}
+
+// PAYING OUT
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct SpendingRequest {
+    payout_round: String,
+    txid: Txid,
+    vout: u32,
+}
+
+pub(crate) struct RedemptionPath {
+    address: String,
+    amount: Amount,
+    txs: Vec<String>,
+}
+
+#[derive(Template)]
+#[template(path = "mining/spending.html.jinja")]
+pub(crate) struct SpendingTemplate {
+    round: u32,
+    txs: Vec<String>,
+    paths: Vec<RedemptionPath>,
+}
+
+pub(crate) async fn spending(
+    Form(request): Form<SpendingRequest>,
+) -> anyhow::Result<SpendingTemplate, AppError> {
+    server::blocking(move || {
+        let payout_round: PayoutRound = serde_json::from_str(&request.payout_round)?;
+        let txs = payout_round.spending_txs(request.txid, request.vout)?;
+        let paths = payout_round
+            .payouts()?
+            .into_iter()
+            .enumerate()
+            .map(|(idx, payout)| {
+                let path = payout_round.redemption_path(idx, request.txid, request.vout)?;
+                Ok(RedemptionPath {
+                    address: payout.address.assume_checked().to_string(),
+                    amount: payout.amount,
+                    txs: path.iter().map(serialize_tx).collect(),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(SpendingTemplate {
+            round: payout_round.round,
+            txs: txs.iter().map(serialize_tx).collect(),
+            paths,
+        })
+    })
+    .await
+}
+
+fn serialize_tx(tx: &bitcoin::Transaction) -> String {
+    hex::encode(bitcoin::consensus::serialize(tx))
+}
+
+pub(crate) async fn package(Form(request): Form<SpendingRequest>) -> Result<Response, AppError> {
+    server::blocking(move || {
+        let payout_round: PayoutRound = serde_json::from_str(&request.payout_round)?;
+        let txs = payout_round.spending_txs(request.txid, request.vout)?;
+        Ok(server::package(
+            txs,
+            &format!("round-{}.txt", payout_round.round),
+        ))
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FeesRequest {
+    payout_round: String,
+}
+
+pub(crate) async fn fees(Form(request): Form<FeesRequest>) -> Result<Json<Vec<TxCost>>, AppError> {
+    server::blocking(move || {
+        let payout_round: PayoutRound = serde_json::from_str(&request.payout_round)?;
+        Ok(Json(payout_round.fee_report()?))
+    })
+    .await
+}
@@ -0,0 +1,119 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use askama::Template;
+use axum::Form;
+use bitcoin::{Address, Network, Txid};
+use serde::Deserialize;
+
+use crate::{
+    payroll::{Employee, Payroll, Period},
+    server::AppError,
+    util, validate,
+};
+
+// CREATE A PAYROLL
+// -------------------
+
+#[derive(Template)]
+#[template(path = "payroll/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreatingRequest {
+    employees: String,
+    periods: u32,
+    start_height: u32,
+    interval: u32,
+    network: Network,
+    taproot: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+#[derive(Template)]
+#[template(path = "payroll/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    payroll: String,
+    periods: Vec<Period>,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    tracing::debug!("{request:?}");
+    let used = validate::parse_used(&request.used_addresses)?;
+    let payroll = Payroll {
+        network: request.network,
+        employees: parse_employees(&request.employees)?,
+        periods: request.periods,
+        start_height: request.start_height,
+        interval: request.interval,
+        taproot: request.taproot.unwrap_or_default(),
+    };
+    let mut warnings = payroll.validate()?;
+    let periods = payroll.periods()?;
+    let addresses = periods
+        .iter()
+        .map(|period| period.address.clone().require_network(payroll.network))
+        .collect::<Result<Vec<_>, _>>()?;
+    warnings.extend(validate::check_reuse("payroll", &addresses, &used));
+    Ok(CreatingTemplate {
+        payroll: serde_json::to_string(&payroll)?,
+        periods,
+        warnings,
+    })
+}
+
+fn parse_employees(employees: &str) -> anyhow::Result<Vec<Employee>> {
+    let mut parsed = Vec::new();
+    for line in employees.lines().filter(|l| !l.trim().is_empty()) {
+        let mut splitter = line.trim().split(':');
+        let address =
+            Address::from_str(splitter.next().ok_or_else(|| anyhow!("Missing address"))?)?;
+        let amount = util::parse_amount(splitter.next().ok_or_else(|| anyhow!("Missing amount"))?)?;
+        parsed.push(Employee { address, amount });
+    }
+    Ok(parsed)
+}
+
+// PAYING A PERIOD
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct SpendingRequest {
+    payroll: String,
+    period: u32,
+    txid: Txid,
+    vout: u32,
+}
+
+#[derive(Template)]
+#[template(path = "payroll/spending.html.jinja")]
+pub(crate) struct SpendingTemplate {
+    payroll: String,
+    period: u32,
+    height: u32,
+    tx: String,
+    next_period: Option<u32>,
+}
+
+pub(crate) async fn spending(
+    Form(request): Form<SpendingRequest>,
+) -> anyhow::Result<SpendingTemplate, AppError> {
+    let payroll: Payroll = serde_json::from_str(&request.payroll)?;
+    let tx = payroll.spending_tx(request.period, request.txid, request.vout)?;
+    let next_period = Some(request.period + 1).filter(|p| *p < payroll.periods);
+    Ok(SpendingTemplate {
+        payroll: request.payroll,
+        period: request.period,
+        height: payroll.height(request.period)?,
+        tx: hex::encode(bitcoin::consensus::serialize(&tx)),
+        next_period,
+    })
+}
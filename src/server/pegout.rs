@@ -0,0 +1,133 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use askama::Template;
+use axum::Form;
+use bitcoin::{address::NetworkChecked, Address, Amount, Network, Txid};
+use serde::Deserialize;
+
+use crate::{
+    explorer,
+    pegout::{PegoutBatch, Withdrawal},
+    server::AppError,
+    util, validate,
+};
+
+// COMMITTING TO A BATCH
+// -------------------
+
+#[derive(Template)]
+#[template(path = "pegout/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreatingRequest {
+    withdrawals: String,
+    network: Network,
+    taproot: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+pub(crate) struct UserInclusion {
+    address: Address<NetworkChecked>,
+    amount: Amount,
+    inclusion: String,
+}
+
+#[derive(Template)]
+#[template(path = "pegout/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    batch: String,
+    address: Address<NetworkChecked>,
+    address_url: Option<String>,
+    amount: Amount,
+    template_hash: String,
+    inclusions: Vec<UserInclusion>,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    tracing::debug!("{request:?}");
+    let used = validate::parse_used(&request.used_addresses)?;
+    let batch = PegoutBatch {
+        network: request.network,
+        withdrawals: parse_withdrawals(&request.withdrawals)?,
+        taproot: request.taproot.unwrap_or_default(),
+    };
+    let mut warnings = batch.validate()?;
+    let address = batch.address()?.require_network(batch.network)?;
+    warnings.extend(validate::check_reuse(
+        "peg-out",
+        std::slice::from_ref(&address),
+        &used,
+    ));
+    let inclusions = batch
+        .inclusions()?
+        .into_iter()
+        .map(|inclusion| {
+            Ok(UserInclusion {
+                address: inclusion
+                    .withdrawal
+                    .address
+                    .clone()
+                    .require_network(batch.network)?,
+                amount: inclusion.withdrawal.amount,
+                inclusion: serde_json::to_string(&inclusion)?,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(CreatingTemplate {
+        batch: serde_json::to_string(&batch)?,
+        address_url: explorer::address_url(&address),
+        address,
+        amount: batch.amount()?,
+        template_hash: batch.template_hash()?,
+        inclusions,
+        warnings,
+    })
+}
+
+fn parse_withdrawals(withdrawals: &str) -> anyhow::Result<Vec<Withdrawal>> {
+    let mut parsed = Vec::new();
+    for line in withdrawals.lines().filter(|l| !l.trim().is_empty()) {
+        let mut splitter = line.trim().split(':');
+        let address =
+            Address::from_str(splitter.next().ok_or_else(|| anyhow!("Missing address"))?)?;
+        let amount = util::parse_amount(splitter.next().ok_or_else(|| anyhow!("Missing amount"))?)?;
+        parsed.push(Withdrawal { address, amount });
+    }
+    Ok(parsed)
+}
+
+// BROADCASTING THE BATCH
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct SpendingRequest {
+    batch: String,
+    txid: Txid,
+    vout: u32,
+}
+
+#[derive(Template)]
+#[template(path = "pegout/spending.html.jinja")]
+pub(crate) struct SpendingTemplate {
+    tx: String,
+}
+
+pub(crate) async fn spending(
+    Form(request): Form<SpendingRequest>,
+) -> anyhow::Result<SpendingTemplate, AppError> {
+    let batch: PegoutBatch = serde_json::from_str(&request.batch)?;
+    let tx = batch.batch_tx(request.txid, request.vout)?;
+    Ok(SpendingTemplate {
+        tx: hex::encode(bitcoin::consensus::serialize(&tx)),
+    })
+}
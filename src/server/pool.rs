@@ -0,0 +1,183 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use askama::Template;
+use axum::Form;
+use bitcoin::{Address, Amount, Network, OutPoint, Txid, XOnlyPublicKey};
+use serde::Deserialize;
+
+use crate::{
+    explorer,
+    pool::{Member, Pool},
+    server::AppError,
+    util, validate,
+};
+
+// CREATE A POOL
+// -------------------
+
+#[derive(Template)]
+#[template(path = "pool/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreatingRequest {
+    members: String,
+    network: Network,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+#[derive(Template)]
+#[template(path = "pool/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    pool: String,
+    address: Address,
+    address_url: Option<String>,
+    amount: Amount,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    tracing::debug!("{request:?}");
+    let used = validate::parse_used(&request.used_addresses)?;
+    let members = parse_members(&request.members)?;
+    let pool = Pool::new(request.network, members)?;
+    let mut warnings = pool.validate()?;
+    let address = pool.address()?.require_network(pool.network)?;
+    warnings.extend(validate::check_reuse(
+        "pool",
+        std::slice::from_ref(&address),
+        &used,
+    ));
+    Ok(CreatingTemplate {
+        pool: serde_json::to_string(&pool)?,
+        address_url: explorer::address_url(&address),
+        address,
+        amount: pool.amount()?,
+        warnings,
+    })
+}
+
+fn parse_members(members: &str) -> anyhow::Result<Vec<Member>> {
+    let mut parsed = Vec::new();
+    for line in members.lines().filter(|l| !l.trim().is_empty()) {
+        let mut splitter = line.trim().split(':');
+        let key = XOnlyPublicKey::from_str(splitter.next().ok_or_else(|| anyhow!("Missing key"))?)?;
+        let address =
+            Address::from_str(splitter.next().ok_or_else(|| anyhow!("Missing address"))?)?;
+        let amount = util::parse_amount(splitter.next().ok_or_else(|| anyhow!("Missing amount"))?)?;
+        parsed.push(Member {
+            key,
+            address,
+            amount,
+        });
+    }
+    Ok(parsed)
+}
+
+// POOL STATUS
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct FundedRequest {
+    pool: String,
+    txid: Txid,
+    vout: u32,
+}
+
+#[derive(Template)]
+#[template(path = "pool/status.html.jinja")]
+pub(crate) struct StatusTemplate {
+    pool: String,
+    version: u32,
+    outpoint: OutPoint,
+    tx_url: Option<String>,
+    members: Vec<Member>,
+    exit_tx: String,
+}
+
+pub(crate) async fn funded(
+    Form(request): Form<FundedRequest>,
+) -> anyhow::Result<StatusTemplate, AppError> {
+    let mut pool: Pool = serde_json::from_str(&request.pool)?;
+    let outpoint = OutPoint {
+        txid: request.txid,
+        vout: request.vout,
+    };
+    pool.outpoint = Some(outpoint);
+    let exit_tx = pool.unilateral_exit()?;
+    Ok(StatusTemplate {
+        pool: serde_json::to_string(&pool)?,
+        version: pool.version,
+        outpoint,
+        tx_url: explorer::tx_url(pool.network, &outpoint.txid),
+        members: pool.members,
+        exit_tx: hex::encode(bitcoin::consensus::serialize(&exit_tx)),
+    })
+}
+
+// COOPERATIVE EXIT
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct SplicingRequest {
+    pool: String,
+    #[serde(default)]
+    exits: Vec<usize>,
+}
+
+#[derive(Template)]
+#[template(path = "pool/splicing.html.jinja")]
+pub(crate) struct SplicingTemplate {
+    leavers: Vec<Member>,
+    tx: String,
+    script: String,
+    control_block: String,
+    next: Option<NextVersion>,
+}
+
+pub(crate) struct NextVersion {
+    pool: String,
+    version: u32,
+    outpoint: OutPoint,
+}
+
+pub(crate) async fn splicing(
+    axum_extra::extract::Form(request): axum_extra::extract::Form<SplicingRequest>,
+) -> anyhow::Result<SplicingTemplate, AppError> {
+    let pool: Pool = serde_json::from_str(&request.pool)?;
+    let (tx, next) = pool.splice_out(&request.exits)?;
+    // Without a next version, everyone was paid out, not only the members who asked to leave.
+    let leavers = match next {
+        Some(_) => request
+            .exits
+            .iter()
+            .filter_map(|idx| pool.members.get(*idx).cloned())
+            .collect(),
+        None => pool.members.clone(),
+    };
+    let next = match next {
+        Some(next) => Some(NextVersion {
+            pool: serde_json::to_string(&next)?,
+            version: next.version,
+            outpoint: next
+                .outpoint
+                .ok_or_else(|| anyhow!("Next pool version has no outpoint"))?,
+        }),
+        None => None,
+    };
+    Ok(SplicingTemplate {
+        leavers,
+        tx: hex::encode(bitcoin::consensus::serialize(&tx)),
+        script: util::colorize(&pool.cooperative_script().to_string()),
+        control_block: hex::encode(pool.cooperative_control_block()?),
+        next,
+    })
+}
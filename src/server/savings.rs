@@ -0,0 +1,125 @@
+use askama::Template;
+use axum::Form;
+use bitcoin::{
+    address::{NetworkChecked, NetworkUnchecked},
+    Address, Amount, Network, PublicKey, Txid,
+};
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::{
+    explorer,
+    savings::{Maturity, SavingsPlan},
+    server::AppError,
+    util, validate,
+};
+
+// CREATING A PLAN
+// -------------------
+
+#[derive(Template)]
+#[template(path = "savings/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+pub(crate) struct CreatingRequest {
+    owner: Address<NetworkUnchecked>,
+    emergency_key: PublicKey,
+    #[serde_as(as = "util::AmountWithUnits")]
+    installment: Amount,
+    dates: String,
+    network: Network,
+    taproot: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+#[derive(Template)]
+#[template(path = "savings/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    plan: String,
+    address: Address<NetworkChecked>,
+    address_url: Option<String>,
+    amount: Amount,
+    calendar: Vec<Maturity>,
+    emergency_script: String,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    let used = validate::parse_used(&request.used_addresses)?;
+    let maturities = request
+        .dates
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(util::parse_date)
+        .collect::<anyhow::Result<_>>()?;
+    let plan = SavingsPlan {
+        network: request.network,
+        owner: request.owner,
+        emergency_key: request.emergency_key,
+        installment: request.installment,
+        maturities,
+        taproot: request.taproot.unwrap_or_default(),
+    };
+    let mut warnings = plan.validate()?;
+    let address = plan.address()?.require_network(plan.network)?;
+    warnings.extend(validate::check_reuse(
+        "savings",
+        std::slice::from_ref(&address),
+        &used,
+    ));
+    Ok(CreatingTemplate {
+        plan: serde_json::to_string(&plan)?,
+        address_url: explorer::address_url(&address),
+        address,
+        amount: plan.amount()?,
+        calendar: plan.calendar()?,
+        emergency_script: util::colorize(&plan.emergency_script(0)?.to_string()),
+        warnings,
+    })
+}
+
+// RELEASING INSTALLMENTS
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct SpendingRequest {
+    plan: String,
+    txid: Txid,
+    vout: u32,
+}
+
+pub(crate) struct Release {
+    maturity: Maturity,
+    tx: String,
+}
+
+#[derive(Template)]
+#[template(path = "savings/spending.html.jinja")]
+pub(crate) struct SpendingTemplate {
+    releases: Vec<Release>,
+}
+
+pub(crate) async fn spending(
+    Form(request): Form<SpendingRequest>,
+) -> anyhow::Result<SpendingTemplate, AppError> {
+    let plan: SavingsPlan = serde_json::from_str(&request.plan)?;
+    let releases = plan
+        .calendar()?
+        .into_iter()
+        .zip(plan.releases(request.txid, request.vout)?)
+        .map(|(maturity, tx)| Release {
+            maturity,
+            tx: hex::encode(bitcoin::consensus::serialize(&tx)),
+        })
+        .collect();
+    Ok(SpendingTemplate { releases })
+}
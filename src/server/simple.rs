@@ -1,16 +1,34 @@
-use std::str::FromStr;
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::anyhow;
 use askama::Template;
-use axum::Form;
+use axum::{response::Response, Form, Json};
 use bitcoin::{
-    absolute::LockTime, transaction::Version, Address, Amount, Network, Sequence, Txid,
-    XOnlyPublicKey,
+    absolute::LockTime,
+    hashes::{sha256, Hash},
+    transaction::Version,
+    Address, Amount, FeeRate, Network, OutPoint, Sequence, TxOut, Txid, XOnlyPublicKey,
 };
 use ctvlib::{Context, Fields, Output, TxType};
 use serde::Deserialize;
+use serde_with::{serde_as, NoneAsEmptyString};
+
+use crate::{
+    backup::Backup,
+    diagram::{Diagram, Format},
+    error::CdvError,
+    explorer, psbt,
+    server::{self, AppError, BackupTemplate},
+    summary,
+    util::{self, Highlight, OutputOrder},
+    validate::{self, Footprint, Scenario, TxCost},
+};
 
-use crate::{error::AppError, util};
+/// Fee prepaid out of each payout, unless the template has a fee rate or is TRUC.
+const OUTPUT_FEE: Amount = Amount::from_sat(600);
 
 #[derive(Template)]
 #[template(path = "simple/index.html.jinja")]
@@ -27,104 +45,310 @@ pub(crate) struct ContextTemplate {
     locking_script: String,
     locking_hex: String,
     address: String,
+    address_url: Option<String>,
     ctv: String,
+    summary: String,
+    footprint: Footprint,
+    fees: Vec<TxCost>,
+    scenarios: Vec<Scenario>,
+    warnings: Vec<String>,
+    fee_rate: Option<u64>,
 }
 
+#[serde_as]
 #[derive(Debug, Deserialize)]
 pub(crate) struct LockingRequest {
     outputs: String,
     network: Network,
     congestion: Option<bool>,
     taproot: Option<bool>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    internal_key: Option<XOnlyPublicKey>,
+    truc: Option<bool>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    fee_rate: Option<u64>,
+    unique: Option<bool>,
+    large_data: Option<bool>,
+    #[serde(default)]
+    output_order: OutputOrder,
+    #[serde(default)]
+    used_addresses: String,
 }
 
 pub(crate) async fn locking(
     Form(request): Form<LockingRequest>,
 ) -> Result<ContextTemplate, AppError> {
-    tracing::info!("Locking started.");
-    tracing::debug!("{request:?}");
-    let ctv = extract_ctv_from_request(&request)?;
-
-    let ctvhash = ctv.ctv()?;
-    let locking_script = ctv.locking_script()?;
-    let address = ctv.address()?;
-
-    tracing::info!("Locking finished.");
-    Ok(ContextTemplate {
-        ctv_hash: hex::encode(ctvhash),
-        locking_script: util::colorize(&locking_script.to_string()),
-        locking_hex: hex::encode(locking_script.into_bytes()),
-        address: address.to_string(),
-        ctv: serde_json::to_string(&ctv)?,
+    server::blocking(move || {
+        tracing::info!("Locking started.");
+        tracing::debug!("{request:?}");
+        let used = validate::parse_used(&request.used_addresses)?;
+        let ctv = extract_ctv_from_request(&request)?;
+        let congestion = request.congestion.unwrap_or_default();
+        let (mut warnings, footprint) = check_ctv(&ctv, congestion, request.fee_rate)?;
+        let (paid, fee) = funding(&ctv, congestion, request.fee_rate)?;
+        let fees = validate::fee_report(&ctv, paid + fee)?;
+        let scenarios = validate::fee_scenarios(&validate::tree_leaves(&ctv)?)?;
+
+        let ctvhash = ctv.ctv()?;
+        let locking_script = ctv.locking_script()?;
+        tracing::debug!(
+            "Locking script: {}",
+            util::highlight(&locking_script.to_string(), Highlight::Ansi)
+        );
+        let address = ctv.address()?;
+        warnings.extend(validate::check_reuse(
+            "simple",
+            std::slice::from_ref(&address),
+            &used,
+        ));
+        if let (Some(true), Some(key)) = (request.taproot, request.internal_key) {
+            warnings.push(validate::key_path_warning(&key));
+        }
+
+        tracing::info!("Locking finished.");
+        Ok(ContextTemplate {
+            ctv_hash: hex::encode(ctvhash),
+            locking_script: util::colorize(&locking_script.to_string()),
+            locking_hex: hex::encode(locking_script.into_bytes()),
+            address_url: explorer::address_url(&address),
+            address: address.to_string(),
+            ctv: serde_json::to_string(&ctv)?,
+            summary: summary::ctv(&ctv)?,
+            footprint,
+            fees,
+            scenarios,
+            warnings,
+            fee_rate: request.fee_rate,
+        })
+    })
+    .await
+}
+
+fn check_ctv(
+    ctv: &Context,
+    congestion: bool,
+    fee_rate: Option<u64>,
+) -> anyhow::Result<(Vec<String>, Footprint)> {
+    let (paid, fee) = funding(ctv, congestion, fee_rate)?;
+    let footprint = validate::measure_tree(ctv, fee)?;
+    Ok((validate::check_tree(ctv, paid + fee, fee)?, footprint))
+}
+
+/// What the template pays out, and the fee of each transaction on top of that. Every monetary
+/// output pays [`output_fee`]. In a congestion control tree, that's one output per transaction.
+fn funding(
+    ctv: &Context,
+    congestion: bool,
+    fee_rate: Option<u64>,
+) -> anyhow::Result<(Amount, Amount)> {
+    let output_fee = output_fee(ctv, congestion, fee_rate)?;
+    let mut fee = Amount::ZERO;
+    let mut paid = Amount::ZERO;
+    for output in &ctv.fields.outputs {
+        match output {
+            Output::Address { amount, .. } => {
+                fee += output_fee;
+                paid += *amount;
+            }
+            Output::Tree { amount, .. } => paid += *amount,
+            Output::Data { .. } => {}
+        }
+    }
+    if congestion {
+        fee = output_fee;
+    }
+    Ok((paid, fee))
+}
+
+/// The output the locking page asks for a template to be funded with.
+fn funding_output(ctv: &Context, fee_rate: Option<u64>) -> anyhow::Result<TxOut> {
+    // Only congestion control trees commit to other templates.
+    let congestion = ctv
+        .fields
+        .outputs
+        .iter()
+        .any(|output| matches!(output, Output::Tree { .. }));
+    let (paid, fee) = funding(ctv, congestion, fee_rate)?;
+    Ok(TxOut {
+        value: paid + fee,
+        script_pubkey: ctv.address()?.script_pubkey(),
     })
 }
 
+/// The fee prepaid out of each payout. TRUC templates prepay nothing, since a child spending their
+/// anchor pays the fee at whatever rate is needed when they're broadcast.
+///
+/// Without a fee rate, each payout prepays a flat fee. At a fee rate, the payouts of a simple
+/// template share the fee of its transaction, and each payout of a congestion control tree
+/// prepays enough for the largest transaction in the tree.
+fn output_fee(ctv: &Context, congestion: bool, fee_rate: Option<u64>) -> anyhow::Result<Amount> {
+    if ctv.fields.version == Version(3) {
+        return Ok(Amount::ZERO);
+    }
+    let Some(rate) = fee_rate else {
+        return Ok(OUTPUT_FEE);
+    };
+    let fee_rate = FeeRate::from_sat_per_vb(rate).ok_or(CdvError::FeeRate(rate))?;
+    // Only the amounts depend on the fee, and amounts are always the same size, so the
+    // transactions are the same size whatever the payouts prepay.
+    let largest = util::spending_txs(ctv, validate::placeholder_txid(), 0)?
+        .iter()
+        .map(|tx| tx.vsize() as u64)
+        .max()
+        .unwrap_or_default();
+    let fee = fee_rate.fee_vb(largest).ok_or(CdvError::FeeRate(rate))?;
+    if congestion {
+        return Ok(fee);
+    }
+    let payouts = ctv
+        .fields
+        .outputs
+        .iter()
+        .filter(|output| matches!(output, Output::Address { .. }))
+        .count()
+        .max(1) as u64;
+    Ok(Amount::from_sat(fee.to_sat().div_ceil(payouts)))
+}
+
+/// The fields of a template paying `outputs`. TRUC templates are version 3, with an ephemeral
+/// anchor to bump them through.
+fn fields(mut outputs: Vec<Output>, network: Network, truc: bool) -> Fields {
+    let version = if truc {
+        outputs.push(util::anchor_output(network));
+        Version(3)
+    } else {
+        Version::ONE
+    };
+    Fields {
+        version,
+        locktime: LockTime::ZERO,
+        sequences: vec![Sequence::ZERO],
+        outputs,
+        input_idx: 0,
+    }
+}
+
 fn extract_ctv_from_request(request: &LockingRequest) -> Result<Context, AppError> {
+    let truc = request.truc.unwrap_or_default();
+    if truc && request.fee_rate.is_some() {
+        return Err(
+            anyhow!("A TRUC template pays no fees up front, so it can't have a fee rate").into(),
+        );
+    }
     let mut addresses = Vec::new();
     let mut amounts = Vec::new();
     let mut datas = Vec::new();
-    for line in request.outputs.lines() {
+    for (idx, line) in request.outputs.lines().enumerate() {
         let mut splitter = line.split(':');
         let address =
-            Address::from_str(splitter.next().ok_or_else(|| anyhow!("Missing address"))?)?
-                .require_network(request.network)?;
-        let amount = Amount::from_str(splitter.next().ok_or_else(|| anyhow!("Missing amount"))?)?;
+            Address::from_str(splitter.next().ok_or_else(|| anyhow!("Missing address"))?)?;
+        validate::check_network(
+            &format!("Output {} address", idx + 1),
+            &address,
+            request.network,
+        )?;
+        let address = address.require_network(request.network)?;
+        let amount = util::parse_amount(splitter.next().ok_or_else(|| anyhow!("Missing amount"))?)?;
         addresses.push(address);
         amounts.push(amount);
-        datas.push(splitter.next().map(ToString::to_string));
+        let data = splitter.next().map(ToString::to_string);
+        if let Some(data) = &data {
+            validate::check_data(
+                &format!("Output {} data", idx + 1),
+                data,
+                request.large_data.unwrap_or_default(),
+            )?;
+        }
+        datas.push(data);
     }
+    // A congestion control tree peels off one output per transaction.
+    let depth = if request.congestion.unwrap_or_default() {
+        addresses.len()
+    } else {
+        1
+    };
+    validate::TreeLimits::from_env()?.check_shape(addresses.len(), depth)?;
     let tx_type = if request.taproot.unwrap_or_default() {
         TxType::Taproot {
-            internal_key: nums_points(),
+            internal_key: request.internal_key.unwrap_or_else(util::nums_points),
         }
     } else {
         TxType::Segwit
     };
-    let ctv = if request.congestion.unwrap_or_default() {
+    let congestion = request.congestion.unwrap_or_default();
+    let nonce = match request.unique.unwrap_or_default() {
+        true => Some(nonce()?),
+        false => None,
+    };
+    let build = |output_fee: Amount| -> anyhow::Result<Context> {
+        let mut ctv = if congestion {
+            locking_tree(
+                &addresses,
+                &amounts,
+                &datas,
+                request.network,
+                tx_type,
+                truc,
+                output_fee,
+            )
+            .ok_or_else(|| anyhow!("No outputs"))?
+        } else {
+            simple_ctv(&addresses, &amounts, &datas, request, tx_type, output_fee)
+        };
+        if let Some(data) = &nonce {
+            ctv.fields.outputs.push(Output::Data { data: data.clone() });
+        }
+        util::order_outputs(&mut ctv, request.output_order)?;
+        Ok(ctv)
+    };
+    if congestion {
         tracing::debug!("User requested congestion control tree.");
-        locking_tree(&addresses, &amounts, &datas, request.network, tx_type).unwrap()
     } else {
         tracing::debug!("User requested simple CTV.");
-        simple_ctv(addresses, amounts, datas, request, tx_type)
-    };
-    Ok(ctv)
+    }
+    // The fee only changes the amounts, so a template with no fee is the same size as the real one.
+    let output_fee = output_fee(&build(Amount::ZERO)?, congestion, request.fee_rate)?;
+    for (address, amount) in addresses.iter().zip(&amounts) {
+        if *amount <= output_fee {
+            return Err(
+                anyhow!("{amount} to {address} does not cover its fee of {output_fee}").into(),
+            );
+        }
+    }
+    Ok(build(output_fee)?)
 }
 
-fn nums_points() -> XOnlyPublicKey {
-    ctvlib::util::hash2curve(b"Activate CTV now!")
+/// A value which is only ever used once, to make an otherwise identical template unique. It
+/// doesn't need to be secret, so the time is enough.
+fn nonce() -> anyhow::Result<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    Ok(hex::encode(&sha256::Hash::hash(&now.to_le_bytes())[..8]))
 }
 
 fn simple_ctv(
-    addresses: Vec<Address>,
-    amounts: Vec<Amount>,
-    datas: Vec<Option<String>>,
+    addresses: &[Address],
+    amounts: &[Amount],
+    datas: &[Option<String>],
     request: &LockingRequest,
     tx_type: TxType,
+    output_fee: Amount,
 ) -> Context {
+    let truc = request.truc.unwrap_or_default();
     let mut outputs = Vec::new();
-    for ((address, amount), data) in addresses
-        .into_iter()
-        .zip(amounts.into_iter())
-        .zip(datas.into_iter())
-    {
+    for ((address, amount), data) in addresses.iter().zip(amounts).zip(datas) {
         outputs.push(Output::Address {
             address: address.as_unchecked().clone(),
-            amount: amount - Amount::from_sat(600),
+            amount: *amount - output_fee,
         });
         if let Some(data) = data {
-            outputs.push(Output::Data { data });
+            outputs.push(Output::Data { data: data.clone() });
         }
     }
     Context {
         network: request.network,
         tx_type,
-        fields: Fields {
-            version: Version::ONE,
-            locktime: LockTime::ZERO,
-            sequences: vec![Sequence::ZERO],
-            outputs,
-            input_idx: 0,
-        },
+        fields: fields(outputs, request.network, truc),
     }
 }
 
@@ -134,6 +358,8 @@ fn locking_tree(
     datas: &[Option<String>],
     network: Network,
     tx_type: TxType,
+    truc: bool,
+    output_fee: Amount,
 ) -> Option<Context> {
     let address = addresses.first()?.clone();
     let amount = *amounts.first()?;
@@ -149,6 +375,8 @@ fn locking_tree(
         &datas[1..],
         network,
         tx_type,
+        truc,
+        output_fee,
     );
     let mut outputs = Vec::new();
     if let Some(ctv) = next_ctv {
@@ -159,7 +387,7 @@ fn locking_tree(
     }
     outputs.push(Output::Address {
         address: address.as_unchecked().clone(),
-        amount: amount - Amount::from_sat(600),
+        amount: amount - output_fee,
     });
 
     if let Some(data) = data {
@@ -169,43 +397,142 @@ fn locking_tree(
     Some(Context {
         network,
         tx_type,
-        fields: Fields {
-            version: Version::ONE,
-            locktime: LockTime::ZERO,
-            sequences: vec![Sequence::ZERO],
-            outputs,
-            input_idx: 0,
-        },
+        fields: fields(outputs, network, truc),
     })
 }
 
+#[serde_as]
 #[derive(Debug, Deserialize)]
 pub(crate) struct SpendingRequest {
     ctv: String,
     txid: Txid,
     vout: u32,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    fee_rate: Option<u64>,
 }
 
 #[derive(Template)]
 #[template(path = "simple/spending.html.jinja")]
 pub(crate) struct SpendingTemplate {
-    txs: Vec<String>,
+    /// Each transaction as hex, and as a base64 PSBT.
+    txs: Vec<(String, String)>,
 }
 
 pub(crate) async fn spending(
     Form(request): Form<SpendingRequest>,
 ) -> Result<SpendingTemplate, AppError> {
-    tracing::info!("Spending started.");
-    tracing::debug!("{request:?}");
+    server::blocking(move || {
+        tracing::info!("Spending started.");
+        tracing::debug!("{request:?}");
+        let ctv: Context = serde_json::from_str(&request.ctv)?;
+        validate::check_sequences(&ctv)?;
+        validate::check_data_outputs(&ctv, true)?;
+        let txs = util::spending_txs(&ctv, request.txid, request.vout)?;
+        let funding = OutPoint {
+            txid: request.txid,
+            vout: request.vout,
+        };
+        let psbts = psbt::from_txs(&txs, (funding, funding_output(&ctv, request.fee_rate)?))?;
+
+        tracing::info!("Spending finished.");
+        Ok(SpendingTemplate {
+            txs: txs
+                .iter()
+                .map(|tx| hex::encode(bitcoin::consensus::serialize(tx)))
+                .zip(psbts.iter().map(ToString::to_string))
+                .collect(),
+        })
+    })
+    .await
+}
+
+pub(crate) async fn package(Form(request): Form<SpendingRequest>) -> Result<Response, AppError> {
+    server::blocking(move || {
+        let ctv: Context = serde_json::from_str(&request.ctv)?;
+        validate::check_sequences(&ctv)?;
+        validate::check_data_outputs(&ctv, true)?;
+        let txs = util::spending_txs(&ctv, request.txid, request.vout)?;
+        Ok(server::package(txs, "package.txt"))
+    })
+    .await
+}
+
+pub(crate) async fn backup(
+    Form(request): Form<SpendingRequest>,
+) -> Result<BackupTemplate, AppError> {
+    server::blocking(move || {
+        let ctv: Context = serde_json::from_str(&request.ctv)?;
+        validate::check_sequences(&ctv)?;
+        let txs = util::spending_txs(&ctv, request.txid, request.vout)?;
+        let instructions = vec![
+            format!(
+                "The funds are locked at {}, in output {}:{}.",
+                ctv.address()?,
+                request.txid,
+                request.vout
+            ),
+            "Broadcast the transactions below in order, from any Bitcoin node or wallet. They \
+             don't need a signature, and they can only pay the committed outputs."
+                .to_string(),
+            "If a transaction is rejected as spending a missing output, broadcast the ones \
+             before it first."
+                .to_string(),
+        ];
+        let txs = txs
+            .into_iter()
+            .enumerate()
+            .map(|(idx, tx)| (format!("Transaction {}", idx + 1), tx))
+            .collect();
+        Ok(BackupTemplate {
+            backup: Backup::new("CTV lock", request.ctv, txs, instructions)?,
+        })
+    })
+    .await
+}
+
+// DIAGRAMS AND SUMMARIES
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct DiagramRequest {
+    ctv: String,
+    #[serde(default)]
+    format: Format,
+}
+
+pub(crate) async fn diagram(Form(request): Form<DiagramRequest>) -> Result<Response, AppError> {
+    server::blocking(move || {
+        let ctv: Context = serde_json::from_str(&request.ctv)?;
+        let diagram = Diagram::from_ctv(&ctv)?;
+        Ok(server::diagram(&diagram, request.format, "contract"))
+    })
+    .await
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+pub(crate) struct ContextRequest {
+    ctv: String,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    fee_rate: Option<u64>,
+}
+
+pub(crate) async fn summary(Form(request): Form<ContextRequest>) -> Result<String, AppError> {
     let ctv: Context = serde_json::from_str(&request.ctv)?;
-    let tx = ctv.spending_tx(request.txid, request.vout)?;
-
-    tracing::info!("Spending finished.");
-    Ok(SpendingTemplate {
-        txs: tx
-            .iter()
-            .map(bitcoin::consensus::serialize)
-            .map(hex::encode)
-            .collect(),
+    Ok(summary::ctv(&ctv)?)
+}
+
+pub(crate) async fn fees(
+    Form(request): Form<ContextRequest>,
+) -> Result<Json<Vec<TxCost>>, AppError> {
+    server::blocking(move || {
+        let ctv: Context = serde_json::from_str(&request.ctv)?;
+        Ok(Json(validate::fee_report(
+            &ctv,
+            funding_output(&ctv, request.fee_rate)?.value,
+        )?))
     })
+    .await
 }
@@ -0,0 +1,196 @@
+use anyhow::anyhow;
+use askama::Template;
+use axum::Form;
+use bitcoin::{address::NetworkUnchecked, Address, Amount, Network, OutPoint, TxOut, Txid};
+use serde::Deserialize;
+use serde_with::{serde_as, NoneAsEmptyString};
+
+use crate::{
+    explorer,
+    server::AppError,
+    spacechain::{FeeInput, Spacechain},
+    util, validate,
+};
+
+// CREATE A SPACECHAIN
+// -------------------
+
+#[derive(Template)]
+#[template(path = "spacechain/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreatingRequest {
+    blocks: u32,
+    network: Network,
+    taproot: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+#[derive(Template)]
+#[template(path = "spacechain/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    spacechain: String,
+    address: Address,
+    address_url: Option<String>,
+    amount: Amount,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    tracing::debug!("{request:?}");
+    let used = validate::parse_used(&request.used_addresses)?;
+    let spacechain = Spacechain::new(
+        request.network,
+        request.blocks,
+        request.taproot.unwrap_or_default(),
+    )?;
+    let mut warnings = spacechain.validate()?;
+    let address = spacechain.address()?.require_network(spacechain.network)?;
+    warnings.extend(validate::check_reuse(
+        "spacechain",
+        std::slice::from_ref(&address),
+        &used,
+    ));
+    Ok(CreatingTemplate {
+        spacechain: serde_json::to_string(&spacechain)?,
+        address_url: explorer::address_url(&address),
+        address,
+        amount: spacechain.amount(),
+        warnings,
+    })
+}
+
+// SPACECHAIN STATUS
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct FundedRequest {
+    spacechain: String,
+    txid: Txid,
+    vout: u32,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct StatusRequest {
+    spacechain: String,
+}
+
+#[derive(Template)]
+#[template(path = "spacechain/status.html.jinja")]
+pub(crate) struct StatusTemplate {
+    spacechain: String,
+    next_block: u32,
+    blocks: u32,
+}
+
+pub(crate) async fn funded(
+    Form(request): Form<FundedRequest>,
+) -> anyhow::Result<StatusTemplate, AppError> {
+    let mut spacechain: Spacechain = serde_json::from_str(&request.spacechain)?;
+    spacechain.outpoint = Some(OutPoint {
+        txid: request.txid,
+        vout: request.vout,
+    });
+    status_template(&spacechain)
+}
+
+pub(crate) async fn status(
+    Form(request): Form<StatusRequest>,
+) -> anyhow::Result<StatusTemplate, AppError> {
+    let spacechain: Spacechain = serde_json::from_str(&request.spacechain)?;
+    Ok(StatusTemplate {
+        next_block: spacechain.next_block,
+        blocks: spacechain.blocks,
+        spacechain: request.spacechain,
+    })
+}
+
+fn status_template(spacechain: &Spacechain) -> anyhow::Result<StatusTemplate, AppError> {
+    Ok(StatusTemplate {
+        spacechain: serde_json::to_string(spacechain)?,
+        next_block: spacechain.next_block,
+        blocks: spacechain.blocks,
+    })
+}
+
+// COMMITTING A BLOCK
+// -------------------
+
+#[serde_as]
+#[derive(Deserialize)]
+pub(crate) struct CommittingRequest {
+    spacechain: String,
+    commitment: String,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    fee_outpoint: Option<OutPoint>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    change_address: Option<Address<NetworkUnchecked>>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    change_amount: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "spacechain/committing.html.jinja")]
+pub(crate) struct CommittingTemplate {
+    block: u32,
+    block_tx: String,
+    commitment_tx: String,
+    has_fee_input: bool,
+    next: Option<String>,
+}
+
+pub(crate) async fn committing(
+    Form(request): Form<CommittingRequest>,
+) -> anyhow::Result<CommittingTemplate, AppError> {
+    let mut spacechain: Spacechain = serde_json::from_str(&request.spacechain)?;
+    let commitment: [u8; 32] = hex::decode(request.commitment.trim())?
+        .try_into()
+        .map_err(|_| anyhow!("Commitment must be 32 bytes"))?;
+    let fee_input = match (
+        request.fee_outpoint,
+        request.change_address,
+        request.change_amount,
+    ) {
+        (None, None, None) => None,
+        (Some(outpoint), Some(address), Some(amount)) => Some(FeeInput {
+            outpoint,
+            change: TxOut {
+                value: util::parse_amount(&amount)?,
+                script_pubkey: address.require_network(spacechain.network)?.script_pubkey(),
+            },
+        }),
+        _ => {
+            return Err(anyhow!(
+                "A fee input needs an outpoint, a change address and a change amount"
+            )
+            .into())
+        }
+    };
+    let has_fee_input = fee_input.is_some();
+    let block = spacechain.next_block;
+    let (block_tx, commitment_tx) = spacechain.commit(commitment, fee_input)?;
+
+    let next = if spacechain.next_block < spacechain.blocks {
+        Some(serde_json::to_string(&spacechain)?)
+    } else {
+        None
+    };
+    Ok(CommittingTemplate {
+        block,
+        block_tx: hex::encode(bitcoin::consensus::serialize(&block_tx)),
+        commitment_tx: hex::encode(bitcoin::consensus::serialize(&commitment_tx)),
+        has_fee_input,
+        next,
+    })
+}
@@ -0,0 +1,139 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use askama::Template;
+use axum::Form;
+use bitcoin::{address::NetworkChecked, Address, Amount, Network, Txid};
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::{
+    explorer,
+    server::AppError,
+    splitter::{Recipient, Splitter},
+    util, validate,
+};
+
+// CREATING A SPLITTER
+// -------------------
+
+#[derive(Template)]
+#[template(path = "splitter/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreatingRequest {
+    recipients: String,
+    denominations: String,
+    network: Network,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+pub(crate) struct Rung {
+    denomination: Amount,
+    split: Vec<(Address, Amount)>,
+}
+
+#[derive(Template)]
+#[template(path = "splitter/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    splitter: String,
+    address: Address<NetworkChecked>,
+    address_url: Option<String>,
+    ladder: Vec<Rung>,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    tracing::debug!("{request:?}");
+    let used = validate::parse_used(&request.used_addresses)?;
+    let splitter = Splitter {
+        network: request.network,
+        recipients: parse_recipients(&request.recipients)?,
+        denominations: request
+            .denominations
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| util::parse_amount(l.trim()))
+            .collect::<Result<_, _>>()?,
+    };
+    let mut warnings = splitter.validate()?;
+    let address = splitter.address()?.require_network(splitter.network)?;
+    warnings.extend(validate::check_reuse(
+        "splitter",
+        std::slice::from_ref(&address),
+        &used,
+    ));
+    let ladder = splitter
+        .denominations
+        .iter()
+        .map(|denomination| {
+            Ok(Rung {
+                denomination: *denomination,
+                split: splitter.split(*denomination)?,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(CreatingTemplate {
+        splitter: serde_json::to_string(&splitter)?,
+        address_url: explorer::address_url(&address),
+        address,
+        ladder,
+        warnings,
+    })
+}
+
+fn parse_recipients(recipients: &str) -> anyhow::Result<Vec<Recipient>> {
+    let mut parsed = Vec::new();
+    for line in recipients.lines().filter(|l| !l.trim().is_empty()) {
+        let mut splitter = line.trim().split(':');
+        let address =
+            Address::from_str(splitter.next().ok_or_else(|| anyhow!("Missing address"))?)?;
+        let percent = splitter
+            .next()
+            .ok_or_else(|| anyhow!("Missing percentage"))?
+            .trim()
+            .trim_end_matches('%')
+            .parse()?;
+        parsed.push(Recipient { address, percent });
+    }
+    Ok(parsed)
+}
+
+// SPLITTING A DONATION
+// -------------------
+
+#[serde_as]
+#[derive(Deserialize)]
+pub(crate) struct SpendingRequest {
+    splitter: String,
+    txid: Txid,
+    vout: u32,
+    #[serde_as(as = "util::AmountWithUnits")]
+    amount: Amount,
+}
+
+#[derive(Template)]
+#[template(path = "splitter/spending.html.jinja")]
+pub(crate) struct SpendingTemplate {
+    splitter: String,
+    tx: String,
+}
+
+pub(crate) async fn spending(
+    Form(request): Form<SpendingRequest>,
+) -> anyhow::Result<SpendingTemplate, AppError> {
+    let splitter: Splitter = serde_json::from_str(&request.splitter)?;
+    let tx = splitter.spending_tx(request.txid, request.vout, request.amount)?;
+    Ok(SpendingTemplate {
+        splitter: request.splitter,
+        tx: hex::encode(bitcoin::consensus::serialize(&tx)),
+    })
+}
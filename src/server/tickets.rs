@@ -0,0 +1,138 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use askama::Template;
+use axum::Form;
+use bitcoin::{address::NetworkUnchecked, hashes::sha256, Address, Network, Txid};
+use serde::Deserialize;
+
+use crate::{
+    server::AppError,
+    tickets::{IssuedTicket, Ticket, TicketSale},
+    util, validate,
+};
+
+// ISSUING TICKETS
+// -------------------
+
+#[derive(Template)]
+#[template(path = "tickets/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreatingRequest {
+    organizer: Address<NetworkUnchecked>,
+    event_date: String,
+    cancel_hash: sha256::Hash,
+    attendees: String,
+    network: Network,
+    taproot: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+#[derive(Template)]
+#[template(path = "tickets/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    sale: String,
+    event_date: String,
+    tickets: Vec<IssuedTicket>,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    tracing::debug!("{request:?}");
+    let used = validate::parse_used(&request.used_addresses)?;
+    let sale = TicketSale {
+        network: request.network,
+        organizer: request.organizer,
+        event_date: util::parse_date(&request.event_date)?,
+        cancel_hash: request.cancel_hash,
+        tickets: parse_attendees(&request.attendees)?,
+        taproot: request.taproot.unwrap_or_default(),
+    };
+    let mut warnings = sale.validate()?;
+    let tickets = sale.issue()?;
+    let addresses = tickets
+        .iter()
+        .map(|ticket| ticket.address.clone().require_network(sale.network))
+        .collect::<Result<Vec<_>, _>>()?;
+    warnings.extend(validate::check_reuse("ticket", &addresses, &used));
+    Ok(CreatingTemplate {
+        sale: serde_json::to_string(&sale)?,
+        event_date: sale.event_date(),
+        tickets,
+        warnings,
+    })
+}
+
+fn parse_attendees(attendees: &str) -> anyhow::Result<Vec<Ticket>> {
+    let mut parsed = Vec::new();
+    for line in attendees.lines().filter(|l| !l.trim().is_empty()) {
+        let mut splitter = line.trim().split(':');
+        let refund = Address::from_str(
+            splitter
+                .next()
+                .ok_or_else(|| anyhow!("Missing refund address"))?,
+        )?;
+        let amount = util::parse_amount(
+            splitter
+                .next()
+                .ok_or_else(|| anyhow!("Missing ticket price"))?,
+        )?;
+        parsed.push(Ticket { refund, amount });
+    }
+    Ok(parsed)
+}
+
+// COLLECTING OR REFUNDING A TICKET
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct SpendingRequest {
+    sale: String,
+    ticket: usize,
+    txid: Txid,
+    vout: u32,
+    secret: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "tickets/spending.html.jinja")]
+pub(crate) struct SpendingTemplate {
+    ticket: usize,
+    event_date: String,
+    payout: String,
+    refund: Option<String>,
+}
+
+pub(crate) async fn spending(
+    Form(request): Form<SpendingRequest>,
+) -> anyhow::Result<SpendingTemplate, AppError> {
+    let sale: TicketSale = serde_json::from_str(&request.sale)?;
+    let payout = sale.payout_tx(request.ticket, request.txid, request.vout)?;
+    let refund = match request.secret.filter(|s| !s.trim().is_empty()) {
+        Some(secret) => {
+            let tx = sale.refund_tx(
+                request.ticket,
+                request.txid,
+                request.vout,
+                &hex::decode(secret.trim())?,
+            )?;
+            Some(hex::encode(bitcoin::consensus::serialize(&tx)))
+        }
+        None => None,
+    };
+    Ok(SpendingTemplate {
+        ticket: request.ticket + 1,
+        event_date: sale.event_date(),
+        payout: hex::encode(bitcoin::consensus::serialize(&payout)),
+        refund,
+    })
+}
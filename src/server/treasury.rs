@@ -0,0 +1,119 @@
+use askama::Template;
+use axum::Form;
+use bitcoin::{address::NetworkChecked, Address, Amount, Network, Txid};
+use serde::Deserialize;
+
+use crate::{
+    explorer,
+    server::AppError,
+    treasury::{Department, Tranche, Treasury},
+    validate,
+};
+
+// DEFINING THE TREASURY
+// -------------------
+
+#[derive(Template)]
+#[template(path = "treasury/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreatingRequest {
+    departments: String,
+    network: Network,
+    taproot: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+#[derive(Template)]
+#[template(path = "treasury/creating.html.jinja")]
+pub(crate) struct CreatingTemplate {
+    treasury: String,
+    address: Address<NetworkChecked>,
+    address_url: Option<String>,
+    amount: Amount,
+    tranches: Vec<Tranche>,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn creating(
+    Form(request): Form<CreatingRequest>,
+) -> anyhow::Result<CreatingTemplate, AppError> {
+    tracing::debug!("{request:?}");
+    let used = validate::parse_used(&request.used_addresses)?;
+    let departments: Vec<Department> = serde_json::from_str(&request.departments)?;
+    let treasury = Treasury {
+        network: request.network,
+        departments,
+        taproot: request.taproot.unwrap_or_default(),
+    };
+    let mut warnings = treasury.validate()?;
+    let address = treasury.address()?.require_network(treasury.network)?;
+    warnings.extend(validate::check_reuse(
+        "treasury",
+        std::slice::from_ref(&address),
+        &used,
+    ));
+    Ok(CreatingTemplate {
+        treasury: serde_json::to_string(&treasury)?,
+        address_url: explorer::address_url(&address),
+        address,
+        amount: treasury.amount()?,
+        tranches: treasury.tranches(),
+        warnings,
+    })
+}
+
+// SPLITTING INTO VAULTS
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct SpendingRequest {
+    treasury: String,
+    txid: Txid,
+    vout: u32,
+}
+
+pub(crate) struct TrancheVault {
+    department: String,
+    amount: Amount,
+    vout: u32,
+    vault: String,
+}
+
+#[derive(Template)]
+#[template(path = "treasury/spending.html.jinja")]
+pub(crate) struct SpendingTemplate {
+    tx: String,
+    txid: Txid,
+    vaults: Vec<TrancheVault>,
+}
+
+pub(crate) async fn spending(
+    Form(request): Form<SpendingRequest>,
+) -> anyhow::Result<SpendingTemplate, AppError> {
+    let treasury: Treasury = serde_json::from_str(&request.treasury)?;
+    let tx = treasury.split_tx(request.txid, request.vout)?;
+    let vaults = treasury
+        .tranches()
+        .into_iter()
+        .map(|tranche| {
+            Ok(TrancheVault {
+                department: tranche.department,
+                amount: tranche.vault.amount,
+                vout: tranche.vout,
+                vault: serde_json::to_string(&tranche.vault)?,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(SpendingTemplate {
+        tx: hex::encode(bitcoin::consensus::serialize(&tx)),
+        txid: tx.txid(),
+        vaults,
+    })
+}
@@ -0,0 +1,140 @@
+use askama::Template;
+use axum::{response::Response, Form};
+use bitcoin::{Amount, Network, OutPoint, TxOut, Txid};
+use serde::Deserialize;
+use serde_with::{serde_as, NoneAsEmptyString};
+
+use crate::{
+    explorer, psbt,
+    server::{self, AppError},
+    tree::{self, Tree, TreeBuilder},
+    util,
+    validate::{self, Footprint, TxCost},
+};
+
+// BUILDING A TREE
+// -------------------
+
+#[derive(Template)]
+#[template(path = "tree/index.html.jinja")]
+pub(crate) struct IndexTemplate;
+
+pub(crate) async fn index() -> IndexTemplate {
+    IndexTemplate
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub(crate) struct BuildingRequest {
+    recipients: String,
+    network: Network,
+    radix: usize,
+    #[serde_as(as = "NoneAsEmptyString")]
+    fee_budget: Option<String>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    fee_rate: Option<u64>,
+    taproot: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
+}
+
+#[derive(Template)]
+#[template(path = "tree/building.html.jinja")]
+pub(crate) struct BuildingTemplate {
+    tree: String,
+    amount: Amount,
+    address: String,
+    address_url: Option<String>,
+    footprint: Footprint,
+    fees: Vec<TxCost>,
+    warnings: Vec<String>,
+}
+
+pub(crate) async fn building(
+    Form(request): Form<BuildingRequest>,
+) -> Result<BuildingTemplate, AppError> {
+    server::blocking(move || {
+        tracing::debug!("{request:?}");
+        let used = validate::parse_used(&request.used_addresses)?;
+        let mut builder =
+            TreeBuilder::new(request.network, tree::parse_payouts(&request.recipients)?)
+                .radix(request.radix)
+                .taproot(request.taproot.unwrap_or_default());
+        if let Some(fee_budget) = &request.fee_budget {
+            builder = builder.fee_budget(util::parse_amount(fee_budget)?);
+        }
+        if let Some(fee_rate) = request.fee_rate {
+            builder = builder.fee_rate(fee_rate);
+        }
+        let tree = builder.build()?;
+        let (mut warnings, footprint) = tree.validate()?;
+        let address = tree.ctv.address()?;
+        warnings.extend(validate::check_reuse(
+            "tree",
+            std::slice::from_ref(&address),
+            &used,
+        ));
+        Ok(BuildingTemplate {
+            tree: serde_json::to_string(&tree)?,
+            amount: tree.amount,
+            address_url: explorer::address_url(&address),
+            address: address.to_string(),
+            footprint,
+            fees: validate::fee_report(&tree.ctv, tree.amount)?,
+            warnings,
+        })
+    })
+    .await
+}
+
+// UNROLLING A TREE
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct SpendingRequest {
+    tree: String,
+    txid: Txid,
+    vout: u32,
+}
+
+#[derive(Template)]
+#[template(path = "tree/spending.html.jinja")]
+pub(crate) struct SpendingTemplate {
+    /// Each transaction as hex, and as a base64 PSBT.
+    txs: Vec<(String, String)>,
+}
+
+pub(crate) async fn spending(
+    Form(request): Form<SpendingRequest>,
+) -> Result<SpendingTemplate, AppError> {
+    server::blocking(move || {
+        let tree: Tree = serde_json::from_str(&request.tree)?;
+        let txs = tree.transactions(request.txid, request.vout)?;
+        let funding = OutPoint {
+            txid: request.txid,
+            vout: request.vout,
+        };
+        let output = TxOut {
+            value: tree.amount,
+            script_pubkey: tree.ctv.address()?.script_pubkey(),
+        };
+        let psbts = psbt::from_txs(&txs, (funding, output))?;
+        Ok(SpendingTemplate {
+            txs: txs
+                .iter()
+                .map(|tx| hex::encode(bitcoin::consensus::serialize(tx)))
+                .zip(psbts.iter().map(ToString::to_string))
+                .collect(),
+        })
+    })
+    .await
+}
+
+pub(crate) async fn package(Form(request): Form<SpendingRequest>) -> Result<Response, AppError> {
+    server::blocking(move || {
+        let tree: Tree = serde_json::from_str(&request.tree)?;
+        let txs = tree.transactions(request.txid, request.vout)?;
+        Ok(server::package(txs, "tree.txt"))
+    })
+    .await
+}
@@ -1,16 +1,21 @@
 use askama::Template;
-use axum::Form;
+use axum::{response::Response, Form, Json};
 use bitcoin::{
     address::{NetworkChecked, NetworkUnchecked},
-    Address, Amount, Network, Txid,
+    Address, Amount, Network, OutPoint, TxOut, Txid, XOnlyPublicKey,
 };
-use serde::Deserialize;
-use serde_with::{serde_as, DisplayFromStr};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, NoneAsEmptyString};
 
 use crate::{
-    error::AppError,
+    backup::Backup,
+    diagram::{Diagram, Format},
+    explorer, psbt,
+    server::{self, AppError, BackupTemplate},
+    summary,
     util::{self},
-    vault::Vault,
+    validate::{self, Scenario},
+    vault::{Transition, Vault, VaultAction, VaultProgress, VaultState},
 };
 
 // INITIATE A VAULT
@@ -32,18 +37,29 @@ pub(crate) async fn index() -> IndexTemplate {
 pub(crate) struct VaultingTemplate {
     vault: String,
     address: Address<NetworkChecked>,
+    address_url: Option<String>,
+    summary: String,
+    scenarios: Vec<Scenario>,
+    warnings: Vec<String>,
 }
 
 #[serde_as]
 #[derive(Deserialize)]
 pub(crate) struct VaultingRequest {
-    #[serde_as(as = "DisplayFromStr")]
+    #[serde_as(as = "util::AmountWithUnits")]
     amount: Amount,
     cold_address: Address<NetworkUnchecked>,
     hot_address: Address<NetworkUnchecked>,
     block_delay: u16,
     network: Network,
     taproot: Option<bool>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    internal_key: Option<XOnlyPublicKey>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    fee_rate: Option<u64>,
+    anchor: Option<bool>,
+    #[serde(default)]
+    used_addresses: String,
 }
 
 impl From<VaultingRequest> for Vault {
@@ -55,6 +71,9 @@ impl From<VaultingRequest> for Vault {
             network: value.network,
             delay: value.block_delay,
             taproot: value.taproot.unwrap_or_default(),
+            internal_key: value.internal_key,
+            fee_rate: value.fee_rate,
+            anchor: value.anchor.unwrap_or_default(),
         }
     }
 }
@@ -62,10 +81,46 @@ impl From<VaultingRequest> for Vault {
 pub(crate) async fn vaulting(
     Form(request): Form<VaultingRequest>,
 ) -> anyhow::Result<VaultingTemplate, AppError> {
+    let used = validate::parse_used(&request.used_addresses)?;
     let vault: Vault = request.into();
+    let mut warnings = vault.validate()?;
     let address = vault.vault_address()?.require_network(vault.network)?;
-    let vault = serde_json::to_string(&vault)?;
-    Ok(VaultingTemplate { vault, address })
+    warnings.extend(validate::check_reuse(
+        "vault",
+        std::slice::from_ref(&address),
+        &used,
+    ));
+    Ok(VaultingTemplate {
+        vault: serde_json::to_string(&vault)?,
+        address_url: explorer::address_url(&address),
+        address,
+        summary: summary::vault(&vault)?,
+        scenarios: validate::fee_scenarios(&vault.leaves()?)?,
+        warnings,
+    })
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DiagramRequest {
+    vault: String,
+    #[serde(default)]
+    format: Format,
+}
+
+pub(crate) async fn diagram(Form(request): Form<DiagramRequest>) -> Result<Response, AppError> {
+    let vault: Vault = serde_json::from_str(&request.vault)?;
+    let diagram = Diagram::from_vault(&vault)?;
+    Ok(server::diagram(&diagram, request.format, "vault"))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SummaryRequest {
+    vault: String,
+}
+
+pub(crate) async fn summary(Form(request): Form<SummaryRequest>) -> Result<String, AppError> {
+    let vault: Vault = serde_json::from_str(&request.vault)?;
+    Ok(summary::vault(&vault)?)
 }
 
 // UNVAULTING FUNDS
@@ -84,6 +139,7 @@ pub(crate) struct UnvaultingTemplate {
     vault: String,
     script: String,
     tx: String,
+    psbt: String,
     txid: Txid,
 }
 
@@ -96,11 +152,20 @@ pub(crate) async fn unvaulting(
     let vault_ctv = vault.vault_ctv()?;
     let spending_tx = vault_ctv.spending_tx(request.txid, request.vout)?[0].clone();
     let tx = hex::encode(bitcoin::consensus::serialize(&spending_tx));
-    let vault = serde_json::to_string(&vault)?;
+    let funding = TxOut {
+        value: vault.amount,
+        script_pubkey: vault_ctv.address()?.script_pubkey(),
+    };
+    let outpoint = OutPoint {
+        txid: request.txid,
+        vout: request.vout,
+    };
+    let psbt = psbt::from_txs(std::slice::from_ref(&spending_tx), (outpoint, funding))?.remove(0);
     Ok(UnvaultingTemplate {
-        vault,
+        vault: request.vault,
         script,
         tx,
+        psbt: psbt.to_string(),
         txid: spending_tx.txid(),
     })
 }
@@ -115,7 +180,9 @@ pub(crate) struct SpendingRequest {
 #[template(path = "vaults/spending.html.jinja")]
 pub(crate) struct SpendingTemplate {
     cold_tx: String,
+    cold_psbt: String,
     hot_tx: String,
+    hot_psbt: String,
 }
 
 pub(crate) async fn spending(
@@ -124,8 +191,104 @@ pub(crate) async fn spending(
     let vault: Vault = serde_json::from_str(&request.vault)?;
     let cold_tx = vault.cold_spend(request.txid, 0)?;
     let hot_tx = vault.hot_spend(request.txid, 0)?;
+    // The unvaulting output doesn't depend on where the vault was funded from.
+    let unvault = vault
+        .vault_ctv()?
+        .spending_tx(validate::placeholder_txid(), 0)?[0]
+        .output[0]
+        .clone();
+    let outpoint = OutPoint {
+        txid: request.txid,
+        vout: 0,
+    };
+    let cold_psbt =
+        psbt::from_txs(std::slice::from_ref(&cold_tx), (outpoint, unvault.clone()))?.remove(0);
+    let hot_psbt = psbt::from_txs(std::slice::from_ref(&hot_tx), (outpoint, unvault))?.remove(0);
     Ok(SpendingTemplate {
         cold_tx: hex::encode(bitcoin::consensus::serialize(&cold_tx)),
+        cold_psbt: cold_psbt.to_string(),
         hot_tx: hex::encode(bitcoin::consensus::serialize(&hot_tx)),
+        hot_psbt: hot_psbt.to_string(),
+    })
+}
+
+// VAULT STATE
+// -------------------
+
+#[derive(Deserialize)]
+pub(crate) struct StateRequest {
+    vault: String,
+    txid: Option<Txid>,
+    vout: Option<u32>,
+    unvault_confirmations: Option<u32>,
+    cold_swept: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct StateResponse {
+    state: VaultState,
+    transitions: Vec<Transition>,
+    actions: Vec<VaultAction>,
+}
+
+/// Where a vault is in its lifecycle, given the funding outpoint and what has happened since.
+pub(crate) async fn state(
+    Form(request): Form<StateRequest>,
+) -> anyhow::Result<Json<StateResponse>, AppError> {
+    let vault: Vault = serde_json::from_str(&request.vault)?;
+    let progress = VaultProgress {
+        funding: request
+            .txid
+            .zip(request.vout)
+            .map(|(txid, vout)| OutPoint { txid, vout }),
+        unvault_confirmations: request.unvault_confirmations,
+        cold_swept: request.cold_swept.unwrap_or_default(),
+    };
+    let transitions = vault.transitions(&progress)?;
+    let state = transitions
+        .last()
+        .map_or(VaultState::Unfunded, |transition| transition.to);
+    Ok(Json(StateResponse {
+        state,
+        actions: state.actions(),
+        transitions,
+    }))
+}
+
+// PAPER BACKUP
+// -------------------
+
+pub(crate) async fn backup(
+    Form(request): Form<UnvaultingRequest>,
+) -> anyhow::Result<BackupTemplate, AppError> {
+    let vault: Vault = serde_json::from_str(&request.vault)?;
+    let unvault = vault.vault_ctv()?.spending_tx(request.txid, request.vout)?[0].clone();
+    let cold = vault.cold_spend(unvault.txid(), 0)?;
+    let hot = vault.hot_spend(unvault.txid(), 0)?;
+    let instructions = vec![
+        format!(
+            "The vault holds {} in output {}:{}.",
+            vault.amount, request.txid, request.vout
+        ),
+        "To move the funds, first broadcast the unvaulting transaction.".to_string(),
+        format!(
+            "To sweep them to the cold address {}, broadcast the cold sweep at any time after \
+             the unvaulting transaction. Do this straight away if you didn't start the unvault.",
+            vault.cold.assume_checked_ref()
+        ),
+        format!(
+            "To spend them to the hot address {}, wait until the unvaulting transaction has {} \
+             confirmations, then broadcast the hot spend.",
+            vault.hot.assume_checked_ref(),
+            vault.delay
+        ),
+    ];
+    let txs = vec![
+        ("Unvaulting transaction".to_string(), unvault),
+        ("Cold sweep".to_string(), cold),
+        ("Hot spend".to_string(), hot),
+    ];
+    Ok(BackupTemplate {
+        backup: Backup::new("Vault", request.vault, txs, instructions)?,
     })
 }
@@ -0,0 +1,286 @@
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime, address::NetworkUnchecked, script::PushBytesBuf, transaction::Version,
+    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    util::{self, nums_points},
+    validate,
+};
+
+/// Value of the anchor output in every spacechain transaction. It's above the dust limit of a P2A
+/// output, so the anchor doesn't need ephemeral dust relay.
+const ANCHOR: Amount = Amount::from_sat(330);
+
+/// Payload of the data output which ends the chain, in place of a covenant output.
+const END: &str = "spacechain end";
+
+/// Spacechains are the longest chains of CTV transactions we allow.
+const MAX_BLOCKS: u32 = 1000;
+
+/// A spacechain built out of a chain of CTV transactions, one per spacechain block.
+///
+/// Every transaction in the chain has two outputs: a pay-to-anchor (P2A) output, and a CTV
+/// covenant paying on to the next transaction in the chain. Whoever wants to mine the next
+/// spacechain block spends the anchor in a child transaction that carries the block's
+/// commitment in an OP_RETURN, and pays the fees of both transactions. The block transactions
+/// are TRUC (version 3), so they can pay no fee and still be relayed in a package with that
+/// child.
+///
+/// The covenant addresses of the blocks are worked out once, from the last block back, so
+/// committing a block only has to build that block's transaction.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Spacechain {
+    pub(crate) network: Network,
+    pub(crate) blocks: u32,
+    pub(crate) taproot: bool,
+    /// The covenant output the next block spends: the funding outpoint to start with, then the
+    /// covenant output of the last committed block.
+    pub(crate) outpoint: Option<OutPoint>,
+    /// The next spacechain block that needs a commitment.
+    pub(crate) next_block: u32,
+    /// The covenant address of every block, in block order.
+    addresses: Vec<Address<NetworkUnchecked>>,
+}
+
+/// A wallet output added to a commitment transaction, to pay more in fees than the anchor holds.
+#[derive(Debug)]
+pub(crate) struct FeeInput {
+    pub(crate) outpoint: OutPoint,
+    /// Whatever the input holds beyond the fee, paid back to the wallet.
+    pub(crate) change: TxOut,
+}
+
+impl Spacechain {
+    pub(crate) fn new(network: Network, blocks: u32, taproot: bool) -> anyhow::Result<Spacechain> {
+        if blocks == 0 || blocks > MAX_BLOCKS {
+            bail!("A spacechain must have between 1 and {MAX_BLOCKS} blocks");
+        }
+        let mut spacechain = Spacechain {
+            network,
+            blocks,
+            taproot,
+            outpoint: None,
+            next_block: 0,
+            addresses: Vec::new(),
+        };
+        // Each block pays on to the address of the block after it, so they're built backwards.
+        let mut addresses: Vec<Address<NetworkUnchecked>> = Vec::new();
+        for block in (0..blocks).rev() {
+            let address = spacechain
+                .block_ctv(block, addresses.last())?
+                .address()?
+                .as_unchecked()
+                .clone();
+            addresses.push(address);
+        }
+        addresses.reverse();
+        spacechain.addresses = addresses;
+        Ok(spacechain)
+    }
+
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        // The anchor pays for each block by CPFP, so the block transactions themselves pay no fee.
+        let mut outpoint = OutPoint {
+            txid: validate::placeholder_txid(),
+            vout: 0,
+        };
+        let mut chain = Vec::new();
+        for block in 0..self.blocks {
+            let tx = self.block_tx(block, outpoint)?;
+            outpoint = OutPoint {
+                txid: tx.txid(),
+                vout: 1,
+            };
+            chain.push(tx);
+        }
+        let fees = vec![Amount::ZERO; chain.len()];
+        Ok(validate::check_txs(
+            self.network,
+            self.amount(),
+            &chain,
+            &fees,
+        )?)
+    }
+
+    /// The amount which has to be locked into the spacechain.
+    pub(crate) fn amount(&self) -> Amount {
+        ANCHOR * self.blocks as u64
+    }
+
+    pub(crate) fn address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+        self.addresses
+            .first()
+            .cloned()
+            .ok_or_else(|| anyhow!("Spacechain has no blocks"))
+    }
+
+    /// Fill in the commitment for the next spacechain block, and move on to the block after it.
+    /// Returns the covenant transaction for the block and the child transaction which spends its
+    /// anchor and carries the commitment.
+    ///
+    /// Without a `fee_input`, the child pays exactly the anchor's 330 sats in fees, for itself
+    /// and its parent together, which is only enough while blocks are close to empty.
+    pub(crate) fn commit(
+        &mut self,
+        commitment: [u8; 32],
+        fee_input: Option<FeeInput>,
+    ) -> anyhow::Result<(Transaction, Transaction)> {
+        let outpoint = self
+            .outpoint
+            .ok_or_else(|| anyhow!("Spacechain has not been funded"))?;
+        if self.next_block >= self.blocks {
+            bail!("Every block of the spacechain has been committed");
+        }
+        let block_tx = self.block_tx(self.next_block, outpoint)?;
+        let commitment_tx = commitment_tx(block_tx.txid(), commitment, fee_input)?;
+        self.outpoint = Some(OutPoint {
+            txid: block_tx.txid(),
+            vout: 1,
+        });
+        self.next_block += 1;
+        Ok((block_tx, commitment_tx))
+    }
+
+    fn block_tx(&self, block: u32, outpoint: OutPoint) -> anyhow::Result<Transaction> {
+        let next = match self.addresses.get(block as usize + 1) {
+            None if block + 1 < self.blocks => {
+                bail!("Spacechain has no address for block {}", block + 1)
+            }
+            next => next,
+        };
+        self.block_ctv(block, next)?
+            .spending_tx(outpoint.txid, outpoint.vout)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Spacechain block {block} has no transaction"))
+    }
+
+    /// The template of a block, which pays on to `next`, the address of the block after it,
+    /// unless it's the last block. A transaction with nothing but the anchor would be under the
+    /// 65 byte standard minimum, so the last block ends the chain with a data output instead.
+    fn block_ctv(
+        &self,
+        block: u32,
+        next: Option<&Address<NetworkUnchecked>>,
+    ) -> anyhow::Result<Context> {
+        let mut outputs = vec![Output::Address {
+            address: Address::from_script(&util::anchor_script(), self.network)?
+                .as_unchecked()
+                .clone(),
+            amount: ANCHOR,
+        }];
+        if let Some(next) = next {
+            outputs.push(Output::Address {
+                address: next.clone(),
+                amount: ANCHOR * (self.blocks - block - 1) as u64,
+            });
+        } else {
+            outputs.push(Output::Data {
+                data: END.to_string(),
+            });
+        }
+        Ok(Context {
+            network: self.network,
+            tx_type: self.tx_type(),
+            fields: Fields {
+                version: Version(3),
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::ZERO],
+                outputs,
+                input_idx: 0,
+            },
+        })
+    }
+
+    fn tx_type(&self) -> TxType {
+        if self.taproot {
+            return TxType::Taproot {
+                internal_key: nums_points(),
+            };
+        }
+        TxType::Segwit
+    }
+}
+
+/// The child of a block's covenant transaction, which has to be version 3 like its parent. The
+/// anchor input needs no witness, but a fee input is left unsigned, for the wallet which owns it
+/// to sign.
+fn commitment_tx(
+    block_txid: Txid,
+    commitment: [u8; 32],
+    fee_input: Option<FeeInput>,
+) -> anyhow::Result<Transaction> {
+    let mut tx = Transaction {
+        version: Version(3),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: block_txid,
+                vout: 0,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::new_op_return(PushBytesBuf::try_from(commitment.to_vec())?),
+        }],
+    };
+    if let Some(fee_input) = fee_input {
+        tx.input.push(TxIn {
+            previous_output: fee_input.outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        });
+        tx.output.push(fee_input.change);
+    }
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    #[test]
+    fn commit() {
+        for taproot in [false, true] {
+            let mut spacechain = Spacechain::new(Network::Regtest, 3, taproot).unwrap();
+            spacechain.validate().unwrap();
+            assert_eq!(spacechain.amount(), Amount::from_sat(990));
+            assert!(spacechain.commit([1; 32], None).is_err());
+
+            spacechain.outpoint = Some(OutPoint::new(Txid::all_zeros(), 0));
+            for block in 0..3 {
+                let (block_tx, commitment_tx) = spacechain.commit([block as u8; 32], None).unwrap();
+                assert_eq!(block_tx.version, Version(3));
+                assert_eq!(block_tx.output[0].script_pubkey, util::anchor_script());
+                // The package relays as long as the child pays for both.
+                let fees = [Amount::ZERO, ANCHOR];
+                let txs = [block_tx.clone(), commitment_tx.clone()];
+                assert!(validate::check_truc(&txs, &fees).unwrap().is_empty());
+                assert_eq!(
+                    commitment_tx.input[0].previous_output,
+                    OutPoint::new(block_tx.txid(), 0)
+                );
+                assert!(commitment_tx.input[0].witness.is_empty());
+                assert_eq!(spacechain.outpoint, Some(OutPoint::new(block_tx.txid(), 1)));
+            }
+            assert!(spacechain.commit([4; 32], None).is_err());
+        }
+    }
+
+    #[test]
+    fn block_count() {
+        assert!(Spacechain::new(Network::Regtest, 0, false).is_err());
+        assert!(Spacechain::new(Network::Regtest, MAX_BLOCKS + 1, false).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime,
+    address::NetworkUnchecked,
+    opcodes::all::OP_NOP4,
+    script::PushBytesBuf,
+    secp256k1::SECP256K1,
+    taproot::{LeafVersion, TaprootSpendInfo},
+    transaction::Version,
+    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+
+use crate::{util::nums_points, validate};
+
+/// Fee paid by every split transaction.
+const SPLIT_FEE: Amount = Amount::from_sat(600);
+
+/// The amount ladder can't have more rungs than this.
+const MAX_DENOMINATIONS: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Recipient {
+    pub(crate) address: Address<NetworkUnchecked>,
+    pub(crate) percent: u32,
+}
+
+/// A reusable donation address which always splits incoming funds between fixed recipients.
+///
+/// CTV commits to exact output amounts, so a single template can only split one exact amount.
+/// To accept donations of different sizes, the splitter uses an amount ladder: the taproot
+/// address has one CTV leaf per accepted denomination, each of which splits that denomination
+/// by the same percentages.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Splitter {
+    pub(crate) network: Network,
+    pub(crate) recipients: Vec<Recipient>,
+    pub(crate) denominations: Vec<Amount>,
+}
+
+impl Splitter {
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        for (idx, recipient) in self.recipients.iter().enumerate() {
+            let field = format!("Recipient {} address", idx + 1);
+            validate::check_network(&field, &recipient.address, self.network)?;
+        }
+        if self.recipients.is_empty() {
+            bail!("Splitter has no recipients");
+        }
+        let percent = self
+            .recipients
+            .iter()
+            .try_fold(0u32, |total, r| total.checked_add(r.percent));
+        if percent != Some(100) {
+            bail!("Recipient percentages must add up to 100");
+        }
+        if self.denominations.is_empty() || self.denominations.len() > MAX_DENOMINATIONS {
+            bail!("Splitter needs between 1 and {MAX_DENOMINATIONS} denominations");
+        }
+        let mut denominations = self.denominations.clone();
+        denominations.sort();
+        denominations.dedup();
+        if denominations.len() != self.denominations.len() {
+            bail!("Denominations must be unique");
+        }
+        let mut warnings = Vec::new();
+        for denomination in &self.denominations {
+            if *denomination <= SPLIT_FEE {
+                bail!("Denomination {denomination} does not cover the split fee");
+            }
+            let split = [self.spending_tx(validate::placeholder_txid(), 0, *denomination)?];
+            warnings.extend(validate::check_txs(
+                self.network,
+                *denomination,
+                &split,
+                &[SPLIT_FEE],
+            )?);
+        }
+        Ok(warnings)
+    }
+
+    pub(crate) fn address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+        let tsi = self.taproot_spend_info()?;
+        Ok(
+            Address::p2tr(SECP256K1, nums_points(), tsi.merkle_root(), self.network)
+                .as_unchecked()
+                .clone(),
+        )
+    }
+
+    /// How a donation of the given denomination is split.
+    pub(crate) fn split(&self, denomination: Amount) -> anyhow::Result<Vec<(Address, Amount)>> {
+        let available = denomination
+            .checked_sub(SPLIT_FEE)
+            .ok_or_else(|| anyhow!("Denomination {denomination} does not cover the split fee"))?;
+        let mut split = self
+            .recipients
+            .iter()
+            .map(|r| {
+                Ok((
+                    r.address.clone().require_network(self.network)?,
+                    Amount::from_sat((available.to_sat() as u128 * r.percent as u128 / 100) as u64),
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Whatever is left over from rounding goes to the last recipient, rather than to the fee.
+        let left = split
+            .iter()
+            .try_fold(available, |left, (_, amount)| left.checked_sub(*amount))
+            .ok_or_else(|| anyhow!("Recipient percentages add up to more than 100"))?;
+        if let Some((_, last)) = split.last_mut() {
+            *last += left;
+        }
+        Ok(split)
+    }
+
+    /// Split a donation. The amount has to be one of the rungs on the amount ladder.
+    pub(crate) fn spending_tx(
+        &self,
+        txid: Txid,
+        vout: u32,
+        amount: Amount,
+    ) -> anyhow::Result<Transaction> {
+        if !self.denominations.contains(&amount) {
+            bail!("{amount} is not one of the denominations accepted by this splitter");
+        }
+        let script = self.split_script(amount)?;
+        let cb = self
+            .taproot_spend_info()?
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or_else(|| anyhow!("Invalid tapscript formation"))?;
+        let mut witness = Witness::new();
+        witness.push(script);
+        witness.push(cb.serialize());
+
+        Ok(Transaction {
+            version: Version::ONE,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint { txid, vout },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ZERO,
+                witness,
+            }],
+            output: self
+                .split(amount)?
+                .into_iter()
+                .map(|(address, value)| TxOut {
+                    value,
+                    script_pubkey: address.script_pubkey(),
+                })
+                .collect(),
+        })
+    }
+
+    fn split_script(&self, denomination: Amount) -> anyhow::Result<ScriptBuf> {
+        let split_hash = PushBytesBuf::try_from(self.split_ctv(denomination)?.ctv()?)?;
+        Ok(bitcoin::script::Builder::new()
+            .push_slice(split_hash)
+            .push_opcode(OP_NOP4)
+            .into_script())
+    }
+
+    fn split_ctv(&self, denomination: Amount) -> anyhow::Result<Context> {
+        Ok(Context {
+            network: self.network,
+            tx_type: TxType::Taproot {
+                internal_key: nums_points(),
+            },
+            fields: Fields {
+                version: Version::ONE,
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::ZERO],
+                outputs: self
+                    .split(denomination)?
+                    .into_iter()
+                    .map(|(address, amount)| Output::Address {
+                        address: address.as_unchecked().clone(),
+                        amount,
+                    })
+                    .collect(),
+                input_idx: 0,
+            },
+        })
+    }
+
+    fn taproot_spend_info(&self) -> anyhow::Result<TaprootSpendInfo> {
+        let leaves = self
+            .denominations
+            .iter()
+            .map(|d| Ok((1, self.split_script(*d)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        TaprootSpendInfo::with_huffman_tree(SECP256K1, nums_points(), leaves)
+            .map_err(|_| anyhow!("Taproot not finalizable"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    fn splitter(percents: &[u32]) -> Splitter {
+        Splitter {
+            network: Network::Regtest,
+            recipients: percents
+                .iter()
+                .enumerate()
+                .map(|(idx, percent)| {
+                    let script = ScriptBuf::from_bytes(vec![idx as u8; 8]);
+                    Recipient {
+                        address: Address::p2wsh(&script, Network::Regtest)
+                            .as_unchecked()
+                            .clone(),
+                        percent: *percent,
+                    }
+                })
+                .collect(),
+            denominations: vec![Amount::from_sat(10_000), Amount::from_sat(100_000)],
+        }
+    }
+
+    #[test]
+    fn split() {
+        let splitter = splitter(&[33, 33, 34]);
+        splitter.validate().unwrap();
+        for denomination in &splitter.denominations {
+            let split = splitter.split(*denomination).unwrap();
+            let paid: Amount = split.iter().map(|(_, amount)| *amount).sum();
+            assert_eq!(paid + SPLIT_FEE, *denomination);
+
+            // The spend pays exactly what its leaf commits to.
+            let tx = splitter
+                .spending_tx(Txid::all_zeros(), 0, *denomination)
+                .unwrap();
+            let template = &splitter
+                .split_ctv(*denomination)
+                .unwrap()
+                .spending_tx(Txid::all_zeros(), 0)
+                .unwrap()[0];
+            assert_eq!(tx.output, template.output);
+            assert_eq!(tx.version, template.version);
+            assert_eq!(tx.input[0].sequence, template.input[0].sequence);
+        }
+        assert!(splitter
+            .spending_tx(Txid::all_zeros(), 0, Amount::from_sat(5_000))
+            .is_err());
+    }
+
+    #[test]
+    fn percentages() {
+        assert!(splitter(&[50, 40]).validate().is_err());
+        assert!(splitter(&[u32::MAX, 101]).validate().is_err());
+        assert!(splitter(&[60, 60]).split(Amount::from_sat(10_000)).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Plain English descriptions of contracts, for users to check before they fund them.
+
+use std::fmt::Write;
+
+use bitcoin::{relative, Amount};
+use ctvlib::{Context, Output};
+
+use crate::{util, validate, vault::Vault};
+
+/// Describe what a CTV template pays out, including the templates in any trees it commits to.
+pub(crate) fn ctv(ctx: &Context) -> anyhow::Result<String> {
+    let mut summary = format!(
+        "Locks funds at {}, which can only be spent by a single transaction.",
+        ctx.address()?
+    );
+    describe_template(&mut summary, ctx, 0);
+    Ok(summary)
+}
+
+/// Describe where a vault's funds can go, and when.
+pub(crate) fn vault(vault: &Vault) -> anyhow::Result<String> {
+    let unvault_tx = &vault
+        .vault_ctv()?
+        .spending_tx(validate::placeholder_txid(), 0)?[0];
+    let unvault_fee = vault.amount - unvault_tx.output[0].value;
+    let payout = vault.cold_spend(unvault_tx.txid(), 0)?.output[0].value;
+    let fees = if vault.anchor {
+        "The transactions pay no fees themselves, and are bumped by spending their anchor outputs"
+            .to_string()
+    } else {
+        format!("Fees: {unvault_fee} per stage")
+    };
+    Ok(format!(
+        "Locks {}. Unvaulting can start at any time. {} after the unvaulting transaction \
+         confirms, the funds can move to the hot address {}, or at any time to the cold address \
+         {}. {fees}, so either address receives {}.",
+        vault.amount,
+        blocks(vault.delay),
+        vault.hot.assume_checked_ref(),
+        vault.cold.assume_checked_ref(),
+        payout,
+    ))
+}
+
+fn describe_template(summary: &mut String, ctx: &Context, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let sequence = ctx.fields.sequences.get(ctx.fields.input_idx as usize);
+    match sequence.and_then(|s| s.to_relative_lock_time()) {
+        Some(relative::LockTime::Blocks(height)) if height.value() > 0 => {
+            let _ = write!(
+                summary,
+                "\n{indent}It can be spent {} after it confirms.",
+                blocks(height.value())
+            );
+        }
+        Some(relative::LockTime::Time(time)) if time.value() > 0 => {
+            let _ = write!(
+                summary,
+                "\n{indent}It can be spent {} seconds after it confirms.",
+                u32::from(time.value()) * 512
+            );
+        }
+        _ => {}
+    }
+    if ctx.fields.locktime.to_consensus_u32() != 0 {
+        let _ = write!(
+            summary,
+            "\n{indent}It can't be spent before {}.",
+            ctx.fields.locktime
+        );
+    }
+
+    let _ = write!(summary, "\n{indent}The spend pays:");
+    for output in &ctx.fields.outputs {
+        match output {
+            output if util::is_anchor(output) => {
+                let _ = write!(
+                    summary,
+                    "\n{indent}- an anchor output, which anyone can spend to pay its fee"
+                );
+            }
+            Output::Address { address, amount } => {
+                let _ = write!(
+                    summary,
+                    "\n{indent}- {amount} to {}",
+                    address.assume_checked_ref()
+                );
+            }
+            Output::Data { data } => {
+                let _ = write!(summary, "\n{indent}- an OP_RETURN output with {data:?}");
+            }
+            Output::Tree { tree, amount } => {
+                let fee = amount.checked_sub(paid(tree)).unwrap_or(Amount::ZERO);
+                let _ = write!(
+                    summary,
+                    "\n{indent}- {amount} into another template, whose spend pays a {fee} fee."
+                );
+                describe_template(summary, tree, depth + 1);
+            }
+        }
+    }
+}
+
+fn paid(ctx: &Context) -> Amount {
+    ctx.fields
+        .outputs
+        .iter()
+        .map(|output| match output {
+            Output::Address { amount, .. } | Output::Tree { amount, .. } => *amount,
+            Output::Data { .. } => Amount::ZERO,
+        })
+        .fold(Amount::ZERO, |total, amount| {
+            total.checked_add(amount).unwrap_or(Amount::MAX)
+        })
+}
+
+fn blocks(count: u16) -> String {
+    match count {
+        1 => "1 block".to_string(),
+        count => format!("{count} blocks"),
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime, address::NetworkUnchecked, hashes::sha256, Address, Amount, Network,
+    OutPoint, Transaction, Txid,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{hashlock::HashlockCovenant, util, validate};
+
+/// A single sale can't issue more tickets than this.
+const MAX_TICKETS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Ticket {
+    /// Where the buyer is refunded if the event is cancelled.
+    pub(crate) refund: Address<NetworkUnchecked>,
+    pub(crate) amount: Amount,
+}
+
+/// A batch of refundable event tickets.
+///
+/// Each buyer pays for their ticket into its own covenant address. After the event date, the
+/// organizer can collect the payment with a CTV transaction. If the event is cancelled, the
+/// organizer publishes the cancel secret, which lets every buyer take their refund instead.
+///
+/// Script can't stop a branch from being taken after a given time, only before it, so the refund
+/// stays open after the event date too. Once the date has passed, a cancelled ticket can go to
+/// whichever of the buyer and the organizer is mined first, so buyers have to get their refunds
+/// confirmed before `event_date`. A relative timelock on the payout wouldn't help: it would only
+/// delay the payout from when the ticket was bought, not from the event date.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct TicketSale {
+    pub(crate) network: Network,
+    pub(crate) organizer: Address<NetworkUnchecked>,
+    /// The event date, as a unix timestamp.
+    pub(crate) event_date: u32,
+    pub(crate) cancel_hash: sha256::Hash,
+    pub(crate) tickets: Vec<Ticket>,
+    pub(crate) taproot: bool,
+}
+
+pub(crate) struct IssuedTicket {
+    pub(crate) index: usize,
+    pub(crate) refund: Address<NetworkUnchecked>,
+    pub(crate) amount: Amount,
+    pub(crate) address: Address<NetworkUnchecked>,
+}
+
+impl TicketSale {
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        validate::check_network("Organizer address", &self.organizer, self.network)?;
+        for (idx, ticket) in self.tickets.iter().enumerate() {
+            let field = format!("Attendee {} refund address", idx + 1);
+            validate::check_network(&field, &ticket.refund, self.network)?;
+        }
+        if self.tickets.is_empty() {
+            bail!("Ticket sale has no attendees");
+        }
+        if self.tickets.len() > MAX_TICKETS {
+            bail!("Ticket sale can't issue more than {MAX_TICKETS} tickets");
+        }
+        let mut warnings = Vec::new();
+        for ticket in 0..self.tickets.len() {
+            warnings.extend(self.covenant(ticket)?.validate()?);
+        }
+        Ok(warnings)
+    }
+
+    pub(crate) fn event_date(&self) -> String {
+        util::format_date(self.event_date)
+    }
+
+    /// The covenant address of every ticket, in attendee list order.
+    pub(crate) fn issue(&self) -> anyhow::Result<Vec<IssuedTicket>> {
+        self.tickets
+            .iter()
+            .enumerate()
+            .map(|(index, ticket)| {
+                Ok(IssuedTicket {
+                    index,
+                    refund: ticket.refund.clone(),
+                    amount: ticket.amount,
+                    address: self.covenant(index)?.address()?,
+                })
+            })
+            .collect()
+    }
+
+    /// The organizer's payout of a ticket, which can be mined after the event date.
+    pub(crate) fn payout_tx(
+        &self,
+        ticket: usize,
+        txid: Txid,
+        vout: u32,
+    ) -> anyhow::Result<Transaction> {
+        Ok(self
+            .covenant(ticket)?
+            .timelock_spend(OutPoint { txid, vout })?)
+    }
+
+    /// The buyer's refund of a ticket, which needs the cancel secret. It's only guaranteed to
+    /// win if it confirms before the event date, after which the organizer's payout is valid too.
+    pub(crate) fn refund_tx(
+        &self,
+        ticket: usize,
+        txid: Txid,
+        vout: u32,
+        secret: &[u8],
+    ) -> anyhow::Result<Transaction> {
+        Ok(self
+            .covenant(ticket)?
+            .hashlock_spend(OutPoint { txid, vout }, secret)?)
+    }
+
+    pub(crate) fn covenant(&self, ticket: usize) -> anyhow::Result<HashlockCovenant> {
+        let ticket = self
+            .tickets
+            .get(ticket)
+            .ok_or_else(|| anyhow!("Ticket sale has no ticket {ticket}"))?;
+        Ok(HashlockCovenant {
+            network: self.network,
+            amount: ticket.amount,
+            hash: self.cancel_hash,
+            hashlock_address: ticket.refund.clone(),
+            timelock_address: self.organizer.clone(),
+            locktime: LockTime::from_time(self.event_date)?,
+            taproot: self.taproot,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        hashes::{sha256, Hash},
+        ScriptBuf,
+    };
+
+    use super::*;
+
+    fn sale(tickets: usize) -> TicketSale {
+        let address = |byte: u8| {
+            let script = ScriptBuf::from_bytes(vec![byte; 8]);
+            Address::p2wsh(&script, Network::Regtest)
+                .as_unchecked()
+                .clone()
+        };
+        TicketSale {
+            network: Network::Regtest,
+            organizer: address(0),
+            event_date: 1_767_225_600,
+            cancel_hash: sha256::Hash::hash(b"cancelled"),
+            tickets: (0..tickets)
+                .map(|idx| Ticket {
+                    refund: address(idx as u8 + 1),
+                    amount: Amount::from_sat(50_000),
+                })
+                .collect(),
+            taproot: false,
+        }
+    }
+
+    #[test]
+    fn issue_and_spend() {
+        let sale = sale(3);
+        sale.validate().unwrap();
+        let issued = sale.issue().unwrap();
+        assert_eq!(issued.len(), 3);
+        assert_ne!(issued[0].address, issued[1].address);
+
+        let payout = sale.payout_tx(1, Txid::all_zeros(), 0).unwrap();
+        let organizer = sale.organizer.assume_checked_ref().script_pubkey();
+        assert_eq!(payout.output[0].script_pubkey, organizer);
+        assert_eq!(
+            payout.lock_time,
+            LockTime::from_time(sale.event_date).unwrap()
+        );
+
+        let refund = sale
+            .refund_tx(1, Txid::all_zeros(), 0, b"cancelled")
+            .unwrap();
+        let buyer = sale.tickets[1].refund.assume_checked_ref().script_pubkey();
+        assert_eq!(refund.output[0].script_pubkey, buyer);
+        assert!(sale.refund_tx(1, Txid::all_zeros(), 0, b"wrong").is_err());
+        assert!(sale.payout_tx(3, Txid::all_zeros(), 0).is_err());
+    }
+
+    #[test]
+    fn needs_attendees() {
+        assert!(sale(0).validate().is_err());
+    }
+}
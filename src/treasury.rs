@@ -0,0 +1,241 @@
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime, address::NetworkUnchecked, transaction::Version, Address, Amount, Network,
+    Sequence, Transaction, Txid,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::{
+    util::{self, nums_points},
+    validate,
+    vault::Vault,
+};
+
+/// Fee paid by the transaction which splits the treasury into department vaults.
+const SPLIT_FEE: Amount = Amount::from_sat(600);
+
+/// A department may not be split into more vaults than this.
+const MAX_TRANCHES: u64 = 100;
+
+/// A department's budget, as written in the org definition file.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Department {
+    pub(crate) name: String,
+    #[serde_as(as = "util::AmountWithUnits")]
+    pub(crate) budget: Amount,
+    /// The most the department can unvault at once.
+    #[serde_as(as = "util::AmountWithUnits")]
+    pub(crate) limit: Amount,
+    /// Blocks before an unvaulted tranche can reach the department's hot wallet.
+    pub(crate) delay: u16,
+    pub(crate) hot: Address<NetworkUnchecked>,
+    pub(crate) cold: Address<NetworkUnchecked>,
+}
+
+/// A corporate treasury whose root output commits to a set of per-department vaults.
+///
+/// Each department's budget is split into tranches no larger than its spending limit, and each
+/// tranche is locked in its own vault, so the department can never move more than its limit
+/// without waiting out the vault delay, and the treasurer can always sweep a tranche to cold
+/// storage.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Treasury {
+    pub(crate) network: Network,
+    pub(crate) departments: Vec<Department>,
+    pub(crate) taproot: bool,
+}
+
+pub(crate) struct Tranche {
+    pub(crate) department: String,
+    pub(crate) vout: u32,
+    pub(crate) vault: Vault,
+}
+
+impl Treasury {
+    pub(crate) fn validate(&self) -> anyhow::Result<Vec<String>> {
+        for department in &self.departments {
+            let field = format!("{} hot address", department.name);
+            validate::check_network(&field, &department.hot, self.network)?;
+            let field = format!("{} cold address", department.name);
+            validate::check_network(&field, &department.cold, self.network)?;
+        }
+        if self.departments.is_empty() {
+            bail!("Treasury has no departments");
+        }
+        for department in &self.departments {
+            if department.limit == Amount::ZERO || department.budget == Amount::ZERO {
+                bail!("Department {} has no budget", department.name);
+            }
+            if department
+                .budget
+                .to_sat()
+                .div_ceil(department.limit.to_sat())
+                > MAX_TRANCHES
+            {
+                bail!(
+                    "Department {} would need more than {MAX_TRANCHES} vaults, raise its limit",
+                    department.name
+                );
+            }
+        }
+        let mut warnings = validate::check_tree(&self.root_ctv()?, self.amount()?, SPLIT_FEE)?;
+        for tranche in self.tranches() {
+            warnings.extend(tranche.vault.validate()?);
+        }
+        Ok(warnings)
+    }
+
+    /// The amount to lock into the treasury: every budget plus the fee of the split.
+    pub(crate) fn amount(&self) -> anyhow::Result<Amount> {
+        self.departments
+            .iter()
+            .try_fold(SPLIT_FEE, |total, d| total.checked_add(d.budget))
+            .ok_or_else(|| anyhow!("Budgets add up to more than 21 million BTC"))
+    }
+
+    pub(crate) fn address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+        Ok(self.root_ctv()?.address()?.as_unchecked().clone())
+    }
+
+    /// Every department vault, in the order of the outputs of the split transaction.
+    pub(crate) fn tranches(&self) -> Vec<Tranche> {
+        let mut tranches = Vec::new();
+        for department in &self.departments {
+            let mut remaining = department.budget;
+            while remaining > Amount::ZERO {
+                let amount = remaining.min(department.limit);
+                remaining -= amount;
+                tranches.push(Tranche {
+                    department: department.name.clone(),
+                    vout: tranches.len() as u32,
+                    vault: Vault {
+                        hot: department.hot.clone(),
+                        cold: department.cold.clone(),
+                        amount,
+                        network: self.network,
+                        delay: department.delay,
+                        taproot: self.taproot,
+                        internal_key: None,
+                        fee_rate: None,
+                        anchor: false,
+                    },
+                });
+            }
+        }
+        tranches
+    }
+
+    /// The transaction which splits the treasury into department vaults.
+    pub(crate) fn split_tx(&self, txid: Txid, vout: u32) -> anyhow::Result<Transaction> {
+        Ok(util::spending_tx(&self.root_ctv()?, txid, vout)?)
+    }
+
+    fn root_ctv(&self) -> anyhow::Result<Context> {
+        let outputs = self
+            .tranches()
+            .into_iter()
+            .map(|tranche| {
+                Ok(Output::Address {
+                    address: tranche.vault.vault_address()?,
+                    amount: tranche.vault.amount,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Context {
+            network: self.network,
+            tx_type: self.tx_type(),
+            fields: Fields {
+                version: Version::ONE,
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::ZERO],
+                outputs,
+                input_idx: 0,
+            },
+        })
+    }
+
+    fn tx_type(&self) -> TxType {
+        if self.taproot {
+            return TxType::Taproot {
+                internal_key: nums_points(),
+            };
+        }
+        TxType::Segwit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, ScriptBuf};
+
+    use super::*;
+
+    fn department(name: &str, budget: u64, limit: u64) -> Department {
+        let address = |byte: u8| {
+            let script = ScriptBuf::from_bytes(vec![byte; 8]);
+            Address::p2wsh(&script, Network::Regtest)
+                .as_unchecked()
+                .clone()
+        };
+        Department {
+            name: name.to_string(),
+            budget: Amount::from_sat(budget),
+            limit: Amount::from_sat(limit),
+            delay: 144,
+            hot: address(1),
+            cold: address(2),
+        }
+    }
+
+    fn treasury(departments: Vec<Department>) -> Treasury {
+        Treasury {
+            network: Network::Regtest,
+            departments,
+            taproot: false,
+        }
+    }
+
+    #[test]
+    fn split() {
+        let treasury = treasury(vec![
+            department("Ops", 250_000, 100_000),
+            department("Payroll", 50_000, 100_000),
+        ]);
+        treasury.validate().unwrap();
+        assert_eq!(treasury.amount().unwrap(), Amount::from_sat(300_600));
+
+        // Ops is split into three tranches under its limit, and Payroll fits in one.
+        let tranches = treasury.tranches();
+        let amounts: Vec<u64> = tranches.iter().map(|t| t.vault.amount.to_sat()).collect();
+        assert_eq!(amounts, [100_000, 100_000, 50_000, 50_000]);
+        assert_eq!(tranches[3].department, "Payroll");
+
+        // Each output of the split funds the vault of its tranche.
+        let split = treasury.split_tx(Txid::all_zeros(), 0).unwrap();
+        for tranche in &tranches {
+            let output = &split.output[tranche.vout as usize];
+            let address = tranche.vault.vault_address().unwrap();
+            assert_eq!(
+                output.script_pubkey,
+                address.assume_checked().script_pubkey()
+            );
+            assert_eq!(output.value, tranche.vault.amount);
+        }
+    }
+
+    #[test]
+    fn rejects() {
+        assert!(treasury(vec![]).validate().is_err());
+        assert!(treasury(vec![department("Ops", 1_000_000, 1_000)])
+            .validate()
+            .is_err());
+        let overflow = treasury(vec![
+            department("Ops", u64::MAX, u64::MAX),
+            department("Payroll", 1, 1),
+        ]);
+        assert!(overflow.amount().is_err());
+    }
+}
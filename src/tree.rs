@@ -0,0 +1,372 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use bitcoin::{
+    absolute::LockTime, address::NetworkUnchecked, transaction::Version, Address, Amount, FeeRate,
+    Network, Sequence, Transaction, Txid,
+};
+use ctvlib::{Context, Fields, Output, TxType};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    util,
+    validate::{self, Footprint},
+};
+
+/// How many outputs each transaction in a tree has, unless set with [`TreeBuilder::radix`].
+const DEFAULT_RADIX: usize = 4;
+
+/// Builds a congestion control tree: a single output which commits to paying out many payouts,
+/// through a tree of CTV templates which anyone can unroll later when fees are low.
+///
+/// Each template pays up to `radix` outputs. When there are more payouts than that, they're split
+/// into `radix` groups as even as possible, each paid through a subtree of its own.
+pub(crate) struct TreeBuilder {
+    network: Network,
+    payouts: Vec<(Address<NetworkUnchecked>, Amount)>,
+    radix: usize,
+    fee_budget: Option<Amount>,
+    fee_rate: Option<u64>,
+    taproot: bool,
+}
+
+/// A built tree, with what it has to be funded with.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Tree {
+    pub(crate) ctv: Context,
+    pub(crate) amount: Amount,
+    /// The fee every transaction in the tree pays.
+    pub(crate) fee: Amount,
+}
+
+impl TreeBuilder {
+    pub(crate) fn new(
+        network: Network,
+        payouts: Vec<(Address<NetworkUnchecked>, Amount)>,
+    ) -> TreeBuilder {
+        TreeBuilder {
+            network,
+            payouts,
+            radix: DEFAULT_RADIX,
+            fee_budget: None,
+            fee_rate: None,
+            taproot: false,
+        }
+    }
+
+    pub(crate) fn radix(mut self, radix: usize) -> TreeBuilder {
+        self.radix = radix;
+        self
+    }
+
+    /// The fees of the whole tree together. It's split evenly between its transactions, and
+    /// whatever doesn't divide evenly isn't spent.
+    pub(crate) fn fee_budget(mut self, fee_budget: Amount) -> TreeBuilder {
+        self.fee_budget = Some(fee_budget);
+        self
+    }
+
+    /// The fee rate of the tree's transactions, in sat/vB, instead of a budget. Every transaction
+    /// pays enough for the largest of them at this rate.
+    pub(crate) fn fee_rate(mut self, fee_rate: u64) -> TreeBuilder {
+        self.fee_rate = Some(fee_rate);
+        self
+    }
+
+    pub(crate) fn taproot(mut self, taproot: bool) -> TreeBuilder {
+        self.taproot = taproot;
+        self
+    }
+
+    pub(crate) fn build(&self) -> anyhow::Result<Tree> {
+        if self.radix < 2 {
+            bail!("A tree needs a radix of at least 2, not {}", self.radix);
+        }
+        if self.payouts.is_empty() {
+            bail!("A tree needs at least one payout");
+        }
+        validate::TreeLimits::from_env()?
+            .check_shape(self.payouts.len(), self.depth(self.payouts.len()))?;
+        for (idx, (address, amount)) in self.payouts.iter().enumerate() {
+            let field = format!("Payout {} address", idx + 1);
+            validate::check_network(&field, address, self.network)?;
+            if *amount == Amount::ZERO {
+                bail!("Payout {} is zero", idx + 1);
+            }
+        }
+        let fee = self.fee()?;
+        let (ctv, amount) = self.template(&self.payouts, fee)?;
+        Ok(Tree { ctv, amount, fee })
+    }
+
+    /// The fee each transaction in the tree pays.
+    fn fee(&self) -> anyhow::Result<Amount> {
+        match (self.fee_budget, self.fee_rate) {
+            (Some(budget), None) => {
+                let transactions = self.transactions(self.payouts.len());
+                let fee = budget / transactions as u64;
+                // A budget split too thin would leave transactions which no node relays.
+                let minimum = Amount::from_sat(
+                    (validate::FeeLimits::from_env()?.min * self.largest()? as f64).ceil() as u64,
+                );
+                if fee < minimum {
+                    bail!(
+                        "Fee budget {budget} gives each of the {transactions} transactions {fee}, but the largest needs at least {minimum} to be relayed"
+                    );
+                }
+                Ok(fee)
+            }
+            (None, Some(rate)) => {
+                let fee_rate = FeeRate::from_sat_per_vb(rate)
+                    .ok_or_else(|| anyhow!("Fee rate {rate} sat/vB is too high"))?;
+                fee_rate
+                    .fee_vb(self.largest()?)
+                    .ok_or_else(|| anyhow!("Fee rate {rate} sat/vB is too high"))
+            }
+            (Some(_), Some(_)) => bail!("A tree takes a fee budget or a fee rate, not both"),
+            (None, None) => bail!("A tree needs a fee budget or a fee rate"),
+        }
+    }
+
+    /// The vsize of the largest transaction in the tree. Only the amounts depend on the fee, and
+    /// amounts are always the same size, so the tree without fees has transactions of the same
+    /// size.
+    fn largest(&self) -> anyhow::Result<u64> {
+        let (ctv, _) = self.template(&self.payouts, Amount::ZERO)?;
+        Ok(util::spending_txs(&ctv, validate::placeholder_txid(), 0)?
+            .iter()
+            .map(|tx| tx.vsize() as u64)
+            .max()
+            .unwrap_or_default())
+    }
+
+    /// How many transactions deep a tree paying this many payouts is.
+    fn depth(&self, payouts: usize) -> usize {
+        if payouts <= self.radix {
+            return 1;
+        }
+        1 + self.depth(payouts.div_ceil(self.radix))
+    }
+
+    /// How many transactions a tree paying this many payouts has.
+    fn transactions(&self, payouts: usize) -> usize {
+        if payouts <= self.radix {
+            return 1;
+        }
+        let group = payouts.div_ceil(self.radix);
+        let mut transactions = 1;
+        let mut remaining = payouts;
+        while remaining > 0 {
+            let size = group.min(remaining);
+            if size > 1 {
+                transactions += self.transactions(size);
+            }
+            remaining -= size;
+        }
+        transactions
+    }
+
+    /// The template paying these payouts, and the amount it has to be funded with.
+    fn template(
+        &self,
+        payouts: &[(Address<NetworkUnchecked>, Amount)],
+        fee: Amount,
+    ) -> anyhow::Result<(Context, Amount)> {
+        let mut outputs = Vec::new();
+        if payouts.len() <= self.radix {
+            for (address, amount) in payouts {
+                outputs.push(Output::Address {
+                    address: address.clone(),
+                    amount: *amount,
+                });
+            }
+        } else {
+            for group in payouts.chunks(payouts.len().div_ceil(self.radix)) {
+                match group {
+                    [(address, amount)] => outputs.push(Output::Address {
+                        address: address.clone(),
+                        amount: *amount,
+                    }),
+                    group => {
+                        let (tree, amount) = self.template(group, fee)?;
+                        outputs.push(Output::Tree {
+                            tree: Box::new(tree),
+                            amount,
+                        });
+                    }
+                }
+            }
+        }
+        let amount = outputs
+            .iter()
+            .map(|output| match output {
+                Output::Address { amount, .. } | Output::Tree { amount, .. } => *amount,
+                Output::Data { .. } => Amount::ZERO,
+            })
+            .try_fold(fee, |total, amount| total.checked_add(amount))
+            .ok_or_else(|| anyhow!("Payouts add up to more than 21 million BTC"))?;
+        let tx_type = if self.taproot {
+            TxType::Taproot {
+                internal_key: util::nums_points(),
+            }
+        } else {
+            TxType::Segwit
+        };
+        let ctv = Context {
+            network: self.network,
+            tx_type,
+            fields: Fields {
+                version: Version::ONE,
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::ZERO],
+                outputs,
+                input_idx: 0,
+            },
+        };
+        Ok((ctv, amount))
+    }
+}
+
+impl Tree {
+    pub(crate) fn validate(&self) -> anyhow::Result<(Vec<String>, Footprint)> {
+        let footprint = validate::measure_tree(&self.ctv, self.fee)?;
+        let warnings = validate::check_tree(&self.ctv, self.amount, self.fee)?;
+        Ok((warnings, footprint))
+    }
+
+    /// Every transaction which unrolls the tree, in broadcast order.
+    pub(crate) fn transactions(&self, txid: Txid, vout: u32) -> anyhow::Result<Vec<Transaction>> {
+        Ok(util::spending_txs(&self.ctv, txid, vout)?)
+    }
+}
+
+/// Payouts as CSV, one `address,amount` per line, as the tree page and `tree build` take them.
+pub(crate) fn parse_payouts(
+    payouts: &str,
+) -> anyhow::Result<Vec<(Address<NetworkUnchecked>, Amount)>> {
+    let mut parsed = Vec::new();
+    for (idx, line) in payouts
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+    {
+        let mut fields = line.trim().split(',');
+        let address = fields
+            .next()
+            .ok_or_else(|| anyhow!("Line {} is missing an address", idx + 1))?;
+        let amount = fields
+            .next()
+            .ok_or_else(|| anyhow!("Line {} is missing an amount", idx + 1))?;
+        parsed.push((
+            Address::from_str(address.trim())?,
+            util::parse_amount(amount)?,
+        ));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::ScriptBuf;
+
+    use super::*;
+    use crate::error::CdvError;
+
+    fn payouts(count: usize) -> Vec<(Address<NetworkUnchecked>, Amount)> {
+        (0..count)
+            .map(|idx| {
+                let script = ScriptBuf::from_bytes(vec![idx as u8; 8]);
+                let address = Address::p2wsh(&script, Network::Regtest);
+                (address.as_unchecked().clone(), Amount::from_sat(10_000))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn build() {
+        let tree = TreeBuilder::new(Network::Regtest, payouts(3))
+            .fee_budget(Amount::from_sat(600))
+            .build()
+            .unwrap();
+        assert_eq!(tree.fee, Amount::from_sat(600));
+        assert_eq!(tree.amount, Amount::from_sat(30_600));
+        assert_eq!(tree.ctv.fields.outputs.len(), 3);
+    }
+
+    #[test]
+    fn validates() {
+        // Every template in the tree is version 1 with a sequence of zero.
+        let tree = TreeBuilder::new(Network::Regtest, payouts(4))
+            .radix(2)
+            .fee_budget(Amount::from_sat(1_000))
+            .build()
+            .unwrap();
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn fee_split() {
+        // Two subtrees of two payouts under the root, so three transactions share the budget.
+        let builder = TreeBuilder::new(Network::Regtest, payouts(4)).radix(2);
+        assert_eq!(builder.transactions(4), 3);
+        assert_eq!(builder.depth(4), 2);
+        let tree = builder.fee_budget(Amount::from_sat(1_000)).build().unwrap();
+        assert_eq!(tree.fee, Amount::from_sat(333));
+        assert_eq!(tree.amount, Amount::from_sat(40_000 + 333 * 3));
+
+        let tree = TreeBuilder::new(Network::Regtest, payouts(4))
+            .radix(2)
+            .fee_rate(2)
+            .build()
+            .unwrap();
+        let largest = tree
+            .transactions(validate::placeholder_txid(), 0)
+            .unwrap()
+            .iter()
+            .map(|tx| tx.vsize() as u64)
+            .max()
+            .unwrap();
+        assert_eq!(tree.fee, Amount::from_sat(2 * largest));
+    }
+
+    #[test]
+    fn budget_too_small() {
+        // Three transactions can't each pay a relayable fee out of 30 sats.
+        let builder = TreeBuilder::new(Network::Regtest, payouts(4)).radix(2);
+        let err = builder
+            .fee_budget(Amount::from_sat(30))
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("to be relayed"));
+    }
+
+    #[test]
+    fn needs_a_fee() {
+        let builder = TreeBuilder::new(Network::Regtest, payouts(3));
+        assert!(builder.build().is_err());
+        let builder = builder.fee_budget(Amount::from_sat(600)).fee_rate(1);
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let payouts = (0..1_001)
+            .map(|idx: u32| {
+                let script = ScriptBuf::from_bytes(idx.to_le_bytes().to_vec());
+                let address = Address::p2wsh(&script, Network::Regtest);
+                (address.as_unchecked().clone(), Amount::from_sat(10_000))
+            })
+            .collect();
+        let builder = TreeBuilder::new(Network::Regtest, payouts).fee_budget(Amount::from_sat(600));
+        let err = builder.build().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CdvError>(),
+            Some(CdvError::TreeLeaves { leaves: 1_001, .. })
+        ));
+        let builder = TreeBuilder::new(Network::Regtest, self::payouts(200))
+            .radix(2)
+            .fee_budget(Amount::from_sat(100_000));
+        assert_eq!(builder.depth(200), 8);
+        builder.build().unwrap();
+    }
+}
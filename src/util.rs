@@ -1,10 +1,370 @@
+use std::thread;
+
+use bitcoin::{
+    hashes::Hash,
+    secp256k1::rand::{seq::SliceRandom, thread_rng},
+    Address, Amount, Denomination, Network, ScriptBuf, Transaction, TxOut, Txid, XOnlyPublicKey,
+};
+use ctvlib::{Context, Fields, Output};
+use once_cell::sync::Lazy;
 use regex::Regex;
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_with::{DeserializeAs, SerializeAs};
+
+use crate::error::CdvError;
+
+static OPCODE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(OP_\w+)").unwrap());
+static HEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"([0-9a-z]{64})").unwrap());
+
+/// Where a highlighted script is going to be shown.
+#[derive(Debug, Clone, Copy)]
+pub enum Highlight {
+    Html,
+    /// ANSI escape codes, for terminals.
+    Ansi,
+}
 
 pub fn colorize(script: &str) -> String {
-    let opcode = Regex::new(r"(OP_\w+)").unwrap();
-    let hex = Regex::new(r"([0-9a-z]{64})").unwrap();
-    let color = opcode.replace_all(script, r#"<span style="color: red">$1</span>"#);
-    let color = hex.replace_all(&color, r#"<span style="color: green">$1</span>"#);
+    highlight(script, Highlight::Html)
+}
+
+/// Color the opcodes of a script red and its hashes green, and show OP_NOP4 as OP_CTV.
+pub fn highlight(script: &str, style: Highlight) -> String {
+    let (opcode, hash) = match style {
+        Highlight::Html => (
+            r#"<span style="color: red">$1</span>"#,
+            r#"<span style="color: green">$1</span>"#,
+        ),
+        Highlight::Ansi => ("\x1b[31m$1\x1b[0m", "\x1b[32m$1\x1b[0m"),
+    };
+    let color = OPCODE.replace_all(script, opcode);
+    let color = HEX.replace_all(&color, hash);
 
     color.replace("OP_NOP4", "OP_CTV")
 }
+
+pub fn nums_points() -> XOnlyPublicKey {
+    ctvlib::util::hash2curve(b"Activate CTV now!")
+}
+
+/// The script of a pay-to-anchor (P2A) output: `OP_1 <0x4e73>`, which anyone can spend with an
+/// empty witness.
+pub fn anchor_script() -> ScriptBuf {
+    ScriptBuf::from_bytes(vec![0x51, 0x02, 0x4e, 0x73])
+}
+
+/// A zero-value P2A output, so that a TRUC transaction which pays no fee of its own can be bumped
+/// by a child spending it.
+pub fn anchor_output(network: Network) -> Output {
+    let address =
+        Address::from_script(&anchor_script(), network).expect("P2A is a witness v1 program");
+    Output::Address {
+        address: address.as_unchecked().clone(),
+        amount: Amount::ZERO,
+    }
+}
+
+pub fn is_anchor(output: &Output) -> bool {
+    match output {
+        Output::Address { address, .. } => {
+            address.assume_checked_ref().script_pubkey() == anchor_script()
+        }
+        Output::Data { .. } | Output::Tree { .. } => false,
+    }
+}
+
+/// How the outputs of a template are ordered. The order is fixed once the template is hashed, so
+/// it has to be picked when the template is built.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputOrder {
+    /// The order the outputs were given in.
+    #[default]
+    Insertion,
+    /// By amount and then by script, as in BIP-69, so the order doesn't reveal anything.
+    Bip69,
+    /// A random order.
+    Random,
+}
+
+/// Reorder the outputs of a template, and of every tree under it.
+pub fn order_outputs(ctx: &mut Context, order: OutputOrder) -> anyhow::Result<()> {
+    // A tree's address depends on its order, so the trees have to be ordered first.
+    for output in &mut ctx.fields.outputs {
+        if let Output::Tree { tree, .. } = output {
+            order_outputs(tree, order)?;
+        }
+    }
+    match order {
+        OutputOrder::Insertion => {}
+        OutputOrder::Bip69 => {
+            let txouts = spending_tx(ctx, Txid::all_zeros(), 0)?.output;
+            let mut outputs: Vec<(TxOut, Output)> = txouts
+                .into_iter()
+                .zip(ctx.fields.outputs.drain(..))
+                .collect();
+            outputs.sort_by(|(a, _), (b, _)| {
+                (a.value, a.script_pubkey.as_bytes()).cmp(&(b.value, b.script_pubkey.as_bytes()))
+            });
+            ctx.fields.outputs = outputs.into_iter().map(|(_, output)| output).collect();
+        }
+        OutputOrder::Random => ctx.fields.outputs.shuffle(&mut thread_rng()),
+    }
+    Ok(())
+}
+
+/// The transaction which spends a CTV template, without building the spends of any trees it
+/// commits to.
+pub fn spending_tx(ctx: &Context, txid: Txid, vout: u32) -> Result<Transaction, CdvError> {
+    // Paying a tree's address produces the same output as committing to the tree itself, so the
+    // template hash and the spend are the same.
+    let outputs = ctx
+        .fields
+        .outputs
+        .iter()
+        .map(|output| {
+            Ok(match output {
+                Output::Tree { tree, amount } => Output::Address {
+                    address: tree.address()?.as_unchecked().clone(),
+                    amount: *amount,
+                },
+                Output::Address { address, amount } => Output::Address {
+                    address: address.clone(),
+                    amount: *amount,
+                },
+                Output::Data { data } => Output::Data { data: data.clone() },
+            })
+        })
+        .collect::<Result<_, CdvError>>()?;
+    let shallow = Context {
+        network: ctx.network,
+        tx_type: ctx.tx_type,
+        fields: Fields {
+            version: ctx.fields.version,
+            locktime: ctx.fields.locktime,
+            sequences: ctx.fields.sequences.clone(),
+            outputs,
+            input_idx: ctx.fields.input_idx,
+        },
+    };
+    shallow
+        .spending_tx(txid, vout)?
+        .into_iter()
+        .next()
+        .ok_or(CdvError::NoSpendingTx)
+}
+
+/// Every transaction of a CTV template and the trees it commits to, in broadcast order, like
+/// `Context::spending_tx`. The subtrees don't depend on each other, so they're built on separate
+/// threads.
+pub fn spending_txs(ctx: &Context, txid: Txid, vout: u32) -> Result<Vec<Transaction>, CdvError> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    spending_txs_with(ctx, txid, vout, threads)
+}
+
+fn spending_txs_with(
+    ctx: &Context,
+    txid: Txid,
+    vout: u32,
+    threads: usize,
+) -> Result<Vec<Transaction>, CdvError> {
+    let trees = ctx
+        .fields
+        .outputs
+        .iter()
+        .filter(|o| matches!(o, Output::Tree { .. }))
+        .count();
+    if trees == 0 || threads <= 1 {
+        return Ok(ctx.spending_tx(txid, vout)?);
+    }
+
+    let tx = spending_tx(ctx, txid, vout)?;
+    let parent = tx.txid();
+    let threads = (threads / trees).max(1);
+    let chains = thread::scope(|scope| {
+        let handles = ctx
+            .fields
+            .outputs
+            .iter()
+            .enumerate()
+            .filter_map(|(vout, output)| match output {
+                Output::Tree { tree, .. } => {
+                    Some(scope.spawn(move || spending_txs_with(tree, parent, vout as u32, threads)))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().map_err(|_| CdvError::SubtreePanicked)?)
+            .collect::<Result<Vec<_>, CdvError>>()
+    })?;
+
+    let mut txs = vec![tx];
+    txs.extend(chains.into_iter().flatten());
+    Ok(txs)
+}
+
+/// The template hash of a CTV template, ready to be pushed onto the stack.
+pub fn template_hash(ctx: &Context) -> Result<[u8; 32], CdvError> {
+    ctx.ctv()?
+        .try_into()
+        .map_err(|hash: Vec<u8>| CdvError::HashLength(hash.len()))
+}
+
+/// Parse a `YYYY-MM-DD` date into a unix timestamp at midnight UTC.
+pub fn parse_date(date: &str) -> anyhow::Result<u32> {
+    let mut parts = date.trim().splitn(3, '-');
+    let mut next = || -> anyhow::Result<i64> {
+        Ok(parts
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Invalid date {date}, expected YYYY-MM-DD"))?
+            .parse()?)
+    };
+    let (year, month, day) = (next()?, next()?, next()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        anyhow::bail!("Invalid date {date}, expected YYYY-MM-DD");
+    }
+
+    // Days since the unix epoch, from Howard Hinnant's `days_from_civil`.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    u32::try_from(days * 86400).map_err(|_| anyhow::anyhow!("Date {date} is out of range"))
+}
+
+/// Format a unix timestamp as a `YYYY-MM-DD` date.
+pub fn format_date(timestamp: u32) -> String {
+    // Howard Hinnant's `civil_from_days`.
+    let days = timestamp as i64 / 86400 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Parse an amount written in BTC (`0.5 BTC`, `0.5btc`), in sats (`50000 sats`, `50000sat`), or as
+/// a plain number of sats (`50000`). Units aren't case sensitive.
+pub fn parse_amount(amount: &str) -> anyhow::Result<Amount> {
+    let amount = amount.trim();
+    let (value, unit) = amount.split_at(
+        amount
+            .find(|c: char| c.is_ascii_alphabetic())
+            .unwrap_or(amount.len()),
+    );
+    let denomination = match unit.to_ascii_lowercase().as_str() {
+        "" | "sat" | "sats" | "satoshi" | "satoshis" => Denomination::Satoshi,
+        "btc" => Denomination::Bitcoin,
+        _ => anyhow::bail!("Invalid amount {amount}, expected BTC or sats"),
+    };
+    Amount::from_str_in(value.trim(), denomination)
+        .map_err(|e| anyhow::anyhow!("Invalid amount {amount}: {e}"))
+}
+
+/// A `serde_as` adapter for amounts, which serializes them with their unit and deserializes them
+/// with [`parse_amount`], or from a plain number of sats in JSON.
+pub struct AmountWithUnits;
+
+impl SerializeAs<Amount> for AmountWithUnits {
+    fn serialize_as<S: Serializer>(amount: &Amount, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(amount)
+    }
+}
+
+impl<'de> DeserializeAs<'de, Amount> for AmountWithUnits {
+    fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Amount, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Written {
+            Sats(u64),
+            Text(String),
+        }
+        match Written::deserialize(deserializer)? {
+            Written::Sats(sats) => Ok(Amount::from_sat(sats)),
+            Written::Text(text) => parse_amount(&text).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Serialize;
+    use serde_with::serde_as;
+
+    use super::*;
+
+    /// Amounts are accepted with or without units, and written back in a form which parses to
+    /// the same amount.
+    #[serde_as]
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Budget {
+        #[serde_as(as = "AmountWithUnits")]
+        amount: Amount,
+    }
+
+    #[test]
+    fn units() {
+        let half = Amount::from_sat(50_000_000);
+        for written in [
+            "0.5 BTC",
+            "0.5btc",
+            " 0.5 Btc ",
+            "50000000",
+            "50000000 sats",
+            "50000000sat",
+        ] {
+            assert_eq!(parse_amount(written).unwrap(), half, "{written}");
+        }
+        assert_eq!(parse_amount("1 satoshi").unwrap(), Amount::from_sat(1));
+    }
+
+    #[test]
+    fn invalid() {
+        for written in [
+            "",
+            "BTC",
+            "0.5",
+            "1.5 sats",
+            "-1",
+            "1 eth",
+            "0.000000001 BTC",
+            "1 mBTC",
+        ] {
+            assert!(parse_amount(written).is_err(), "{written}");
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for sats in [0, 1, 546, 50_000, 100_000_000, 2_100_000_000_000_000] {
+            let amount = Amount::from_sat(sats);
+            assert_eq!(parse_amount(&amount.to_string()).unwrap(), amount);
+
+            let budget = Budget { amount };
+            let json = serde_json::to_string(&budget).unwrap();
+            assert_eq!(serde_json::from_str::<Budget>(&json).unwrap(), budget);
+        }
+    }
+
+    #[test]
+    fn json() {
+        for json in [
+            r#"{"amount": 50000}"#,
+            r#"{"amount": "50000"}"#,
+            r#"{"amount": "50000 sats"}"#,
+            r#"{"amount": "0.0005 BTC"}"#,
+        ] {
+            let budget: Budget = serde_json::from_str(json).unwrap();
+            assert_eq!(budget.amount, Amount::from_sat(50_000), "{json}");
+        }
+    }
+}
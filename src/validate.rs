@@ -0,0 +1,1407 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::anyhow;
+use bitcoin::{
+    absolute::LockTime,
+    address::NetworkUnchecked,
+    constants::WITNESS_SCALE_FACTOR,
+    hashes::Hash,
+    opcodes::all::{OP_CSV, OP_ELSE, OP_ENDIF, OP_IF, OP_NOTIF, OP_PUSHNUM_16},
+    policy::{DEFAULT_MIN_RELAY_TX_FEE, MAX_STANDARD_TX_SIGOPS_COST, MAX_STANDARD_TX_WEIGHT},
+    script::{Instruction, PushBytes},
+    Address, Amount, FeeRate, Network, OutPoint, Script, ScriptBuf, Sequence, Transaction, Txid,
+    Witness, XOnlyPublicKey,
+};
+use ctvlib::{Context, Output, TxType};
+use serde::Serialize;
+
+use crate::{error::CdvError, util};
+
+/// Bitcoin Core won't relay transactions smaller than this, not counting the witness, since
+/// v25. (The constant in `bitcoin::policy` predates that release.)
+const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+
+/// OP_RETURN outputs larger than this are only relayed by Bitcoin Core v30 and later.
+const MAX_OP_RETURN_SIZE: usize = 83;
+
+/// Fail if an address is for a different network than the contract, naming the field it came
+/// from and the network it's actually for.
+pub(crate) fn check_network(
+    field: &str,
+    address: &Address<NetworkUnchecked>,
+    network: Network,
+) -> Result<(), CdvError> {
+    if address.is_valid_for_network(network) {
+        return Ok(());
+    }
+    let actual = [
+        Network::Bitcoin,
+        Network::Testnet,
+        Network::Signet,
+        Network::Regtest,
+    ]
+    .into_iter()
+    .filter(|n| address.is_valid_for_network(*n))
+    .map(|n| n.to_string())
+    .collect::<Vec<_>>()
+    .join(" or ");
+    Err(CdvError::NetworkMismatch {
+        field: field.to_string(),
+        address: address.assume_checked_ref().to_string(),
+        actual,
+        network,
+    })
+}
+
+/// Consensus limits on scripts, from `script.h` in Bitcoin Core. Before tapscript, the size and
+/// opcode limits apply to witness scripts as well.
+const MAX_SCRIPT_SIZE: usize = 10_000;
+const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+const MAX_OPS_PER_SCRIPT: usize = 201;
+const MAX_STACK_SIZE: usize = 1000;
+
+/// Policy limits on witness scripts and their stacks, from `policy.h` in Bitcoin Core.
+const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+const MAX_STANDARD_P2WSH_STACK_ITEMS: usize = 100;
+const MAX_STANDARD_P2WSH_STACK_ITEM_SIZE: usize = 80;
+const MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE: usize = 80;
+
+/// Parts of a relative timelock, as encoded in a sequence or an OP_CSV argument (BIP 68).
+const DISABLE_FLAG: u32 = 1 << 31;
+const TYPE_FLAG: u32 = 1 << 22;
+const VALUE_MASK: u32 = 0xffff;
+
+/// Bitcoin Core refuses to broadcast transactions paying more than this, in sat/vB, by default.
+const DEFAULT_MAX_FEE_RATE: f64 = 10_000.0;
+
+/// The fee rates a generated transaction has to fall between, in sat/vB.
+///
+/// The minimum defaults to the minimum relay fee rate of Bitcoin Core, and the maximum to the
+/// default `maxfeerate` of its `sendrawtransaction`. They can be changed with the
+/// `CDV_MIN_FEE_RATE` and `CDV_MAX_FEE_RATE` environment variables.
+pub(crate) struct FeeLimits {
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+}
+
+impl FeeLimits {
+    pub(crate) fn from_env() -> Result<FeeLimits, CdvError> {
+        let limit = |var: &'static str, default: f64| -> Result<f64, CdvError> {
+            match std::env::var(var) {
+                Ok(value) => value.parse().map_err(|_| CdvError::Config {
+                    var,
+                    expected: "a fee rate in sat/vB",
+                    value,
+                }),
+                Err(_) => Ok(default),
+            }
+        };
+        Ok(FeeLimits {
+            min: limit("CDV_MIN_FEE_RATE", DEFAULT_MIN_RELAY_TX_FEE as f64 / 1000.0)?,
+            max: limit("CDV_MAX_FEE_RATE", DEFAULT_MAX_FEE_RATE)?,
+        })
+    }
+}
+
+/// Fail if `fees`, one for each transaction, give any of them a fee rate too low to be relayed,
+/// or one so high that it's most likely a mistake. Transactions with an ephemeral anchor which
+/// pay no fee are bumped by a child instead, so they're skipped. Transactions are numbered in
+/// the given order.
+pub(crate) fn check_fee_rates(
+    txs: &[Transaction],
+    fees: &[Amount],
+    limits: &FeeLimits,
+) -> Result<(), CdvError> {
+    check_fee_count(txs, fees)?;
+    for (idx, (tx, &fee)) in txs.iter().zip(fees).enumerate() {
+        let anchored = tx
+            .output
+            .iter()
+            .any(|output| output.script_pubkey == util::anchor_script());
+        if fee == Amount::ZERO && anchored {
+            continue;
+        }
+        let fee_rate = fee.to_sat() as f64 / tx.vsize() as f64;
+        if fee_rate < limits.min {
+            return Err(CdvError::FeeRateTooLow {
+                number: idx + 1,
+                fee,
+                vsize: tx.vsize(),
+                fee_rate,
+                limit: limits.min,
+            });
+        }
+        if fee_rate > limits.max {
+            return Err(CdvError::FeeRateTooHigh {
+                number: idx + 1,
+                fee,
+                vsize: tx.vsize(),
+                fee_rate,
+                limit: limits.max,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The largest tree the tree builders will generate, unless overridden with the
+/// `CDV_MAX_TREE_LEAVES`, `CDV_MAX_TREE_DEPTH` and `CDV_MAX_TREE_VSIZE` environment variables.
+const DEFAULT_MAX_TREE_LEAVES: usize = 1000;
+const DEFAULT_MAX_TREE_DEPTH: usize = 100;
+const DEFAULT_MAX_TREE_VSIZE: u64 = 100_000;
+
+/// How large a CTV tree is allowed to get: how many outputs it pays out at its leaves, how many
+/// transactions deep it goes, and how many vbytes it takes up on chain in total.
+pub(crate) struct TreeLimits {
+    pub(crate) max_leaves: usize,
+    pub(crate) max_depth: usize,
+    pub(crate) max_vsize: u64,
+}
+
+impl TreeLimits {
+    pub(crate) fn from_env() -> Result<TreeLimits, CdvError> {
+        fn limit<T: std::str::FromStr>(var: &'static str, default: T) -> Result<T, CdvError> {
+            match std::env::var(var) {
+                Ok(value) => value.parse().map_err(|_| CdvError::Config {
+                    var,
+                    expected: "a whole number",
+                    value,
+                }),
+                Err(_) => Ok(default),
+            }
+        }
+        Ok(TreeLimits {
+            max_leaves: limit("CDV_MAX_TREE_LEAVES", DEFAULT_MAX_TREE_LEAVES)?,
+            max_depth: limit("CDV_MAX_TREE_DEPTH", DEFAULT_MAX_TREE_DEPTH)?,
+            max_vsize: limit("CDV_MAX_TREE_VSIZE", DEFAULT_MAX_TREE_VSIZE)?,
+        })
+    }
+
+    /// Fail if a tree of this shape is too large, before going to the trouble of building it.
+    pub(crate) fn check_shape(&self, leaves: usize, depth: usize) -> Result<(), CdvError> {
+        if leaves > self.max_leaves {
+            return Err(CdvError::TreeLeaves {
+                leaves,
+                limit: self.max_leaves,
+            });
+        }
+        if depth > self.max_depth {
+            return Err(CdvError::TreeDepth {
+                depth,
+                limit: self.max_depth,
+            });
+        }
+        Ok(())
+    }
+
+    /// Fail if a tree is too large from its shape alone, before any of its transactions are
+    /// built.
+    pub(crate) fn check_estimate(&self, shape: &Shape) -> Result<(), CdvError> {
+        self.check_shape(shape.leaves, shape.depth)?;
+        if shape.vsize > self.max_vsize {
+            return Err(CdvError::TreeEstimate {
+                transactions: shape.transactions,
+                vsize: shape.vsize,
+                limit: self.max_vsize,
+            });
+        }
+        Ok(())
+    }
+
+    /// Fail if a tree which has been built is too large.
+    pub(crate) fn check_footprint(&self, footprint: &Footprint) -> Result<(), CdvError> {
+        self.check_shape(footprint.leaves, footprint.depth)?;
+        if footprint.vsize > self.max_vsize {
+            return Err(CdvError::TreeVsize {
+                vsize: footprint.vsize,
+                limit: self.max_vsize,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The on-chain footprint of a CTV tree, if every transaction in it is broadcast.
+pub(crate) struct Footprint {
+    pub(crate) transactions: usize,
+    pub(crate) leaves: usize,
+    pub(crate) depth: usize,
+    /// The most outputs any one transaction has.
+    pub(crate) fan_out: usize,
+    pub(crate) vsize: u64,
+    /// The fees of every transaction together, which is the most unrolling the tree can cost.
+    pub(crate) cost: Amount,
+}
+
+/// The shape of a CTV tree, worked out from its templates without building any transactions.
+pub(crate) struct Shape {
+    pub(crate) transactions: usize,
+    pub(crate) leaves: usize,
+    pub(crate) depth: usize,
+    /// The most outputs any one transaction has.
+    pub(crate) fan_out: usize,
+    /// An estimate of the size of every transaction together, from the sizes of their outputs
+    /// and of a bare CTV witness.
+    pub(crate) vsize: u64,
+    /// The estimated size of the largest transaction.
+    pub(crate) largest: u64,
+}
+
+/// Walk a CTV template and the trees it commits to, counting transactions and outputs. It only
+/// looks at the templates, so it's cheap however large the tree is.
+pub(crate) fn tree_shape(ctx: &Context) -> Shape {
+    let mut shape = Shape {
+        transactions: 0,
+        leaves: 0,
+        depth: 0,
+        fan_out: 0,
+        vsize: 0,
+        largest: 0,
+    };
+    let mut templates = vec![(ctx, 1)];
+    while let Some((ctx, depth)) = templates.pop() {
+        shape.transactions += 1;
+        shape.depth = shape.depth.max(depth);
+        shape.fan_out = shape.fan_out.max(ctx.fields.outputs.len());
+        // Version, locktime, and a byte for each of the input and output counts.
+        let mut base = 10;
+        for output in &ctx.fields.outputs {
+            let script = match output {
+                output if util::is_anchor(output) => util::anchor_script().len(),
+                Output::Address { address, .. } => {
+                    shape.leaves += 1;
+                    address.assume_checked_ref().script_pubkey().len()
+                }
+                Output::Data { data } => data.len() + 3,
+                Output::Tree { tree, .. } => {
+                    templates.push((tree, depth + 1));
+                    34
+                }
+            };
+            base += 8 + 1 + script;
+        }
+        // Outpoint, an empty script sig and sequence per input, and the covenant's witness: a
+        // 34 byte script, with a control block for taproot.
+        base += 41 * ctx.fields.sequences.len().max(1);
+        let witness = match ctx.tx_type {
+            TxType::Segwit => 2 + 1 + 1 + 34,
+            TxType::Taproot { .. } => 2 + 1 + 1 + 34 + 1 + 33,
+        };
+        let vsize = (base * WITNESS_SCALE_FACTOR + witness).div_ceil(WITNESS_SCALE_FACTOR) as u64;
+        shape.vsize += vsize;
+        shape.largest = shape.largest.max(vsize);
+    }
+    shape
+}
+
+/// Measure a CTV template, down to the leaves of any trees it commits to, when each transaction
+/// pays `fee`, and fail if it's larger than [`TreeLimits`] allows. Its shape is checked against
+/// the limits first, so a tree which is obviously too large is never built.
+pub(crate) fn measure_tree(ctx: &Context, fee: Amount) -> Result<Footprint, CdvError> {
+    let limits = TreeLimits::from_env()?;
+    let shape = tree_shape(ctx);
+    limits.check_estimate(&shape)?;
+    let txs = util::spending_txs(ctx, placeholder_txid(), 0)?;
+    let footprint = Footprint {
+        transactions: txs.len(),
+        leaves: shape.leaves,
+        depth: shape.depth,
+        fan_out: shape.fan_out,
+        vsize: txs.iter().map(|tx| tx.vsize() as u64).sum(),
+        cost: fee * txs.len() as u64,
+    };
+    limits.check_footprint(&footprint)?;
+    Ok(footprint)
+}
+
+/// What one transaction in a CTV tree costs to broadcast.
+#[derive(Debug, Serialize)]
+pub(crate) struct TxCost {
+    /// Where the transaction is in the tree, as the outputs spent to reach it from the root.
+    pub(crate) path: String,
+    pub(crate) vsize: u64,
+    pub(crate) fee: Amount,
+    /// In sat/vB.
+    pub(crate) fee_rate: f64,
+    /// The fees of this transaction and of every one before it, which is what it costs to get
+    /// to the outputs it pays.
+    pub(crate) cumulative_fee: Amount,
+    /// How many addresses the transaction pays.
+    pub(crate) payouts: usize,
+}
+
+/// The size and fee of every transaction in a CTV tree, when `amount` is locked in it, in the
+/// order they're broadcast.
+pub(crate) fn fee_report(ctx: &Context, amount: Amount) -> anyhow::Result<Vec<TxCost>> {
+    fn walk(
+        ctx: &Context,
+        amount: Amount,
+        path: String,
+        before: Amount,
+        report: &mut Vec<TxCost>,
+    ) -> anyhow::Result<()> {
+        let tx = util::spending_tx(ctx, placeholder_txid(), 0)?;
+        let paid = tx
+            .output
+            .iter()
+            .try_fold(Amount::ZERO, |total, output| {
+                total.checked_add(output.value)
+            })
+            .ok_or_else(|| anyhow!("Transaction {path} pays out more than 21 million BTC"))?;
+        let fee = amount
+            .checked_sub(paid)
+            .ok_or_else(|| anyhow!("Transaction {path} pays out {paid}, more than its {amount}"))?;
+        let cumulative_fee = before
+            .checked_add(fee)
+            .ok_or_else(|| anyhow!("Fees up to transaction {path} overflow"))?;
+        report.push(TxCost {
+            path: path.clone(),
+            vsize: tx.vsize() as u64,
+            fee,
+            fee_rate: fee.to_sat() as f64 / tx.vsize() as f64,
+            cumulative_fee,
+            payouts: ctx
+                .fields
+                .outputs
+                .iter()
+                .filter(|output| {
+                    matches!(output, Output::Address { .. }) && !util::is_anchor(output)
+                })
+                .count(),
+        });
+        for (vout, output) in ctx.fields.outputs.iter().enumerate() {
+            if let Output::Tree { tree, amount } = output {
+                walk(
+                    tree,
+                    *amount,
+                    format!("{path}/{vout}"),
+                    cumulative_fee,
+                    report,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut report = Vec::new();
+    walk(ctx, amount, "root".to_string(), Amount::ZERO, &mut report)?;
+    Ok(report)
+}
+
+/// The future fee rates, in sat/vB, that trees are dry-run at before they're funded, unless
+/// overridden with a comma-separated list in the `CDV_FEE_SCENARIOS` environment variable.
+const DEFAULT_FEE_SCENARIOS: [u64; 3] = [5, 50, 500];
+
+/// One payout of a contract, and the size of every transaction which has to be mined to get to
+/// it.
+pub(crate) struct Leaf {
+    pub(crate) recipient: String,
+    pub(crate) amount: Amount,
+    pub(crate) vsize: u64,
+}
+
+pub(crate) struct LeafCost {
+    pub(crate) recipient: String,
+    pub(crate) amount: Amount,
+    pub(crate) vsize: u64,
+    /// What it costs to bump every transaction on the way to the payout to the fee rate.
+    pub(crate) cost: Amount,
+}
+
+/// How a contract's payouts fare if fees rise to a given rate after it's funded.
+pub(crate) struct Scenario {
+    pub(crate) fee_rate: FeeRate,
+    pub(crate) payouts: usize,
+    /// The payouts which cost at least as much to reach as they're worth.
+    pub(crate) uneconomical: Vec<LeafCost>,
+}
+
+/// Every payout of a CTV tree, with the size of its path from the root.
+pub(crate) fn tree_leaves(ctx: &Context) -> anyhow::Result<Vec<Leaf>> {
+    fn walk(ctx: &Context, before: u64, leaves: &mut Vec<Leaf>) -> anyhow::Result<()> {
+        let vsize = before + util::spending_tx(ctx, placeholder_txid(), 0)?.vsize() as u64;
+        for output in &ctx.fields.outputs {
+            match output {
+                output if util::is_anchor(output) => {}
+                Output::Address { address, amount } => leaves.push(Leaf {
+                    recipient: address.assume_checked_ref().to_string(),
+                    amount: *amount,
+                    vsize,
+                }),
+                Output::Tree { tree, .. } => walk(tree, vsize, leaves)?,
+                Output::Data { .. } => {}
+            }
+        }
+        Ok(())
+    }
+
+    let mut leaves = Vec::new();
+    walk(ctx, 0, &mut leaves)?;
+    Ok(leaves)
+}
+
+/// Dry-run the payouts of a contract at each of the fee rate scenarios. The fees committed to in
+/// the templates can't change, so the cost of a payout at a higher fee rate is what it takes to
+/// bump its whole path there, for example with CPFP.
+pub(crate) fn fee_scenarios(leaves: &[Leaf]) -> anyhow::Result<Vec<Scenario>> {
+    let rates = match std::env::var("CDV_FEE_SCENARIOS") {
+        Ok(rates) => rates
+            .split(',')
+            .map(|rate| {
+                rate.trim().parse().map_err(|_| {
+                    anyhow!("CDV_FEE_SCENARIOS must be a list of whole sat/vB, not {rates}")
+                })
+            })
+            .collect::<anyhow::Result<Vec<u64>>>()?,
+        Err(_) => DEFAULT_FEE_SCENARIOS.to_vec(),
+    };
+    rates
+        .into_iter()
+        .map(|rate| {
+            let fee_rate = FeeRate::from_sat_per_vb(rate)
+                .ok_or_else(|| anyhow!("Fee rate {rate} sat/vB is too high"))?;
+            let mut uneconomical = Vec::new();
+            for leaf in leaves {
+                let cost = fee_rate
+                    .fee_vb(leaf.vsize)
+                    .ok_or_else(|| anyhow!("Fee rate {fee_rate:#} is too high"))?;
+                if cost >= leaf.amount {
+                    uneconomical.push(LeafCost {
+                        recipient: leaf.recipient.clone(),
+                        amount: leaf.amount,
+                        vsize: leaf.vsize,
+                        cost,
+                    });
+                }
+            }
+            Ok(Scenario {
+                fee_rate,
+                payouts: leaves.len(),
+                uneconomical,
+            })
+        })
+        .collect()
+}
+
+/// Parse the covenant addresses a caller says they have already funded, separated by whitespace.
+pub(crate) fn parse_used(used: &str) -> anyhow::Result<Vec<Address<NetworkUnchecked>>> {
+    used.split_whitespace()
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|e| anyhow!("Invalid used address {address}: {e}"))
+        })
+        .collect()
+}
+
+/// Contracts with identical parameters commit to identical templates, and so share an address.
+/// Warn about each address that the caller has already funded, or that this request generates
+/// more than once, since funding it again links the payments on chain.
+pub(crate) fn check_reuse(
+    contract: &str,
+    addresses: &[Address],
+    used: &[Address<NetworkUnchecked>],
+) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut warnings = Vec::new();
+    for address in addresses {
+        let script = address.script_pubkey();
+        let funded = used.iter().any(|used| {
+            used.is_valid_for_network(*address.network())
+                && used.assume_checked_ref().script_pubkey() == script
+        });
+        if funded {
+            warnings.push(format!(
+                "Address {address} is one you have already funded. Funding this {contract} contract reuses the address, which links them on chain; change a parameter to get a fresh one."
+            ));
+        } else if !seen.insert(script) {
+            warnings.push(format!(
+                "Address {address} is generated more than once by this {contract} contract. Funding each reuses the address, which links them on chain; make the parameters differ to get fresh ones."
+            ));
+        }
+    }
+    warnings
+}
+
+/// A taproot contract with a real internal key can be spent by its holder through the key path,
+/// bypassing the covenant entirely.
+pub(crate) fn key_path_warning(key: &XOnlyPublicKey) -> String {
+    format!(
+        "The internal key {key} can spend the contract directly, without following the covenant. Only use a key when everyone it pays agrees to share that power."
+    )
+}
+
+/// Contracts are validated before they are funded, so their transactions are built spending from
+/// this placeholder instead of the real funding outpoint.
+pub(crate) fn placeholder_txid() -> Txid {
+    Txid::all_zeros()
+}
+
+/// Fail if any output of these transactions is below the dust limit for its script type, since
+/// the transaction could never be relayed. Anchors are left to [`check_truc`], since they're
+/// allowed to be dust. Transactions are numbered in the given order.
+pub(crate) fn check_dust(network: Network, txs: &[Transaction]) -> Result<(), CdvError> {
+    for (idx, tx) in txs.iter().enumerate() {
+        for (vout, output) in tx.output.iter().enumerate() {
+            let dust = output.script_pubkey.dust_value();
+            if output.value < dust && output.script_pubkey != util::anchor_script() {
+                let recipient = match Address::from_script(&output.script_pubkey, network) {
+                    Ok(address) => address.to_string(),
+                    Err(_) => output.script_pubkey.to_hex_string(),
+                };
+                return Err(CdvError::Dust {
+                    number: idx + 1,
+                    vout,
+                    value: output.value,
+                    recipient,
+                    dust,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fail unless every transaction pays out exactly what it spends, minus its fee in `fees`. The
+/// transactions must be in broadcast order, starting with the one which spends the contract's
+/// funding output from the placeholder txid. Transactions are numbered in the given order.
+pub(crate) fn check_conservation(
+    funding: Amount,
+    txs: &[Transaction],
+    fees: &[Amount],
+) -> Result<(), CdvError> {
+    check_fee_count(txs, fees)?;
+    let mut values = HashMap::new();
+    values.insert(
+        OutPoint {
+            txid: placeholder_txid(),
+            vout: 0,
+        },
+        funding,
+    );
+    for (idx, (tx, &fee)) in txs.iter().zip(fees).enumerate() {
+        let number = idx + 1;
+        // Summing with overflow checks, since the transactions might not have come from us.
+        let overflow = || CdvError::SupplyOverflow { number };
+        let mut spent = Amount::ZERO;
+        for input in &tx.input {
+            let value = values
+                .get(&input.previous_output)
+                .copied()
+                .ok_or(CdvError::UnknownOutput { number })?;
+            spent = spent.checked_add(value).ok_or_else(overflow)?;
+        }
+        let paid = tx
+            .output
+            .iter()
+            .try_fold(Amount::ZERO, |paid, o| paid.checked_add(o.value))
+            .ok_or_else(overflow)?;
+        let expected = paid.checked_add(fee).ok_or_else(overflow)?;
+        if spent != expected {
+            let (difference, direction) = if spent > expected {
+                (spent - expected, "less")
+            } else {
+                (expected - spent, "more")
+            };
+            return Err(CdvError::Conservation {
+                number,
+                spent,
+                paid,
+                fee,
+                difference,
+                direction,
+            });
+        }
+        let txid = tx.txid();
+        for (vout, output) in tx.output.iter().enumerate() {
+            values.insert(
+                OutPoint {
+                    txid,
+                    vout: vout as u32,
+                },
+                output.value,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The fee checks take one fee per transaction, in the same order.
+fn check_fee_count(txs: &[Transaction], fees: &[Amount]) -> Result<(), CdvError> {
+    if txs.len() != fees.len() {
+        return Err(CdvError::FeeCount {
+            transactions: txs.len(),
+            fees: fees.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Fail on timelocks which can never be satisfied, even though the template hashes fine, and warn
+/// about ones which have no effect. Transactions must be in broadcast order, and are numbered in
+/// the given order.
+pub(crate) fn check_timelocks(txs: &[Transaction]) -> Result<Vec<String>, CdvError> {
+    let mut warnings = Vec::new();
+    // The latest absolute locktime of each transaction or any of its ancestors.
+    let mut inherited: HashMap<Txid, LockTime> = HashMap::new();
+    for (idx, tx) in txs.iter().enumerate() {
+        let number = idx + 1;
+        let absolute = tx.lock_time != LockTime::ZERO;
+        if absolute && tx.input.iter().all(|i| i.sequence.is_final()) {
+            return Err(CdvError::Timelock(format!(
+                "Transaction {number} has a locktime of {}, which is disabled because every input's sequence is final",
+                tx.lock_time
+            )));
+        }
+
+        for (vin, input) in tx.input.iter().enumerate() {
+            // A sequence of zero has the disable flag clear too, but a relative timelock of zero
+            // doesn't wait for anything, so it's fine in any version.
+            let waits = input.sequence.is_relative_lock_time() && input.sequence.0 & VALUE_MASK > 0;
+            if waits && tx.version.0 < 2 {
+                return Err(CdvError::Timelock(format!(
+                    "Input {vin} of transaction {number} has a relative timelock, but relative timelocks are only enforced from version 2, not {}",
+                    tx.version.0
+                )));
+            }
+            if let Some(script) = witness_script(&input.witness) {
+                check_csv(number, vin, script, input.sequence)?;
+            }
+        }
+
+        let parent = tx
+            .input
+            .iter()
+            .find_map(|i| inherited.get(&i.previous_output.txid))
+            .copied();
+        let latest = match parent {
+            Some(parent) if absolute && parent.is_same_unit(tx.lock_time) => {
+                if tx.lock_time.is_implied_by(parent) && tx.lock_time != parent {
+                    warnings.push(format!(
+                        "Transaction {number} has a locktime of {}, which has no effect because an earlier transaction can't be mined before {parent}",
+                        tx.lock_time
+                    ));
+                    parent
+                } else {
+                    tx.lock_time
+                }
+            }
+            Some(parent) => parent,
+            None if absolute => tx.lock_time,
+            None => continue,
+        };
+        inherited.insert(tx.txid(), latest);
+    }
+    Ok(warnings)
+}
+
+/// The script being executed by a witness: the second to last element for a taproot script path
+/// spend, which ends with a control block, and the last element otherwise.
+fn witness_script(witness: &Witness) -> Option<&Script> {
+    if is_tapscript(witness) {
+        return witness.tapscript();
+    }
+    Some(Script::from_bytes(witness.last()?))
+}
+
+fn is_tapscript(witness: &Witness) -> bool {
+    witness.len() >= 2 && matches!(witness.last().and_then(|l| l.first()), Some(0xc0 | 0xc1))
+}
+
+/// Fail if a witness script, or the stack it starts with, is over any of the consensus or policy
+/// limits for its script version, naming the limit. Transactions are numbered in the given order.
+pub(crate) fn check_scripts(txs: &[Transaction]) -> Result<(), CdvError> {
+    for (idx, tx) in txs.iter().enumerate() {
+        let number = idx + 1;
+        for (vin, input) in tx.input.iter().enumerate() {
+            let Some(script) = witness_script(&input.witness) else {
+                continue;
+            };
+            let tapscript = is_tapscript(&input.witness);
+            let stack = input
+                .witness
+                .iter()
+                .take(input.witness.len() - if tapscript { 2 } else { 1 })
+                .collect::<Vec<_>>();
+            let input = format!("Input {vin} of transaction {number}");
+
+            let (max_items, max_item_size) = if tapscript {
+                (MAX_STACK_SIZE, MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE)
+            } else {
+                (
+                    MAX_STANDARD_P2WSH_STACK_ITEMS,
+                    MAX_STANDARD_P2WSH_STACK_ITEM_SIZE,
+                )
+            };
+            if stack.len() > max_items {
+                return Err(CdvError::Script(format!(
+                    "{input} starts with {} stack items, more than the limit of {max_items}",
+                    stack.len()
+                )));
+            }
+            for item in &stack {
+                if item.len() > max_item_size {
+                    return Err(CdvError::Script(format!(
+                        "{input} has a {} byte stack item, more than the standard limit of {max_item_size}",
+                        item.len()
+                    )));
+                }
+            }
+
+            if !tapscript {
+                if script.len() > MAX_SCRIPT_SIZE {
+                    return Err(CdvError::Script(format!(
+                        "{input} has a {} byte witness script, more than the consensus limit of {MAX_SCRIPT_SIZE}",
+                        script.len()
+                    )));
+                }
+                if script.len() > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+                    return Err(CdvError::Script(format!(
+                        "{input} has a {} byte witness script, more than the standard limit of {MAX_STANDARD_P2WSH_SCRIPT_SIZE}",
+                        script.len()
+                    )));
+                }
+            }
+            let mut opcodes = 0;
+            for instruction in script.instructions() {
+                match instruction {
+                    Ok(Instruction::PushBytes(bytes)) if bytes.len() > MAX_SCRIPT_ELEMENT_SIZE => {
+                        return Err(CdvError::Script(format!(
+                            "{input} pushes a {} byte element, more than the consensus limit of {MAX_SCRIPT_ELEMENT_SIZE}",
+                            bytes.len()
+                        )));
+                    }
+                    Ok(Instruction::Op(op)) if op.to_u8() > OP_PUSHNUM_16.to_u8() => opcodes += 1,
+                    Ok(_) => {}
+                    Err(err) => {
+                        return Err(CdvError::Script(format!(
+                            "{input} has a malformed witness script: {err}"
+                        )))
+                    }
+                }
+            }
+            if !tapscript && opcodes > MAX_OPS_PER_SCRIPT {
+                return Err(CdvError::Script(format!(
+                    "{input} has {opcodes} opcodes in its witness script, more than the consensus limit of {MAX_OPS_PER_SCRIPT}"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check the argument of every OP_CSV in a witness script against the relative timelock encoding,
+/// and against the input's sequence. Scripts with branches are only checked for encoding, since
+/// the branch with the OP_CSV might not be the one being executed.
+fn check_csv(
+    number: usize,
+    vin: usize,
+    script: &Script,
+    sequence: Sequence,
+) -> Result<(), CdvError> {
+    let instructions = script
+        .instructions()
+        .collect::<Result<Vec<Instruction>, _>>()
+        .unwrap_or_default();
+    let branches = instructions
+        .iter()
+        .any(|i| matches!(i.opcode(), Some(OP_IF | OP_NOTIF | OP_ELSE | OP_ENDIF)));
+    for pair in instructions.windows(2) {
+        if pair[1].opcode() != Some(OP_CSV) {
+            continue;
+        }
+        let Some(value) = pair[0].script_num() else {
+            continue;
+        };
+        if value < 0 {
+            return Err(CdvError::Timelock(format!(
+                "Input {vin} of transaction {number} has an OP_CSV of {value}, which can never be satisfied"
+            )));
+        }
+        let Ok(value) = u32::try_from(value) else {
+            return Err(CdvError::Timelock(format!(
+                "Input {vin} of transaction {number} has an OP_CSV of {value}, which doesn't fit the relative timelock encoding"
+            )));
+        };
+        if value & DISABLE_FLAG != 0 {
+            continue;
+        }
+        if value & !(TYPE_FLAG | VALUE_MASK) != 0 {
+            return Err(CdvError::Timelock(format!(
+                "Input {vin} of transaction {number} has an OP_CSV of {value}, which doesn't fit the relative timelock encoding"
+            )));
+        }
+        if branches || !sequence.is_relative_lock_time() {
+            continue;
+        }
+        let sequence = sequence.to_consensus_u32();
+        if value & TYPE_FLAG != sequence & TYPE_FLAG || value & VALUE_MASK > sequence & VALUE_MASK {
+            return Err(CdvError::Timelock(format!(
+                "Input {vin} of transaction {number} has a sequence of {sequence:#x}, which doesn't satisfy its OP_CSV of {value:#x}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// TRUC (version 3) transactions can't be larger than this, or than the smaller limit while they
+/// spend an unconfirmed TRUC transaction (BIP 431).
+const TRUC_MAX_VSIZE: usize = 10_000;
+const TRUC_CHILD_MAX_VSIZE: usize = 1_000;
+
+/// Check the TRUC and ephemeral anchor rules of Bitcoin Core's relay policy. Fails on transactions
+/// which could never be relayed, and returns warnings about the ones which have to wait for their
+/// parent to confirm first. Each transaction pays its fee in `fees`, and they're numbered in the
+/// given order.
+pub(crate) fn check_truc(txs: &[Transaction], fees: &[Amount]) -> Result<Vec<String>, CdvError> {
+    check_fee_count(txs, fees)?;
+    let numbers: HashMap<Txid, usize> = txs
+        .iter()
+        .enumerate()
+        .map(|(idx, tx)| (tx.txid(), idx + 1))
+        .collect();
+    let is_truc = |tx: &Transaction| tx.version.0 == 3;
+    let ephemeral = |tx: &Transaction| {
+        tx.output
+            .iter()
+            .filter(|output| {
+                output.script_pubkey == util::anchor_script()
+                    && output.value < output.script_pubkey.dust_value()
+            })
+            .count()
+    };
+
+    let mut warnings = Vec::new();
+    let mut children = BTreeMap::new();
+    for (idx, (tx, &fee)) in txs.iter().zip(fees).enumerate() {
+        let number = idx + 1;
+        match ephemeral(tx) {
+            0 => {}
+            1 if !is_truc(tx) => return Err(CdvError::Truc(format!(
+                "Transaction {number} has an ephemeral anchor, but only version 3 transactions can pay no fee and be bumped through one"
+            ))),
+            1 if fee != Amount::ZERO => return Err(CdvError::Truc(format!(
+                "Transaction {number} has an ephemeral anchor, so it must pay no fee itself, but it pays {fee}"
+            ))),
+            1 => {}
+            anchors => return Err(CdvError::Truc(format!(
+                "Transaction {number} has {anchors} ephemeral anchors, but only one is relayed"
+            ))),
+        }
+        if is_truc(tx) && tx.vsize() > TRUC_MAX_VSIZE {
+            return Err(CdvError::Truc(format!(
+                "Transaction {number} is {} vB, more than the limit of {TRUC_MAX_VSIZE} vB for a version 3 transaction",
+                tx.vsize()
+            )));
+        }
+
+        for input in &tx.input {
+            let Some(&parent) = numbers.get(&input.previous_output.txid) else {
+                continue;
+            };
+            let parent_tx = &txs[parent - 1];
+            *children.entry(parent).or_insert(0) += 1;
+            if is_truc(tx) != is_truc(parent_tx) {
+                warnings.push(format!(
+                    "Transaction {number} spends transaction {parent}, but only one of them is version 3, so it can't be broadcast until transaction {parent} confirms"
+                ));
+            } else if is_truc(tx) && ephemeral(parent_tx) > 0 {
+                warnings.push(format!(
+                    "Transaction {number} spends transaction {parent}, which has to be bumped by a child spending its anchor, and a version 3 transaction can only have one unconfirmed child, so it can't be broadcast until transaction {parent} confirms"
+                ));
+            } else if is_truc(tx) && tx.vsize() > TRUC_CHILD_MAX_VSIZE {
+                warnings.push(format!(
+                    "Transaction {number} is {} vB, more than the limit of {TRUC_CHILD_MAX_VSIZE} vB for a version 3 transaction with an unconfirmed parent, so it can't be broadcast until transaction {parent} confirms",
+                    tx.vsize()
+                ));
+            }
+        }
+    }
+    for (parent, count) in children {
+        if count > 1 && is_truc(&txs[parent - 1]) {
+            warnings.push(format!(
+                "Transaction {parent} is spent by {count} transactions, but a version 3 transaction can only have one unconfirmed child, so only one of them can be broadcast until it confirms"
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
+/// Check these transactions against the relay policy of Bitcoin Core. Fails on transactions which
+/// no node would relay, and returns warnings about the ones which only some nodes would relay.
+pub(crate) fn check_standard(
+    network: Network,
+    txs: &[Transaction],
+) -> Result<Vec<String>, CdvError> {
+    check_dust(network, txs)?;
+    let mut warnings = Vec::new();
+    for (idx, tx) in txs.iter().enumerate() {
+        let number = idx + 1;
+        if !tx.version.is_standard() {
+            if tx.version.0 != 3 {
+                return Err(CdvError::NonStandard(format!(
+                    "Transaction {number} has non-standard version {}",
+                    tx.version.0
+                )));
+            }
+            // Ephemeral anchors are dust, which is only allowed in TRUC packages from v29.
+            let ephemeral = tx.output.iter().any(|output| {
+                output.script_pubkey == util::anchor_script()
+                    && output.value < output.script_pubkey.dust_value()
+            });
+            let version = if ephemeral { "v29" } else { "v28" };
+            warnings.push(format!(
+                "Transaction {number} is version 3, which is only relayed by Bitcoin Core {version} and later"
+            ));
+        }
+        if tx.weight().to_wu() > MAX_STANDARD_TX_WEIGHT as u64 {
+            return Err(CdvError::NonStandard(format!(
+                "Transaction {number} weighs {}, more than the standard limit of {MAX_STANDARD_TX_WEIGHT}",
+                tx.weight()
+            )));
+        }
+        if tx.base_size() < MIN_STANDARD_TX_NONWITNESS_SIZE {
+            return Err(CdvError::NonStandard(format!(
+                "Transaction {number} is {} bytes without its witness, less than the standard minimum of {MIN_STANDARD_TX_NONWITNESS_SIZE}",
+                tx.base_size()
+            )));
+        }
+        // Sigops in witness scripts count once, and tapscript has its own budget instead.
+        let sigops = tx
+            .output
+            .iter()
+            .map(|o| o.script_pubkey.count_sigops_legacy())
+            .sum::<usize>()
+            * WITNESS_SCALE_FACTOR
+            + tx.input
+                .iter()
+                .filter(|i| !is_tapscript(&i.witness))
+                .filter_map(|i| witness_script(&i.witness))
+                .map(|script| script.count_sigops())
+                .sum::<usize>();
+        if sigops > MAX_STANDARD_TX_SIGOPS_COST as usize {
+            return Err(CdvError::NonStandard(format!(
+                "Transaction {number} has a sigop cost of {sigops}, more than the standard limit of {MAX_STANDARD_TX_SIGOPS_COST}"
+            )));
+        }
+
+        let mut op_returns = 0;
+        for (vout, output) in tx.output.iter().enumerate() {
+            if output.script_pubkey.is_op_return() {
+                op_returns += 1;
+                if output.script_pubkey.len() > MAX_OP_RETURN_SIZE {
+                    warnings.push(format!(
+                        "Output {vout} of transaction {number} is an OP_RETURN of {} bytes, which is only relayed by Bitcoin Core v30 and later",
+                        output.script_pubkey.len()
+                    ));
+                }
+            } else if Address::from_script(&output.script_pubkey, network).is_err() {
+                return Err(CdvError::NonStandard(format!(
+                    "Output {vout} of transaction {number} has a non-standard script"
+                )));
+            }
+        }
+        if op_returns > 1 {
+            warnings.push(format!(
+                "Transaction {number} has {op_returns} OP_RETURN outputs, which is only relayed by Bitcoin Core v30 and later"
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
+/// Fail if the payload of a data output can't be pushed, isn't a plain text label, or makes an
+/// OP_RETURN larger than most nodes relay, unless `allow_large` is set.
+pub(crate) fn check_data(field: &str, data: &str, allow_large: bool) -> Result<(), CdvError> {
+    if let Some(c) = data.chars().find(|c| c.is_control()) {
+        return Err(CdvError::Data(format!(
+            "{field} contains the control character {c:?}"
+        )));
+    }
+    if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+        return Err(CdvError::Data(format!(
+            "{field} is {} bytes, more than the consensus limit of {MAX_SCRIPT_ELEMENT_SIZE} for a single push",
+            data.len()
+        )));
+    }
+    let push = <&PushBytes>::try_from(data.as_bytes())
+        .map_err(|err| CdvError::Data(format!("{field} can't be pushed: {err}")))?;
+    let script = ScriptBuf::new_op_return(push);
+    if script.len() > MAX_OP_RETURN_SIZE && !allow_large {
+        return Err(CdvError::Data(format!(
+            "{field} makes an OP_RETURN of {} bytes, more than the standard limit of {MAX_OP_RETURN_SIZE}",
+            script.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Check the payload of every data output of a CTV template with [`check_data`], down to the
+/// leaves of any trees it commits to.
+pub(crate) fn check_data_outputs(ctx: &Context, allow_large: bool) -> Result<(), CdvError> {
+    for (idx, output) in ctx.fields.outputs.iter().enumerate() {
+        match output {
+            Output::Data { data } => {
+                check_data(&format!("Data of output {idx}"), data, allow_large)?;
+            }
+            Output::Tree { tree, .. } => check_data_outputs(tree, allow_large)?,
+            Output::Address { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+/// Fail if a CTV template's spending transaction doesn't have exactly one input per sequence it
+/// commits to, each with that sequence, down to the leaves of any trees it commits to. The
+/// template hash covers both the number of inputs and every sequence, so a mismatch makes the
+/// covenant unspendable.
+pub(crate) fn check_sequences(ctx: &Context) -> Result<(), CdvError> {
+    let sequences = &ctx.fields.sequences;
+    if sequences.is_empty() {
+        return Err(CdvError::Sequences(
+            "Template commits to no inputs".to_string(),
+        ));
+    }
+    if ctx.fields.input_idx as usize >= sequences.len() {
+        return Err(CdvError::Sequences(format!(
+            "Template is spent by input {}, but only commits to {} inputs",
+            ctx.fields.input_idx,
+            sequences.len()
+        )));
+    }
+    let tx = util::spending_tx(ctx, placeholder_txid(), 0)?;
+    if tx.input.len() != sequences.len() {
+        return Err(CdvError::Sequences(format!(
+            "Template commits to {} inputs, but its spending transaction has {}",
+            sequences.len(),
+            tx.input.len()
+        )));
+    }
+    for (vin, (input, sequence)) in tx.input.iter().zip(sequences).enumerate() {
+        if input.sequence != *sequence {
+            return Err(CdvError::Sequences(format!(
+                "Input {vin} of the spending transaction has sequence {:#x}, but the template commits to {:#x}",
+                input.sequence.to_consensus_u32(),
+                sequence.to_consensus_u32()
+            )));
+        }
+    }
+    for output in &ctx.fields.outputs {
+        if let Output::Tree { tree, .. } = output {
+            check_sequences(tree)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check every transaction of a CTV template with [`check_txs`], down to the leaves of any trees
+/// it commits to.
+pub(crate) fn check_tree(
+    ctx: &Context,
+    funding: Amount,
+    fee: Amount,
+) -> Result<Vec<String>, CdvError> {
+    check_sequences(ctx)?;
+    let txs = util::spending_txs(ctx, placeholder_txid(), 0)?;
+    check_txs(ctx.network, funding, &txs, &vec![fee; txs.len()])
+}
+
+/// Check that the transactions spend exactly what they pay out plus their fee in `fees`, that
+/// each fee is a sane fee rate for its transaction, and that they meet relay policy.
+pub(crate) fn check_txs(
+    network: Network,
+    funding: Amount,
+    txs: &[Transaction],
+    fees: &[Amount],
+) -> Result<Vec<String>, CdvError> {
+    check_conservation(funding, txs, fees)?;
+    check_fee_rates(txs, fees, &FeeLimits::from_env()?)?;
+    let mut warnings = check_timelocks(txs)?;
+    check_scripts(txs)?;
+    warnings.extend(check_truc(txs, fees)?);
+    warnings.extend(check_standard(network, txs)?);
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{opcodes::all::OP_NOP4, transaction::Version, TxIn, TxOut, WPubkeyHash};
+
+    use super::*;
+
+    fn limits() -> FeeLimits {
+        FeeLimits {
+            min: 1.0,
+            max: DEFAULT_MAX_FEE_RATE,
+        }
+    }
+
+    fn sats(sats: u64) -> Amount {
+        Amount::from_sat(sats)
+    }
+
+    fn payout(value: u64) -> TxOut {
+        TxOut {
+            value: sats(value),
+            script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros()),
+        }
+    }
+
+    fn anchor() -> TxOut {
+        TxOut {
+            value: Amount::ZERO,
+            script_pubkey: util::anchor_script(),
+        }
+    }
+
+    /// A P2WSH spend of a bare CTV script.
+    fn spend(previous_output: OutPoint, output: Vec<TxOut>) -> Transaction {
+        let script = bitcoin::script::Builder::new()
+            .push_slice([0; 32])
+            .push_opcode(OP_NOP4)
+            .into_script();
+        spend_with(
+            previous_output,
+            output,
+            Witness::from_slice(&[script.as_bytes()]),
+        )
+    }
+
+    fn spend_with(previous_output: OutPoint, output: Vec<TxOut>, witness: Witness) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ZERO,
+                witness,
+            }],
+            output,
+        }
+    }
+
+    fn funding() -> OutPoint {
+        OutPoint {
+            txid: placeholder_txid(),
+            vout: 0,
+        }
+    }
+
+    fn child(parent: &Transaction, output: Vec<TxOut>) -> Transaction {
+        spend(
+            OutPoint {
+                txid: parent.txid(),
+                vout: 0,
+            },
+            output,
+        )
+    }
+
+    #[test]
+    fn conservation() {
+        let parent = spend(funding(), vec![payout(99_400)]);
+        let txs = [parent.clone(), child(&parent, vec![payout(98_400)])];
+        let fees = [sats(600), sats(1_000)];
+        check_conservation(sats(100_000), &txs, &fees).unwrap();
+
+        assert!(matches!(
+            check_conservation(sats(100_000), &txs, &[sats(600); 2]),
+            Err(CdvError::Conservation { number: 2, .. })
+        ));
+        assert!(matches!(
+            check_conservation(sats(100_000), &txs, &fees[..1]),
+            Err(CdvError::FeeCount {
+                transactions: 2,
+                fees: 1
+            })
+        ));
+        assert!(matches!(
+            check_conservation(sats(90_000), &txs, &fees),
+            Err(CdvError::Conservation { number: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn network() {
+        let address = Address::p2wsh(&ScriptBuf::from_bytes(vec![0x51; 7]), Network::Bitcoin)
+            .as_unchecked()
+            .clone();
+        check_network("Payout", &address, Network::Bitcoin).unwrap();
+        let err = check_network("Payout", &address, Network::Regtest).unwrap_err();
+        assert!(matches!(err, CdvError::NetworkMismatch { ref actual, .. } if actual == "bitcoin"));
+    }
+
+    #[test]
+    fn fee_rates() {
+        let txs = [spend(funding(), vec![payout(99_400)])];
+        check_fee_rates(&txs, &[sats(600)], &limits()).unwrap();
+
+        assert!(check_fee_rates(&txs, &[sats(10)], &limits()).is_err());
+        assert!(check_fee_rates(&txs, &[sats(5_000_000)], &limits()).is_err());
+        assert!(check_fee_rates(&txs, &[Amount::ZERO], &limits()).is_err());
+
+        // Bumped through the anchor instead.
+        let anchored = [spend(funding(), vec![payout(100_000), anchor()])];
+        check_fee_rates(&anchored, &[Amount::ZERO], &limits()).unwrap();
+    }
+
+    #[test]
+    fn timelocks() {
+        let mut parent = spend(funding(), vec![payout(99_400)]);
+        parent.lock_time = LockTime::from_height(200).unwrap();
+        let mut earlier = child(&parent, vec![payout(98_800)]);
+        earlier.lock_time = LockTime::from_height(100).unwrap();
+        let warnings = check_timelocks(&[parent.clone(), earlier]).unwrap();
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(check_timelocks(&[parent.clone()]).unwrap().is_empty());
+
+        let mut disabled = parent.clone();
+        disabled.input[0].sequence = Sequence::MAX;
+        assert!(check_timelocks(&[disabled]).is_err());
+
+        let mut relative = spend(funding(), vec![payout(99_400)]);
+        relative.version = Version::ONE;
+        relative.input[0].sequence = Sequence::from_height(10);
+        assert!(check_timelocks(&[relative.clone()]).is_err());
+        relative.input[0].sequence = Sequence::ZERO;
+        assert!(check_timelocks(&[relative]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn scripts() {
+        check_scripts(&[spend(funding(), vec![payout(99_400)])]).unwrap();
+
+        let large = ScriptBuf::from_bytes(vec![OP_PUSHNUM_16.to_u8(); 4_000]);
+        let witness = Witness::from_slice(&[large.as_bytes()]);
+        let tx = spend_with(funding(), vec![payout(99_400)], witness);
+        assert!(check_scripts(&[tx]).is_err());
+
+        let script = ScriptBuf::from_bytes(vec![OP_PUSHNUM_16.to_u8()]);
+        let witness = Witness::from_slice(&[&[0; 81], script.as_bytes()]);
+        let tx = spend_with(funding(), vec![payout(99_400)], witness);
+        assert!(check_scripts(&[tx]).is_err());
+    }
+
+    #[test]
+    fn truc() {
+        let mut parent = spend(funding(), vec![payout(100_000), anchor()]);
+        parent.version = Version(3);
+        assert!(check_truc(&[parent.clone()], &[Amount::ZERO])
+            .unwrap()
+            .is_empty());
+
+        assert!(check_truc(&[parent.clone()], &[sats(600)]).is_err());
+        let mut version_two = parent.clone();
+        version_two.version = Version::TWO;
+        assert!(check_truc(&[version_two], &[Amount::ZERO]).is_err());
+        let mut two_anchors = parent.clone();
+        two_anchors.output.push(anchor());
+        assert!(check_truc(&[two_anchors], &[Amount::ZERO]).is_err());
+
+        // The child can't be relayed alongside the parent, whose one child has to be the bump.
+        let mut child = child(&parent, vec![payout(100_000), anchor()]);
+        child.version = Version(3);
+        let warnings = check_truc(&[parent, child], &[Amount::ZERO; 2]).unwrap();
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+    }
+
+    #[test]
+    fn standard() {
+        let tx = spend(funding(), vec![payout(99_400)]);
+        assert!(check_standard(Network::Regtest, std::slice::from_ref(&tx))
+            .unwrap()
+            .is_empty());
+
+        let dust = spend(funding(), vec![payout(100)]);
+        assert!(check_standard(Network::Regtest, &[dust]).is_err());
+
+        let mut version = tx.clone();
+        version.version = Version(4);
+        assert!(check_standard(Network::Regtest, &[version]).is_err());
+
+        let mut truc = tx.clone();
+        truc.version = Version(3);
+        assert_eq!(check_standard(Network::Regtest, &[truc]).unwrap().len(), 1);
+
+        let mut data = tx;
+        let op_return = TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::new_op_return([0; 4]),
+        };
+        data.output.extend([op_return.clone(), op_return]);
+        assert_eq!(check_standard(Network::Regtest, &[data]).unwrap().len(), 1);
+    }
+
+    fn template(outputs: Vec<Output>) -> Context {
+        Context {
+            network: Network::Regtest,
+            tx_type: TxType::Segwit,
+            fields: ctvlib::Fields {
+                version: Version::TWO,
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::ZERO],
+                outputs,
+                input_idx: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn shape() {
+        let address = || Output::Address {
+            address: Address::p2wsh(&ScriptBuf::new(), Network::Regtest)
+                .as_unchecked()
+                .clone(),
+            amount: sats(10_000),
+        };
+        let mut ctx = template(vec![address(), address(), address()]);
+        for _ in 0..4 {
+            ctx = template(vec![
+                address(),
+                Output::Tree {
+                    tree: Box::new(ctx),
+                    amount: sats(100_000),
+                },
+            ]);
+        }
+        let shape = tree_shape(&ctx);
+        assert_eq!(shape.transactions, 5);
+        assert_eq!(shape.leaves, 7);
+        assert_eq!(shape.depth, 5);
+        assert_eq!(shape.fan_out, 3);
+
+        let limits = |max_leaves, max_depth, max_vsize| TreeLimits {
+            max_leaves,
+            max_depth,
+            max_vsize,
+        };
+        limits(7, 5, shape.vsize).check_estimate(&shape).unwrap();
+        assert!(limits(6, 5, shape.vsize).check_estimate(&shape).is_err());
+        assert!(limits(7, 4, shape.vsize).check_estimate(&shape).is_err());
+        assert!(limits(7, 5, shape.vsize - 1)
+            .check_estimate(&shape)
+            .is_err());
+    }
+
+    #[test]
+    fn reuse() {
+        let address = Address::p2wsh(&ScriptBuf::from_bytes(vec![0x51; 7]), Network::Regtest);
+        let other = Address::p2wsh(&ScriptBuf::from_bytes(vec![0x52; 7]), Network::Regtest);
+        assert!(check_reuse("vault", &[address.clone(), other.clone()], &[]).is_empty());
+        assert_eq!(
+            check_reuse("vault", &[address.clone(), address.clone()], &[]).len(),
+            1
+        );
+
+        let used = parse_used(&format!(" {address}\n{other} ")).unwrap();
+        assert_eq!(used.len(), 2);
+        assert_eq!(
+            check_reuse("vault", std::slice::from_ref(&address), &used).len(),
+            1
+        );
+        assert!(check_reuse("vault", &[address], &used[1..]).is_empty());
+        assert!(parse_used("not-an-address").is_err());
+        assert!(parse_used("  ").unwrap().is_empty());
+    }
+}
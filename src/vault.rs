@@ -1,19 +1,28 @@
-use anyhow::anyhow;
 use bitcoin::{
     absolute::LockTime,
     address::NetworkUnchecked,
     opcodes::all::{OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF, OP_IF, OP_NOP4},
-    script::PushBytesBuf,
     secp256k1::SECP256K1,
     taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo},
     transaction::Version,
-    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
-    Witness, XOnlyPublicKey,
+    Address, Amount, FeeRate, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, Witness, XOnlyPublicKey,
 };
 use ctvlib::{Context, Fields, Output, TxType};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::{
+    error::CdvError,
+    util::{self, nums_points, template_hash},
+    validate,
+};
+
+/// Fee paid by each of the unvaulting transaction and the spend which follows it, unless the vault
+/// has a fee rate or an anchor.
+const FEE: Amount = Amount::from_sat(600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct Vault {
     pub(crate) hot: Address<NetworkUnchecked>,
     pub(crate) cold: Address<NetworkUnchecked>,
@@ -21,18 +30,122 @@ pub(crate) struct Vault {
     pub(crate) network: Network,
     pub(crate) delay: u16,
     pub(crate) taproot: bool,
+    /// The taproot internal key. Without one, the key path is unspendable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) internal_key: Option<XOnlyPublicKey>,
+    /// The fee rate the vault's transactions pay, in sat/vB. Without one, they pay a flat fee.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) fee_rate: Option<u64>,
+    /// Make the vault's transactions TRUC, with an ephemeral anchor to bump them through, so
+    /// they pay no fees up front.
+    #[serde(default)]
+    pub(crate) anchor: bool,
+}
+
+/// Where a vault is in its lifecycle. A vault only moves forward through these, and each step is
+/// caused by one of its transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "state")]
+pub(crate) enum VaultState {
+    Unfunded,
+    Funded {
+        outpoint: OutPoint,
+    },
+    /// The unvaulting transaction has been broadcast, and the hot path is still timelocked.
+    Unvaulting {
+        txid: Txid,
+        blocks_left: u16,
+    },
+    /// The delay has passed, so the funds can go to either the hot or the cold address.
+    HotSpendable {
+        txid: Txid,
+    },
+    ColdSwept {
+        txid: Txid,
+    },
+}
+
+/// What can be done with a vault next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum VaultAction {
+    Fund,
+    Unvault,
+    SpendHot,
+    SweepCold,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Transition {
+    pub(crate) to: VaultState,
+    /// The transaction which caused the transition. Once the delay passes, that's the unvaulting
+    /// transaction maturing.
+    pub(crate) txid: Txid,
+}
+
+/// What has happened to a vault on chain, as far as the caller knows.
+#[derive(Debug, Default)]
+pub(crate) struct VaultProgress {
+    pub(crate) funding: Option<OutPoint>,
+    /// Confirmations of the unvaulting transaction, which is zero while it's in the mempool.
+    pub(crate) unvault_confirmations: Option<u32>,
+    pub(crate) cold_swept: bool,
+}
+
+impl VaultState {
+    pub(crate) fn actions(&self) -> Vec<VaultAction> {
+        match self {
+            VaultState::Unfunded => vec![VaultAction::Fund],
+            VaultState::Funded { .. } => vec![VaultAction::Unvault],
+            VaultState::Unvaulting { .. } => vec![VaultAction::SweepCold],
+            VaultState::HotSpendable { .. } => vec![VaultAction::SpendHot, VaultAction::SweepCold],
+            VaultState::ColdSwept { .. } => Vec::new(),
+        }
+    }
 }
 
 impl Vault {
-    pub(crate) fn vault_address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+    pub(crate) fn validate(&self) -> Result<Vec<String>, CdvError> {
+        validate::check_network("Hot address", &self.hot, self.network)?;
+        validate::check_network("Cold address", &self.cold, self.network)?;
+        if self.anchor && self.fee_rate.is_some() {
+            return Err(CdvError::AnchorFeeRate);
+        }
+        let fee = self.fee()?;
+        if self.amount <= fee * 2 {
+            return Err(CdvError::AmountOverflow {
+                amount: self.amount,
+                fees: fee * 2,
+            });
+        }
+        let vault_tx = self
+            .vault_ctv()?
+            .spending_tx(validate::placeholder_txid(), 0)?[0]
+            .clone();
+        let hot = [vault_tx.clone(), self.hot_spend(vault_tx.txid(), 0)?];
+        let cold = [vault_tx.clone(), self.cold_spend(vault_tx.txid(), 0)?];
+        let mut warnings = validate::check_txs(self.network, self.amount, &hot, &[fee; 2])?;
+        warnings.extend(validate::check_txs(
+            self.network,
+            self.amount,
+            &cold,
+            &[fee; 2],
+        )?);
+        if let (true, Some(key)) = (self.taproot, self.internal_key) {
+            warnings.push(validate::key_path_warning(&key));
+        }
+        Ok(warnings)
+    }
+
+    pub(crate) fn vault_address(&self) -> Result<Address<NetworkUnchecked>, CdvError> {
         let vault_ctv = self.vault_ctv()?;
-        Ok(vault_ctv.address().map(|a| a.as_unchecked().clone())?)
+        Ok(vault_ctv.address()?.as_unchecked().clone())
     }
 
-    pub(crate) fn cold_spend(&self, txid: Txid, vout: u32) -> anyhow::Result<Transaction> {
+    pub(crate) fn cold_spend(&self, txid: Txid, vout: u32) -> Result<Transaction, CdvError> {
         let witness = self.witness(false)?;
         Ok(Transaction {
-            version: Version::ONE,
+            version: self.version(Version::ONE),
             lock_time: LockTime::ZERO,
             input: vec![TxIn {
                 previous_output: OutPoint { txid, vout },
@@ -40,17 +153,17 @@ impl Vault {
                 sequence: Sequence::ZERO,
                 witness,
             }],
-            output: vec![TxOut {
-                value: self.amount - Amount::from_sat(1200),
-                script_pubkey: self.cold.clone().assume_checked().script_pubkey(),
-            }],
+            output: self.with_anchor_txout(TxOut {
+                value: self.payout()?,
+                script_pubkey: self.checked("Cold address", &self.cold)?.script_pubkey(),
+            }),
         })
     }
 
-    pub(crate) fn hot_spend(&self, txid: Txid, vout: u32) -> anyhow::Result<Transaction> {
+    pub(crate) fn hot_spend(&self, txid: Txid, vout: u32) -> Result<Transaction, CdvError> {
         let witness = self.witness(true)?;
         Ok(Transaction {
-            version: Version::TWO,
+            version: self.version(Version::TWO),
             lock_time: LockTime::ZERO,
             input: vec![TxIn {
                 previous_output: OutPoint { txid, vout },
@@ -58,36 +171,56 @@ impl Vault {
                 sequence: Sequence::from_height(self.delay),
                 witness,
             }],
-            output: vec![TxOut {
-                value: self.amount - Amount::from_sat(1200),
-                script_pubkey: self.hot.clone().assume_checked().script_pubkey(),
-            }],
+            output: self.with_anchor_txout(TxOut {
+                value: self.payout()?,
+                script_pubkey: self.checked("Hot address", &self.hot)?.script_pubkey(),
+            }),
         })
     }
 
-    pub(crate) fn vault_ctv(&self) -> anyhow::Result<Context> {
+    /// The vault's two payouts, with the size of the unvaulting transaction and the spend which
+    /// reaches each.
+    pub(crate) fn leaves(&self) -> Result<Vec<validate::Leaf>, CdvError> {
+        let unvault = &self
+            .vault_ctv()?
+            .spending_tx(validate::placeholder_txid(), 0)?[0];
+        let leaf = |recipient: &str, address: &Address<NetworkUnchecked>, spend: Transaction| {
+            validate::Leaf {
+                recipient: format!("{recipient} {}", address.assume_checked_ref()),
+                amount: spend.output[0].value,
+                vsize: (unvault.vsize() + spend.vsize()) as u64,
+            }
+        };
+        Ok(vec![
+            leaf("Hot address", &self.hot, self.hot_spend(unvault.txid(), 0)?),
+            leaf(
+                "Cold address",
+                &self.cold,
+                self.cold_spend(unvault.txid(), 0)?,
+            ),
+        ])
+    }
+
+    pub(crate) fn vault_ctv(&self) -> Result<Context, CdvError> {
         Ok(Context {
             network: self.network,
             tx_type: self.tx_type(),
             fields: Fields {
-                version: Version::ONE,
+                version: self.version(Version::ONE),
                 locktime: LockTime::ZERO,
                 sequences: vec![Sequence::ZERO],
-                outputs: vec![Output::Address {
+                outputs: self.with_anchor(Output::Address {
                     address: self.unvault_address()?,
-                    amount: self.amount - Amount::from_sat(600),
-                }],
+                    amount: self.fund_unvault()?,
+                }),
                 input_idx: 0,
             },
         })
     }
 
-    pub(crate) fn unvault_redeem_script(&self) -> anyhow::Result<ScriptBuf> {
-        let _amount = self.amount - Amount::from_sat(1200);
-        let cold_ctv = self.cold_ctv()?;
-        let cold_hash = PushBytesBuf::try_from(cold_ctv.ctv()?)?;
-        let hot_ctv = self.hot_ctv()?;
-        let hot_hash = PushBytesBuf::try_from(hot_ctv.ctv()?)?;
+    pub(crate) fn unvault_redeem_script(&self) -> Result<ScriptBuf, CdvError> {
+        let cold_hash = template_hash(&self.cold_ctv()?)?;
+        let hot_hash = template_hash(&self.hot_ctv()?)?;
         Ok(bitcoin::script::Builder::new()
             .push_opcode(OP_IF)
             .push_sequence(Sequence::from_height(self.delay))
@@ -102,41 +235,91 @@ impl Vault {
             .into_script())
     }
 
-    fn cold_ctv(&self) -> anyhow::Result<Context> {
+    /// Replay what has happened to the vault, returning every transition it went through in
+    /// order. The last one is the state the vault is in now.
+    pub(crate) fn transitions(
+        &self,
+        progress: &VaultProgress,
+    ) -> Result<Vec<Transition>, CdvError> {
+        let mut transitions = Vec::new();
+        let Some(funding) = progress.funding else {
+            return Ok(transitions);
+        };
+        transitions.push(Transition {
+            to: VaultState::Funded { outpoint: funding },
+            txid: funding.txid,
+        });
+
+        let Some(confirmations) = progress.unvault_confirmations else {
+            return Ok(transitions);
+        };
+        let unvault = self.vault_ctv()?.spending_tx(funding.txid, funding.vout)?[0].txid();
+        let blocks_left = self.blocks_left(confirmations);
+        transitions.push(Transition {
+            to: VaultState::Unvaulting {
+                txid: unvault,
+                blocks_left,
+            },
+            txid: unvault,
+        });
+
+        if progress.cold_swept {
+            let sweep = self.cold_spend(unvault, 0)?.txid();
+            transitions.push(Transition {
+                to: VaultState::ColdSwept { txid: sweep },
+                txid: sweep,
+            });
+        } else if blocks_left == 0 {
+            transitions.push(Transition {
+                to: VaultState::HotSpendable { txid: unvault },
+                txid: unvault,
+            });
+        }
+        Ok(transitions)
+    }
+
+    /// How many more blocks the unvaulting transaction needs before the hot path opens. It has
+    /// to confirm at least once, even without a delay, since an unconfirmed unvault can still be
+    /// replaced or dropped from the mempool.
+    fn blocks_left(&self, confirmations: u32) -> u16 {
+        u32::from(self.delay).max(1).saturating_sub(confirmations) as u16
+    }
+
+    fn cold_ctv(&self) -> Result<Context, CdvError> {
         Ok(Context {
             network: self.network,
             tx_type: self.tx_type(),
             fields: Fields {
-                version: Version::ONE,
+                version: self.version(Version::ONE),
                 locktime: LockTime::ZERO,
                 sequences: vec![Sequence::ZERO],
-                outputs: vec![Output::Address {
+                outputs: self.with_anchor(Output::Address {
                     address: self.cold.clone(),
-                    amount: self.amount - Amount::from_sat(1200),
-                }],
+                    amount: self.payout()?,
+                }),
                 input_idx: 0,
             },
         })
     }
 
-    fn hot_ctv(&self) -> anyhow::Result<Context> {
+    fn hot_ctv(&self) -> Result<Context, CdvError> {
         Ok(Context {
             network: self.network,
             tx_type: self.tx_type(),
             fields: Fields {
-                version: Version::TWO,
+                version: self.version(Version::TWO),
                 locktime: LockTime::ZERO,
                 sequences: vec![Sequence::from_height(self.delay)],
-                outputs: vec![Output::Address {
+                outputs: self.with_anchor(Output::Address {
                     address: self.hot.clone(),
-                    amount: self.amount - Amount::from_sat(1200),
-                }],
+                    amount: self.payout()?,
+                }),
                 input_idx: 0,
             },
         })
     }
 
-    fn unvault_address(&self) -> anyhow::Result<Address<NetworkUnchecked>> {
+    fn unvault_address(&self) -> Result<Address<NetworkUnchecked>, CdvError> {
         match self.tx_type() {
             TxType::Segwit => Ok(Address::p2wsh(&self.unvault_redeem_script()?, self.network)
                 .as_unchecked()
@@ -152,10 +335,98 @@ impl Vault {
         }
     }
 
+    /// What the unvaulting transaction pays on, after its fee.
+    fn fund_unvault(&self) -> Result<Amount, CdvError> {
+        self.after_fees(self.fee()?)
+    }
+
+    /// What either spend pays out, after the fees of both transactions.
+    fn payout(&self) -> Result<Amount, CdvError> {
+        self.after_fees(self.fee()? * 2)
+    }
+
+    /// The fee each of the vault's transactions pays. At a fee rate, that's enough for the
+    /// largest of them, so that neither path pays less than the rate.
+    pub(crate) fn fee(&self) -> Result<Amount, CdvError> {
+        if self.anchor {
+            return Ok(Amount::ZERO);
+        }
+        let Some(rate) = self.fee_rate else {
+            return Ok(FEE);
+        };
+        let fee_rate = FeeRate::from_sat_per_vb(rate).ok_or(CdvError::FeeRate(rate))?;
+        // Only the amounts depend on the fee, and amounts are always the same size, so a vault
+        // with a flat fee has transactions of the same size.
+        let flat = Vault {
+            amount: Amount::ONE_BTC,
+            fee_rate: None,
+            ..self.clone()
+        };
+        let unvault = flat
+            .vault_ctv()?
+            .spending_tx(validate::placeholder_txid(), 0)?[0]
+            .clone();
+        let vsize = [
+            flat.hot_spend(unvault.txid(), 0)?.vsize(),
+            flat.cold_spend(unvault.txid(), 0)?.vsize(),
+            unvault.vsize(),
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or_default();
+        fee_rate.fee_vb(vsize as u64).ok_or(CdvError::FeeRate(rate))
+    }
+
+    /// Version 3, if the vault's transactions are TRUC.
+    fn version(&self, version: Version) -> Version {
+        if self.anchor {
+            Version(3)
+        } else {
+            version
+        }
+    }
+
+    fn with_anchor(&self, output: Output) -> Vec<Output> {
+        let mut outputs = vec![output];
+        if self.anchor {
+            outputs.push(util::anchor_output(self.network));
+        }
+        outputs
+    }
+
+    fn with_anchor_txout(&self, output: TxOut) -> Vec<TxOut> {
+        let mut outputs = vec![output];
+        if self.anchor {
+            outputs.push(TxOut {
+                value: Amount::ZERO,
+                script_pubkey: util::anchor_script(),
+            });
+        }
+        outputs
+    }
+
+    fn after_fees(&self, fees: Amount) -> Result<Amount, CdvError> {
+        self.amount
+            .checked_sub(fees)
+            .ok_or(CdvError::AmountOverflow {
+                amount: self.amount,
+                fees,
+            })
+    }
+
+    fn checked(
+        &self,
+        field: &'static str,
+        address: &Address<NetworkUnchecked>,
+    ) -> Result<Address, CdvError> {
+        validate::check_network(field, address, self.network)?;
+        Ok(address.clone().assume_checked())
+    }
+
     fn tx_type(&self) -> TxType {
         if self.taproot {
             return TxType::Taproot {
-                internal_key: nums_points(),
+                internal_key: self.internal_key.unwrap_or_else(nums_points),
             };
         }
         TxType::Segwit
@@ -164,14 +435,14 @@ impl Vault {
     fn unvault_taproot_spend_info(
         &self,
         internal_key: XOnlyPublicKey,
-    ) -> anyhow::Result<TaprootSpendInfo> {
+    ) -> Result<TaprootSpendInfo, CdvError> {
         TaprootBuilder::new()
             .add_leaf(0, self.unvault_redeem_script()?)?
             .finalize(SECP256K1, internal_key)
-            .map_err(|_| anyhow!("Taproot not finalizable"))
+            .map_err(|_| CdvError::TaprootTree)
     }
 
-    fn witness(&self, hot: bool) -> anyhow::Result<Witness> {
+    fn witness(&self, hot: bool) -> Result<Witness, CdvError> {
         let rs = self.unvault_redeem_script()?;
         let mut witness = Witness::new();
         if hot {
@@ -187,7 +458,7 @@ impl Vault {
                 let tsi = self.unvault_taproot_spend_info(internal_key)?;
                 let cb = tsi
                     .control_block(&(rs, LeafVersion::TapScript))
-                    .ok_or_else(|| anyhow!("Invalid tapscript formation"))?;
+                    .ok_or(CdvError::TapscriptMissing)?;
                 witness.push(cb.serialize());
             }
         }
@@ -195,6 +466,108 @@ impl Vault {
     }
 }
 
-fn nums_points() -> XOnlyPublicKey {
-    ctvlib::util::hash2curve(b"Activate CTV now!")
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    fn vault(delay: u16) -> Vault {
+        let address = Address::p2wsh(&ScriptBuf::from_bytes(vec![0x51; 7]), Network::Regtest)
+            .as_unchecked()
+            .clone();
+        Vault {
+            hot: address.clone(),
+            cold: address,
+            amount: Amount::from_sat(100_000),
+            network: Network::Regtest,
+            delay,
+            taproot: false,
+            internal_key: None,
+            fee_rate: None,
+            anchor: false,
+        }
+    }
+
+    fn progress(unvault_confirmations: Option<u32>, cold_swept: bool) -> VaultProgress {
+        VaultProgress {
+            funding: Some(OutPoint::new(Txid::all_zeros(), 0)),
+            unvault_confirmations,
+            cold_swept,
+        }
+    }
+
+    fn state(vault: &Vault, progress: &VaultProgress) -> VaultState {
+        vault
+            .transitions(progress)
+            .unwrap()
+            .last()
+            .map_or(VaultState::Unfunded, |t| t.to)
+    }
+
+    #[test]
+    fn validates() {
+        // The vault and cold templates are version 1 with a sequence of zero.
+        vault(5).validate().unwrap();
+        let mut taproot = vault(5);
+        taproot.taproot = true;
+        taproot.validate().unwrap();
+    }
+
+    #[test]
+    fn blocks_left() {
+        assert_eq!(vault(0).blocks_left(0), 1);
+        assert_eq!(vault(0).blocks_left(1), 0);
+        assert_eq!(vault(10).blocks_left(3), 7);
+        assert_eq!(vault(10).blocks_left(12), 0);
+    }
+
+    #[test]
+    fn unfunded_and_funded() {
+        let vault = vault(5);
+        let unfunded = VaultProgress {
+            funding: None,
+            unvault_confirmations: None,
+            cold_swept: false,
+        };
+        assert_eq!(state(&vault, &unfunded), VaultState::Unfunded);
+        assert!(matches!(
+            state(&vault, &progress(None, false)),
+            VaultState::Funded { .. }
+        ));
+    }
+
+    #[test]
+    fn unvaulting() {
+        let vault = vault(5);
+        assert!(matches!(
+            state(&vault, &progress(Some(0), false)),
+            VaultState::Unvaulting { blocks_left: 5, .. }
+        ));
+        assert!(matches!(
+            state(&vault, &progress(Some(4), false)),
+            VaultState::Unvaulting { blocks_left: 1, .. }
+        ));
+        assert!(matches!(
+            state(&vault, &progress(Some(5), false)),
+            VaultState::HotSpendable { .. }
+        ));
+        assert!(matches!(
+            state(&vault, &progress(Some(1), true)),
+            VaultState::ColdSwept { .. }
+        ));
+    }
+
+    #[test]
+    fn no_delay_needs_a_confirmation() {
+        let vault = vault(0);
+        assert!(matches!(
+            state(&vault, &progress(Some(0), false)),
+            VaultState::Unvaulting { blocks_left: 1, .. }
+        ));
+        assert!(matches!(
+            state(&vault, &progress(Some(1), false)),
+            VaultState::HotSpendable { .. }
+        ));
+    }
 }